@@ -0,0 +1,260 @@
+use proc_macro::TokenStream;
+
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{Attribute, Data, DeriveInput, Error, Ident, LitStr, parse_macro_input, Result};
+
+/// Derives `MessageKind` for a Maelstrom message enum.
+///
+/// The body `type` of a variant is its `#[serde(rename = "...")]`, or its name converted by the container
+/// `#[serde(rename_all = "...")]` otherwise, as serde names it. Variants named `...Ok` or `Error`, and variants
+/// marked with `#[message(reply)]`, are replies. A request `Foo` expects the reply `FooOk` if the enum has one,
+/// unless another variant is named with `#[message(response = "...")]`.
+#[proc_macro_derive(MessageKind, attributes(message))]
+pub fn derive_message_kind(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input).unwrap_or_else(Error::into_compile_error).into()
+}
+
+struct Variant {
+    ident: Ident,
+    type_name: String,
+    is_reply: bool,
+    response: Option<Ident>,
+}
+
+fn expand(input: DeriveInput) -> Result<TokenStream2> {
+    let Data::Enum(data) = &input.data else {
+        return Err(Error::new_spanned(&input.ident, "MessageKind can only be derived for enums"));
+    };
+
+    let rename_all = serde_attribute(&input.attrs, "rename_all")?
+        .map(|rule| RenameRule::parse(&rule).ok_or_else(|| Error::new_spanned(&input.ident, format!("Unknown rename_all rule '{}'", rule))))
+        .transpose()?;
+    let mut variants = vec![];
+    for variant in &data.variants {
+        let attributes = MessageAttributes::parse(&variant.attrs)?;
+        let name = variant.ident.to_string();
+        variants.push(Variant {
+            ident: variant.ident.clone(),
+            type_name: match serde_attribute(&variant.attrs, "rename")? {
+                Some(rename) => rename,
+                None => rename_all.map_or_else(|| name.clone(), |rule| rule.apply(&name)),
+            },
+            is_reply: attributes.reply || name.ends_with("Ok") || name == "Error",
+            response: attributes.response,
+        });
+    }
+    for idx in 0..variants.len() {
+        if variants[idx].is_reply || variants[idx].response.is_some() {
+            continue;
+        }
+        let conventional = format!("{}Ok", variants[idx].ident);
+        variants[idx].response = variants.iter().find(|variant| variant.ident == conventional).map(|variant| variant.ident.clone());
+    }
+
+    let name = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    let type_name_arms = variants.iter().map(|Variant { ident, type_name, .. }| quote! {
+        #name::#ident { .. } => #type_name,
+    });
+    let replies: Vec<TokenStream2> = variants.iter().filter(|variant| variant.is_reply).map(|Variant { ident, .. }| quote! {
+        #name::#ident { .. }
+    }).collect();
+    let is_reply = if replies.is_empty() {
+        quote! { false }
+    } else {
+        quote! { matches!(self, #(#replies)|*) }
+    };
+    let mut response_arms = vec![];
+    for Variant { ident, response, .. } in &variants {
+        if let Some(response) = response {
+            let response_type = variants.iter()
+                .find(|variant| variant.ident == *response)
+                .map(|variant| variant.type_name.clone())
+                .ok_or_else(|| Error::new_spanned(response, format!("Unknown response variant '{}'", response)))?;
+            response_arms.push(quote! {
+                #name::#ident { .. } => ::std::option::Option::Some(#response_type),
+            });
+        }
+    }
+    let type_names = variants.iter().map(|variant| &variant.type_name);
+
+    Ok(quote! {
+        impl #impl_generics ::gossip_glomers::common::message::MessageKind for #name #type_generics #where_clause {
+            fn type_name(&self) -> &str {
+                match self {
+                    #(#type_name_arms)*
+                }
+            }
+
+            fn is_reply(&self) -> bool {
+                #is_reply
+            }
+
+            fn response_type(&self) -> ::std::option::Option<&'static str> {
+                #[allow(unreachable_patterns)]
+                match self {
+                    #(#response_arms)*
+                    _ => ::std::option::Option::None,
+                }
+            }
+
+            fn type_names() -> &'static [&'static str] {
+                &[#(#type_names),*]
+            }
+        }
+    })
+}
+
+#[derive(Default)]
+struct MessageAttributes {
+    reply: bool,
+    response: Option<Ident>,
+}
+
+impl MessageAttributes {
+    fn parse(attributes: &[Attribute]) -> Result<MessageAttributes> {
+        let mut result = MessageAttributes::default();
+        for attribute in attributes.iter().filter(|attribute| attribute.path().is_ident("message")) {
+            attribute.parse_nested_meta(|meta| {
+                if meta.path.is_ident("reply") {
+                    result.reply = true;
+                    Ok(())
+                } else if meta.path.is_ident("response") {
+                    let value: LitStr = meta.value()?.parse()?;
+                    result.response = Some(value.parse()?);
+                    Ok(())
+                } else {
+                    Err(meta.error("Expected `reply` or `response = \"...\"`"))
+                }
+            })?;
+        }
+        Ok(result)
+    }
+}
+
+/// The value of a `#[serde(name = "...")]` attribute, skipping the other serde attributes.
+fn serde_attribute(attributes: &[Attribute], name: &str) -> Result<Option<String>> {
+    let mut result = None;
+    for attribute in attributes.iter().filter(|attribute| attribute.path().is_ident("serde")) {
+        attribute.parse_nested_meta(|meta| {
+            if meta.path.is_ident(name) {
+                let value: LitStr = meta.value()?.parse()?;
+                result = Some(value.value());
+            } else if meta.input.peek(syn::Token![=]) {
+                meta.value()?.parse::<syn::Expr>()?;
+            } else if meta.input.peek(syn::token::Paren) {
+                let nested;
+                syn::parenthesized!(nested in meta.input);
+                nested.parse::<TokenStream2>()?;
+            }
+            Ok(())
+        })?;
+    }
+    Ok(result)
+}
+
+/// The `rename_all` rules of serde, applied to variant names.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum RenameRule {
+    Lower,
+    Upper,
+    Pascal,
+    Camel,
+    Snake,
+    ScreamingSnake,
+    Kebab,
+    ScreamingKebab,
+}
+
+impl RenameRule {
+    fn parse(rule: &str) -> Option<RenameRule> {
+        match rule {
+            "lowercase" => Some(RenameRule::Lower),
+            "UPPERCASE" => Some(RenameRule::Upper),
+            "PascalCase" => Some(RenameRule::Pascal),
+            "camelCase" => Some(RenameRule::Camel),
+            "snake_case" => Some(RenameRule::Snake),
+            "SCREAMING_SNAKE_CASE" => Some(RenameRule::ScreamingSnake),
+            "kebab-case" => Some(RenameRule::Kebab),
+            "SCREAMING-KEBAB-CASE" => Some(RenameRule::ScreamingKebab),
+            _ => None,
+        }
+    }
+
+    fn apply(&self, name: &str) -> String {
+        match self {
+            RenameRule::Lower => name.to_ascii_lowercase(),
+            RenameRule::Upper => name.to_ascii_uppercase(),
+            RenameRule::Pascal => name.to_string(),
+            RenameRule::Camel => name[..1].to_ascii_lowercase() + &name[1..],
+            RenameRule::Snake => snake_case(name),
+            RenameRule::ScreamingSnake => snake_case(name).to_ascii_uppercase(),
+            RenameRule::Kebab => snake_case(name).replace('_', "-"),
+            RenameRule::ScreamingKebab => snake_case(name).replace('_', "-").to_ascii_uppercase(),
+        }
+    }
+}
+
+fn snake_case(name: &str) -> String {
+    let mut result = String::with_capacity(name.len() + 4);
+    for (idx, char) in name.chars().enumerate() {
+        if char.is_uppercase() {
+            if idx > 0 {
+                result.push('_');
+            }
+            result.extend(char.to_lowercase());
+        } else {
+            result.push(char);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use syn::{parse_quote, DeriveInput};
+
+    use crate::{expand, RenameRule};
+
+    fn type_names(input: DeriveInput) -> String {
+        let output = expand(input).unwrap().to_string();
+        let start = output.find("fn type_names").unwrap();
+        output[start..].to_string()
+    }
+
+    #[test]
+    fn should_apply_rename_rules() {
+        assert_eq!(RenameRule::Snake.apply("ReadOk"), "read_ok");
+        assert_eq!(RenameRule::Kebab.apply("ReadOk"), "read-ok");
+        assert_eq!(RenameRule::Camel.apply("ReadOk"), "readOk");
+        assert_eq!(RenameRule::ScreamingSnake.apply("ReadOk"), "READ_OK");
+        assert_eq!(RenameRule::Lower.apply("ReadOk"), "readok");
+    }
+
+    #[test]
+    fn should_name_variants_as_serde_does() {
+        let renamed = type_names(parse_quote! {
+            #[serde(tag = "type", rename_all = "kebab-case")]
+            enum Renamed { ReadOk, #[serde(rename = "put")] Write }
+        });
+        let plain = type_names(parse_quote! {
+            #[serde(tag = "type")]
+            enum Plain { ReadOk }
+        });
+
+        assert!(renamed.contains(r#""read-ok" , "put""#), "{}", renamed);
+        assert!(plain.contains(r#""ReadOk""#), "{}", plain);
+    }
+
+    #[test]
+    fn should_reject_unknown_rename_rule() {
+        let result = expand(parse_quote! {
+            #[serde(rename_all = "Title Case")]
+            enum Unknown { Read }
+        });
+
+        assert!(result.is_err());
+    }
+}
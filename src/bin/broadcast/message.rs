@@ -2,7 +2,7 @@ use std::collections::{BTreeSet, HashMap};
 
 use serde::{Deserialize, Serialize};
 
-use gossip_glomers::common::message::NodeId;
+use gossip_glomers::common::message::{MessageKind, NodeId};
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 #[serde(untagged)]
@@ -11,24 +11,18 @@ pub enum MessageValue {
     Batch(Vec<i64>),
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
-#[serde(tag = "type")]
+#[derive(Serialize, Deserialize, MessageKind, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum BroadcastMessage {
-    #[serde(rename = "topology")]
     Topology {
         topology: HashMap<NodeId, BTreeSet<NodeId>>,
     },
-    #[serde(rename = "topology_ok")]
     TopologyOk,
-    #[serde(rename = "broadcast")]
     Broadcast {
         message: MessageValue,
     },
-    #[serde(rename = "broadcast_ok")]
     BroadcastOk,
-    #[serde(rename = "read")]
     Read,
-    #[serde(rename = "read_ok")]
     ReadOk {
         messages: BTreeSet<i64>,
     },
@@ -36,10 +30,12 @@ pub enum BroadcastMessage {
 
 #[cfg(test)]
 mod tests {
+    use serde_json::json;
+
     use std::collections::{BTreeSet, HashMap};
 
     use gossip_glomers::common::error::Result;
-    use gossip_glomers::common::message::{MessageId, NodeId};
+    use gossip_glomers::common::message::{MessageId, MessageKind, NodeId};
     use gossip_glomers::common::message::message::{MessageAddress, Message};
 
     use crate::message::{BroadcastMessage, MessageValue};
@@ -56,19 +52,20 @@ mod tests {
           }
         }}"#;
 
-        let result: Message<BroadcastMessage> = serde_json::from_str(&str)?;
+        let result: Message<BroadcastMessage> = serde_json::from_str(str)?;
 
         assert_eq!(result, Message::new_request(MessageAddress {
             src: NodeId::from("c0"),
             dest: NodeId::from("n0"),
-            msg_id: MessageId(1),
+            msg_id: Some(MessageId(1)),
+            in_reply_to: None,
         }, BroadcastMessage::Topology {
             topology: HashMap::from([
                 (NodeId::from("n1"), BTreeSet::from([NodeId::from("n2"), NodeId::from("n3")])),
                 (NodeId::from("n2"), BTreeSet::from([NodeId::from("n1")])),
                 (NodeId::from("n3"), BTreeSet::from([NodeId::from("n1")]))
             ])
-        }));
+        }).with_field("id", json!(0)));
         Ok(())
     }
 
@@ -76,15 +73,16 @@ mod tests {
     fn should_deserialize_broadcast() -> Result<()> {
         let str = r#"{"id":0,"src":"c0","dest":"n0","body":{"msg_id": 1,"type": "broadcast","message": 1000}}"#;
 
-        let result: Message<BroadcastMessage> = serde_json::from_str(&str)?;
+        let result: Message<BroadcastMessage> = serde_json::from_str(str)?;
 
         assert_eq!(result, Message::new_request(MessageAddress {
             src: NodeId::from("c0"),
             dest: NodeId::from("n0"),
-            msg_id: MessageId(1),
+            msg_id: Some(MessageId(1)),
+            in_reply_to: None,
         }, BroadcastMessage::Broadcast {
             message: MessageValue::Single(1000)
-        }));
+        }).with_field("id", json!(0)));
         Ok(())
     }
 
@@ -95,7 +93,8 @@ mod tests {
         let result = serde_json::to_string(&Message::new_request(MessageAddress {
             src: NodeId::from("c0"),
             dest: NodeId::from("n0"),
-            msg_id: MessageId(1),
+            msg_id: Some(MessageId(1)),
+            in_reply_to: None,
         }, BroadcastMessage::Broadcast {
             message: MessageValue::Single(1000)
         }))?;
@@ -108,12 +107,13 @@ mod tests {
     fn should_deserialize_custom_broadcast() -> Result<()> {
         let str = r#"{"src":"c0","dest":"n0","body":{"msg_id":1,"type":"broadcast","message":[1000]}}"#;
 
-        let result: Message<BroadcastMessage> = serde_json::from_str(&str)?;
+        let result: Message<BroadcastMessage> = serde_json::from_str(str)?;
 
         assert_eq!(result, Message::new_request(MessageAddress {
             src: NodeId::from("c0"),
             dest: NodeId::from("n0"),
-            msg_id: MessageId(1),
+            msg_id: Some(MessageId(1)),
+            in_reply_to: None,
         }, BroadcastMessage::Broadcast {
             message: MessageValue::Batch(vec![1000])
         }));
@@ -127,7 +127,8 @@ mod tests {
         let result = serde_json::to_string(&Message::new_request(MessageAddress {
             src: NodeId::from("c0"),
             dest: NodeId::from("n0"),
-            msg_id: MessageId(1),
+            msg_id: Some(MessageId(1)),
+            in_reply_to: None,
         }, BroadcastMessage::Broadcast {
             message: MessageValue::Batch(vec![1000])
         }))?;
@@ -143,13 +144,14 @@ mod tests {
           "type": "read"
         }}"#;
 
-        let result: Message<BroadcastMessage> = serde_json::from_str(&str)?;
+        let result: Message<BroadcastMessage> = serde_json::from_str(str)?;
 
         assert_eq!(result, Message::new_request(MessageAddress {
             src: NodeId::from("c0"),
             dest: NodeId::from("n0"),
-            msg_id: MessageId(1),
-        }, BroadcastMessage::Read));
+            msg_id: Some(MessageId(1)),
+            in_reply_to: None,
+        }, BroadcastMessage::Read).with_field("id", json!(0)));
         Ok(())
     }
 
@@ -160,7 +162,8 @@ mod tests {
         let result = serde_json::to_string(&Message::new_reply(MessageAddress {
             src: NodeId::from("c0"),
             dest: NodeId::from("n0"),
-            msg_id: MessageId(1),
+            msg_id: Some(MessageId(1)),
+            in_reply_to: None,
         }.to_reply_address(), BroadcastMessage::TopologyOk))?;
 
         assert_eq!(result, expected);
@@ -171,12 +174,13 @@ mod tests {
     fn should_deserialize_broadcast_ok() -> Result<()> {
         let str = r#"{"src":"n0","dest":"c0","body":{"in_reply_to":1,"type":"broadcast_ok"}}"#;
 
-        let result: Message<BroadcastMessage> = serde_json::from_str(&str)?;
+        let result: Message<BroadcastMessage> = serde_json::from_str(str)?;
 
         assert_eq!(result, Message::new_reply(MessageAddress {
             src: NodeId::from("c0"),
             dest: NodeId::from("n0"),
-            msg_id: MessageId(1),
+            msg_id: Some(MessageId(1)),
+            in_reply_to: None,
         }.to_reply_address(), BroadcastMessage::BroadcastOk));
         Ok(())
     }
@@ -188,7 +192,8 @@ mod tests {
         let result = serde_json::to_string(&Message::new_reply(MessageAddress {
             src: NodeId::from("c0"),
             dest: NodeId::from("n0"),
-            msg_id: MessageId(1),
+            msg_id: Some(MessageId(1)),
+            in_reply_to: None,
         }.to_reply_address(), BroadcastMessage::BroadcastOk))?;
 
         assert_eq!(result, expected);
@@ -202,7 +207,8 @@ mod tests {
         let result = serde_json::to_string(&Message::new_reply(MessageAddress {
             src: NodeId::from("c0"),
             dest: NodeId::from("n0"),
-            msg_id: MessageId(1),
+            msg_id: Some(MessageId(1)),
+            in_reply_to: None,
         }.to_reply_address(), BroadcastMessage::ReadOk {
             messages: BTreeSet::from([1, 8, 72, 25])
         }))?;
@@ -210,4 +216,14 @@ mod tests {
         assert_eq!(result, expected);
         Ok(())
     }
+
+    #[test]
+    fn should_classify_message_kinds() {
+        assert_eq!(BroadcastMessage::type_names(), &["topology", "topology_ok", "broadcast", "broadcast_ok", "read", "read_ok"]);
+        assert_eq!(BroadcastMessage::Read.type_name(), "read");
+        assert!(!BroadcastMessage::Read.is_reply());
+        assert!(BroadcastMessage::ReadOk { messages: Default::default() }.is_reply());
+        assert_eq!(BroadcastMessage::Read.response_type(), Some("read_ok"));
+        assert_eq!(BroadcastMessage::BroadcastOk.response_type(), None);
+    }
 }
@@ -0,0 +1,505 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use log::debug;
+use serde_json::Value;
+
+use gossip_glomers::common::actor::Actor;
+use gossip_glomers::common::context::Context;
+use gossip_glomers::common::error::Error::UnexpectedMessage;
+use gossip_glomers::common::error::Result;
+use gossip_glomers::common::failure_detector::FailureDetectorConfig;
+use gossip_glomers::common::kv::{Cas, Read};
+use gossip_glomers::common::message::message::{Message, MessageAddress};
+use gossip_glomers::common::message::{MessageKind, NodeId, ServiceKind};
+use gossip_glomers::common::runner::run_actor;
+use gossip_glomers::common::state_machine::forward;
+
+use crate::message::{ChainConfig, ChainMessage};
+
+mod message;
+
+const CONFIG_KEY: &str = "chain-config";
+const TICK_INTERVAL: Duration = Duration::from_millis(100);
+const TEMPORARILY_UNAVAILABLE: u64 = 11;
+const KEY_DOES_NOT_EXIST: u64 = 20;
+const PRECONDITION_FAILED: u64 = 22;
+
+/// Chain replication: writes go to the head, which orders them and passes them down the chain, and are
+/// acknowledged once the tail has applied them. Reads are served by the tail, after checking in `lin-kv` that it
+/// still is the tail.
+///
+/// Nodes suspected by the failure detector are removed from the chain, and recovered nodes are appended as its tail,
+/// by compare-and-set of the configuration in `lin-kv`. Every node accepts writes from its predecessor in its
+/// configuration only, and only once that predecessor has synced it with its whole state for that configuration.
+/// After that, only the writes the successor has not acknowledged are sent again, as `Replicate`.
+struct ChainActor {
+    config: ChainConfig,
+    values: BTreeMap<i64, i64>,
+    seq: u64,
+    /// The configuration this node has been synced in by its predecessor, or leads as the head.
+    synced_version: Option<u64>,
+    /// The last write the successor acknowledged in the current configuration, `None` until it has been synced.
+    successor_seq: Option<u64>,
+    /// The writes passed to the successor which it has not acknowledged yet, by `seq`.
+    unacked: BTreeMap<u64, (i64, i64)>,
+    /// The replies the head sends once the tail has applied the write at their `seq`.
+    pending: BTreeMap<u64, Vec<(MessageAddress, ChainMessage)>>,
+}
+
+impl ChainActor {
+    fn position(&self, context: &Context<Self>) -> Option<usize> {
+        self.config.nodes.iter().position(|node_id| *node_id == context.this_node().node_id)
+    }
+
+    fn head(&self) -> Option<NodeId> {
+        self.config.nodes.first().cloned()
+    }
+
+    fn tail(&self) -> Option<NodeId> {
+        self.config.nodes.last().cloned()
+    }
+
+    fn predecessor(&self, context: &Context<Self>) -> Option<NodeId> {
+        self.position(context).and_then(|position| position.checked_sub(1)).map(|position| self.config.nodes[position].clone())
+    }
+
+    fn successor(&self, context: &Context<Self>) -> Option<NodeId> {
+        self.position(context).and_then(|position| self.config.nodes.get(position + 1)).cloned()
+    }
+
+    fn is_head(&self, context: &Context<Self>) -> bool {
+        self.position(context) == Some(0)
+    }
+
+    fn is_tail(&self, context: &Context<Self>) -> bool {
+        self.position(context).is_some() && self.successor(context).is_none()
+    }
+
+    fn send(context: &mut Context<Self>, dest: NodeId, message: ChainMessage) -> Result<()> {
+        context.send(dest, serde_json::to_value(message)?);
+        Ok(())
+    }
+
+    fn reply(context: &mut Context<Self>, address: MessageAddress, message: ChainMessage) -> Result<()> {
+        context.reply(address, serde_json::to_value(message)?);
+        Ok(())
+    }
+
+    fn adopt(&mut self, context: &mut Context<Self>, config: ChainConfig) -> Result<()> {
+        if config.version <= self.config.version {
+            return Ok(());
+        }
+        debug!("Adopting chain configuration: '{:?}'", config);
+        let was_head = self.is_head(context);
+        self.config = config;
+        self.successor_seq = None;
+        self.unacked.clear();
+        if self.is_head(context) {
+            self.synced_version = Some(self.config.version);
+        } else {
+            self.synced_version = None;
+        }
+        if was_head && !self.is_head(context) {
+            // Writes which are not acknowledged yet may be lost with the old chain.
+            self.pending.clear();
+        }
+        self.sync_successor(context)?;
+        // A node acknowledges writes only once synced, which the head of a chain of its own is already.
+        if self.is_head(context) && self.is_tail(context) {
+            self.commit(context, self.seq)?;
+        }
+        Ok(())
+    }
+
+    /// Sends the whole state to a successor which has not acknowledged anything in this configuration yet, and the
+    /// writes it has not acknowledged otherwise.
+    fn sync_successor(&mut self, context: &mut Context<Self>) -> Result<()> {
+        if self.synced_version != Some(self.config.version) {
+            return Ok(());
+        }
+        let Some(successor) = self.successor(context) else { return Ok(()) };
+        let version = self.config.version;
+        if self.successor_seq.is_none() {
+            return ChainActor::send(context, successor, ChainMessage::Sync { version, seq: self.seq, values: self.values.iter().map(|(key, value)| (*key, *value)).collect() });
+        }
+        for (seq, (key, value)) in &self.unacked {
+            ChainActor::send(context, successor.clone(), ChainMessage::Replicate { version, seq: *seq, key: *key, value: *value })?;
+        }
+        Ok(())
+    }
+
+    /// Passes a write down the chain, keeping it until the successor acknowledges it.
+    fn replicate(&mut self, context: &mut Context<Self>, key: i64, value: i64) -> Result<()> {
+        let Some(successor) = self.successor(context) else { return Ok(()) };
+        self.unacked.insert(self.seq, (key, value));
+        ChainActor::send(context, successor, ChainMessage::Replicate { version: self.config.version, seq: self.seq, key, value })
+    }
+
+    /// Acknowledges every write up to `seq`: the head replies to the clients, other nodes pass it up the chain.
+    fn commit(&mut self, context: &mut Context<Self>, seq: u64) -> Result<()> {
+        if self.is_head(context) {
+            let committed: Vec<u64> = self.pending.range(..=seq).map(|(seq, _)| *seq).collect();
+            for seq in committed {
+                for (address, output) in self.pending.remove(&seq).unwrap_or_default() {
+                    ChainActor::reply(context, address, output)?;
+                }
+            }
+        } else if let Some(predecessor) = self.predecessor(context) {
+            ChainActor::send(context, predecessor, ChainMessage::Ack { version: self.config.version, seq })?;
+        }
+        Ok(())
+    }
+
+    /// Acknowledges again what the tail has applied, for a predecessor which retransmits as it missed an `Ack`.
+    fn acknowledge(&mut self, context: &mut Context<Self>) -> Result<()> {
+        let acked = if self.is_tail(context) { Some(self.seq) } else { self.successor_seq };
+        match acked {
+            Some(seq) => self.commit(context, seq),
+            None => Ok(()),
+        }
+    }
+
+    /// Replaces the configuration with one without the suspected nodes and with the recovered ones at its tail.
+    fn reconfigure(&mut self, context: &mut Context<Self>) -> Result<()> {
+        if self.position(context).is_none() {
+            return Ok(());
+        }
+        let suspects = context.failure_detector().map(|failure_detector| failure_detector.suspects().clone()).unwrap_or_default();
+        let mut nodes: Vec<NodeId> = self.config.nodes.iter().filter(|node_id| !suspects.contains(node_id)).cloned().collect();
+        let mut recovered: Vec<NodeId> = context.this_node().node_ids.iter()
+            .filter(|node_id| !suspects.contains(node_id) && !self.config.nodes.contains(node_id))
+            .cloned()
+            .collect();
+        recovered.sort();
+        nodes.extend(recovered);
+        if nodes == self.config.nodes {
+            return Ok(());
+        }
+        let to = ChainConfig { version: self.config.version + 1, nodes };
+        debug!("Proposing chain configuration: '{:?}'", to);
+        let request = Cas { key: CONFIG_KEY.to_string(), from: Some(self.config.clone()), to: to.clone(), create_if_not_exists: true };
+        context.rpc(NodeId::from(ServiceKind::LinKv), request, move |actor: &mut ChainActor, context, response| {
+            match response {
+                Ok(_) => actor.adopt(context, to),
+                Err(_) => Ok(()),
+            }
+        })
+    }
+
+    fn poll_config(&mut self, context: &mut Context<Self>) -> Result<()> {
+        context.rpc(NodeId::from(ServiceKind::LinKv), Read::<ChainConfig>::new(CONFIG_KEY), |actor: &mut ChainActor, context, response| {
+            match response {
+                Ok(read) => actor.adopt(context, read.value),
+                Err(_) => Ok(()),
+            }
+        })
+    }
+
+    fn on_write(&mut self, context: &mut Context<Self>, message: ChainMessage, address: MessageAddress) -> Result<()> {
+        let (write, output) = match message {
+            ChainMessage::Write { key, value } => (Some((key, value)), ChainMessage::WriteOk),
+            ChainMessage::Cas { key, from, to, create_if_not_exists } => match self.values.get(&key) {
+                Some(value) if *value == from => (Some((key, to)), ChainMessage::CasOk),
+                Some(value) => (None, ChainMessage::Error { code: PRECONDITION_FAILED, text: format!("Expected {} but was {}", from, value) }),
+                None if create_if_not_exists => (Some((key, to)), ChainMessage::CasOk),
+                None => (None, ChainMessage::Error { code: KEY_DOES_NOT_EXIST, text: format!("No key {}", key) }),
+            },
+            message => return Err(UnexpectedMessage(message.type_name().to_string())),
+        };
+        // Failed operations are answered once the writes they observed are acknowledged too.
+        if let Some((key, value)) = write {
+            self.seq += 1;
+            self.values.insert(key, value);
+            self.replicate(context, key, value)?;
+        }
+        self.pending.entry(self.seq).or_default().push((address, output));
+        if self.is_tail(context) {
+            self.commit(context, self.seq)?;
+        }
+        Ok(())
+    }
+
+    fn on_read(&mut self, context: &mut Context<Self>, key: i64, address: MessageAddress) -> Result<()> {
+        let version = self.config.version;
+        context.rpc(NodeId::from(ServiceKind::LinKv), Read::<ChainConfig>::new(CONFIG_KEY), move |actor: &mut ChainActor, context, response| {
+            if let Ok(read) = response {
+                actor.adopt(context, read.value)?;
+            }
+            let serving = actor.config.version == version && actor.is_tail(context) && actor.synced_version == Some(version);
+            let output = match actor.values.get(&key) {
+                _ if !serving => ChainMessage::Error { code: TEMPORARILY_UNAVAILABLE, text: "Not the tail anymore".to_string() },
+                Some(value) => ChainMessage::ReadOk { value: *value },
+                None => ChainMessage::Error { code: KEY_DOES_NOT_EXIST, text: format!("No key {}", key) },
+            };
+            ChainActor::reply(context, address, output)
+        })
+    }
+
+    fn on_chain_message(&mut self, context: &mut Context<Self>, message: ChainMessage, address: MessageAddress) -> Result<()> {
+        let from_predecessor = Some(&address.src) == self.predecessor(context).as_ref();
+        match message {
+            ChainMessage::Replicate { version, seq, key, value } => {
+                let synced = version == self.config.version && self.synced_version == Some(version);
+                if !from_predecessor || !synced {
+                    return Ok(());
+                }
+                if seq <= self.seq {
+                    return self.acknowledge(context);
+                }
+                if seq != self.seq + 1 {
+                    return Ok(());
+                }
+                self.seq = seq;
+                self.values.insert(key, value);
+                match self.successor(context) {
+                    Some(_) => self.replicate(context, key, value)?,
+                    None => self.commit(context, seq)?,
+                }
+            }
+            ChainMessage::Sync { version, seq, values } => {
+                if !from_predecessor || version != self.config.version {
+                    return Ok(());
+                }
+                // Once synced, the writes which follow arrive as `Replicate`, retransmitted until acknowledged.
+                if self.synced_version == Some(version) {
+                    return self.acknowledge(context);
+                }
+                self.seq = seq;
+                self.values = values.into_iter().collect();
+                self.synced_version = Some(version);
+                match self.successor(context) {
+                    Some(_) => self.sync_successor(context)?,
+                    None => self.commit(context, seq)?,
+                }
+            }
+            ChainMessage::Ack { version, seq } => {
+                let from_successor = Some(&address.src) == self.successor(context).as_ref();
+                if from_successor && version == self.config.version {
+                    self.successor_seq = self.successor_seq.max(Some(seq));
+                    self.unacked = self.unacked.split_off(&(seq + 1));
+                    self.commit(context, seq)?;
+                }
+            }
+            message => return Err(UnexpectedMessage(message.type_name().to_string())),
+        }
+        Ok(())
+    }
+}
+
+impl Actor for ChainActor {
+    type Msg = Value;
+    type TimerKey = ();
+
+    fn new(context: &mut Context<Self>) -> Result<Self> {
+        let mut nodes = context.this_node().node_ids.clone();
+        nodes.sort();
+        let actor = ChainActor {
+            config: ChainConfig { version: 0, nodes },
+            values: BTreeMap::new(),
+            seq: 0,
+            synced_version: Some(0),
+            // Every node starts synced, with nothing written.
+            successor_seq: Some(0),
+            unacked: BTreeMap::new(),
+            pending: BTreeMap::new(),
+        };
+        context.detect_failures(FailureDetectorConfig::default());
+        context.set_timer(TICK_INTERVAL, ());
+        Ok(actor)
+    }
+
+    fn on_request(&mut self, context: &mut Context<Self>, request: Message<Self::Msg>) -> Result<()> {
+        let (body, address) = request.body_and_address();
+        match serde_json::from_value(body.clone())? {
+            ChainMessage::Read { key } if self.is_tail(context) => self.on_read(context, key, address),
+            ChainMessage::Read { .. } => forward(context, self.tail(), body, address),
+            message @ (ChainMessage::Write { .. } | ChainMessage::Cas { .. }) if self.is_head(context) => self.on_write(context, message, address),
+            ChainMessage::Write { .. } | ChainMessage::Cas { .. } => forward(context, self.head(), body, address),
+            message => self.on_chain_message(context, message, address),
+        }
+    }
+
+    fn on_timeout(&mut self, context: &mut Context<Self>, _: Self::TimerKey) -> Result<()> {
+        self.poll_config(context)?;
+        self.reconfigure(context)?;
+        self.sync_successor(context)?;
+        context.set_timer(TICK_INTERVAL, ());
+        Ok(())
+    }
+}
+
+fn main() -> Result<()> {
+    run_actor::<ChainActor>()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, VecDeque};
+    use std::time::Instant;
+
+    use serde_json::{json, Value};
+
+    use gossip_glomers::common::context::{Action, ActorCell, TimerId};
+    use gossip_glomers::common::error::Result;
+    use gossip_glomers::common::message::message::{Message, MessageAddress};
+    use gossip_glomers::common::message::{MessageId, NodeId, ServiceKind};
+    use gossip_glomers::common::runner::RunnerAction;
+    use gossip_glomers::common::this_node::ThisNode;
+
+    use crate::message::ChainConfig;
+    use crate::{ChainActor, TICK_INTERVAL};
+
+    /// Three nodes passing messages to each other, and the ticks of each one.
+    struct Chain {
+        cells: BTreeMap<NodeId, ActorCell<ChainActor>>,
+        ticks: BTreeMap<NodeId, TimerId>,
+    }
+
+    impl Chain {
+        fn new() -> Result<Chain> {
+            let node_ids = vec![NodeId::from("n0"), NodeId::from("n1"), NodeId::from("n2")];
+            let mut chain = Chain { cells: BTreeMap::new(), ticks: BTreeMap::new() };
+            for node_id in &node_ids {
+                let (cell, actions) = ActorCell::new(ThisNode::new(node_id.clone(), node_ids.clone()), 0, Instant::now())?;
+                chain.cells.insert(node_id.clone(), cell);
+                chain.note_tick(node_id, &actions);
+            }
+            Ok(chain)
+        }
+
+        fn actor(&self, node_id: &str) -> &ChainActor {
+            self.cells[&NodeId::from(node_id)].actor()
+        }
+
+        fn note_tick(&mut self, node_id: &NodeId, actions: &[Action]) {
+            for action in actions {
+                if let RunnerAction::SetTimer { delay, timer_key } = action {
+                    if *delay == TICK_INTERVAL {
+                        self.ticks.insert(node_id.clone(), *timer_key);
+                    }
+                }
+            }
+        }
+
+        /// Fires the tick of a node, returning what it sends to clients and services.
+        fn tick(&mut self, node_id: &str) -> Result<Vec<Message<Value>>> {
+            let node_id = NodeId::from(node_id);
+            let timer_id = self.ticks[&node_id];
+            let actions = self.cells.get_mut(&node_id).unwrap().on_timeout(timer_id, Instant::now())?;
+            self.note_tick(&node_id, &actions);
+            self.deliver(sent(actions))
+        }
+
+        /// Delivers messages between the nodes until none is left, returning those to clients and services.
+        fn deliver(&mut self, messages: Vec<Message<Value>>) -> Result<Vec<Message<Value>>> {
+            let mut queue = VecDeque::from(messages);
+            let mut outside = vec![];
+            while let Some(message) = queue.pop_front() {
+                let node_id = message.dest().clone();
+                match self.cells.get_mut(&node_id) {
+                    Some(cell) => queue.extend(sent(cell.on_json_message(message, Instant::now())?)),
+                    None => outside.push(message),
+                }
+            }
+            Ok(outside)
+        }
+
+        /// Answers the configuration reads of `lin-kv` among the messages, with `config`.
+        fn answer_config(&mut self, messages: Vec<Message<Value>>, config: &ChainConfig) -> Result<Vec<Message<Value>>> {
+            let mut outside = vec![];
+            for message in messages {
+                if *message.dest() == NodeId::from(ServiceKind::LinKv) && message.body()["type"] == "read" {
+                    let reply = Message::new_reply(message.address().to_reply_address(), json!({"type": "read_ok", "value": config}));
+                    outside.extend(self.deliver(vec![reply])?);
+                } else {
+                    outside.push(message);
+                }
+            }
+            Ok(outside)
+        }
+    }
+
+    fn sent(actions: Vec<Action>) -> Vec<Message<Value>> {
+        actions.into_iter().filter_map(|action| match action {
+            RunnerAction::SendMessage(message) => Some(message),
+            _ => None,
+        }).collect()
+    }
+
+    fn from_client(msg_id: u64, dest: &str, body: Value) -> Message<Value> {
+        Message::new_request(MessageAddress {
+            src: NodeId::from("c1"),
+            dest: NodeId::from(dest),
+            msg_id: Some(MessageId(msg_id)),
+            in_reply_to: None,
+        }, body)
+    }
+
+    fn config(version: u64, nodes: &[&str]) -> ChainConfig {
+        ChainConfig { version, nodes: nodes.iter().map(|node_id| NodeId::from(*node_id)).collect() }
+    }
+
+    #[test]
+    fn should_acknowledge_writes_once_the_tail_has_applied_them() -> Result<()> {
+        let mut chain = Chain::new()?;
+
+        let replies = chain.deliver(vec![from_client(1, "n0", json!({"type": "write", "key": 1, "value": 5}))])?;
+
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].body()["type"], "write_ok");
+        for node_id in ["n0", "n1", "n2"] {
+            assert_eq!(chain.actor(node_id).values, BTreeMap::from([(1, 5)]));
+            assert_eq!(chain.actor(node_id).seq, 1);
+        }
+        assert!(chain.actor("n0").unacked.is_empty() && chain.actor("n1").unacked.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn should_only_read_from_the_tail_of_the_latest_configuration() -> Result<()> {
+        let mut chain = Chain::new()?;
+        chain.deliver(vec![from_client(1, "n0", json!({"type": "write", "key": 1, "value": 5}))])?;
+
+        let fenced = chain.deliver(vec![from_client(2, "n2", json!({"type": "read", "key": 1}))])?;
+        let served = chain.answer_config(fenced, &config(0, &["n0", "n1", "n2"]))?;
+        let fenced = chain.deliver(vec![from_client(3, "n2", json!({"type": "read", "key": 1}))])?;
+        let refused = chain.answer_config(fenced, &config(1, &["n0", "n1"]))?;
+
+        assert_eq!(served[0].body()["type"], "read_ok");
+        assert_eq!(served[0].body()["value"], 5);
+        assert_eq!(refused[0].body()["code"], 11);
+        Ok(())
+    }
+
+    #[test]
+    fn should_adopt_configuration_and_sync_successor_once() -> Result<()> {
+        let mut chain = Chain::new()?;
+        chain.deliver(vec![from_client(1, "n0", json!({"type": "write", "key": 1, "value": 5}))])?;
+        let without_head = config(1, &["n1", "n2"]);
+
+        let polls = chain.tick("n2")?;
+        chain.answer_config(polls, &without_head)?;
+        let polls = chain.tick("n1")?;
+        chain.answer_config(polls, &without_head)?;
+        // The replicated write is lost on its way to the tail.
+        let head = chain.cells.get_mut(&NodeId::from("n1")).unwrap();
+        head.on_json_message(from_client(2, "n1", json!({"type": "write", "key": 1, "value": 6})), Instant::now())?;
+        let node_id = NodeId::from("n1");
+        let timer_id = chain.ticks[&node_id];
+        let (retransmitted, _): (Vec<_>, Vec<_>) = sent(chain.cells.get_mut(&node_id).unwrap().on_timeout(timer_id, Instant::now())?)
+            .into_iter()
+            .partition(|message| message.dest().is_peer());
+        let types: Vec<Value> = retransmitted.iter().map(|message| message.body()["type"].clone()).collect();
+        let replies = chain.deliver(retransmitted)?;
+
+        assert_eq!(chain.actor("n1").config, without_head);
+        assert_eq!(chain.actor("n2").synced_version, Some(1));
+        // The successor acknowledged the sync, so only the write it is missing is sent again.
+        assert_eq!(types, vec![json!("replicate")]);
+        assert_eq!(replies[0].body()["type"], "write_ok");
+        assert_eq!(chain.actor("n2").values, BTreeMap::from([(1, 6)]));
+        assert_eq!(chain.actor("n1").successor_seq, Some(2));
+        Ok(())
+    }
+}
@@ -0,0 +1,75 @@
+use serde::{Deserialize, Serialize};
+
+use gossip_glomers::common::message::{MessageKind, NodeId};
+
+#[derive(Serialize, Deserialize, MessageKind, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChainMessage {
+    Read {
+        key: i64,
+    },
+    ReadOk {
+        value: i64,
+    },
+    Write {
+        key: i64,
+        value: i64,
+    },
+    WriteOk,
+    Cas {
+        key: i64,
+        from: i64,
+        to: i64,
+        #[serde(default)]
+        create_if_not_exists: bool,
+    },
+    CasOk,
+    Error {
+        code: u64,
+        text: String,
+    },
+    /// The write with sequence number `seq`, passed down the chain of configuration `version`.
+    Replicate {
+        version: u64,
+        seq: u64,
+        key: i64,
+        value: i64,
+    },
+    /// The whole state of a node, for a successor which has fallen behind or just joined.
+    Sync {
+        version: u64,
+        seq: u64,
+        /// Pairs rather than a map, as internally tagged messages cannot deserialize integer keys.
+        values: Vec<(i64, i64)>,
+    },
+    /// Passed up the chain once the tail has applied every write up to `seq`.
+    Ack {
+        version: u64,
+        seq: u64,
+    },
+}
+
+/// The order of the nodes, stored in `lin-kv` and replaced as nodes fail and recover.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ChainConfig {
+    pub version: u64,
+    pub nodes: Vec<NodeId>,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use gossip_glomers::common::error::Result;
+
+    use crate::message::ChainMessage;
+
+    #[test]
+    fn should_serialize_sync() -> Result<()> {
+        let result = serde_json::to_value(ChainMessage::Sync { version: 2, seq: 7, values: vec![(1, 5)] })?;
+
+        assert_eq!(result, json!({"type": "sync", "version": 2, "seq": 7, "values": [[1, 5]]}));
+        assert_eq!(serde_json::from_value::<ChainMessage>(result)?, ChainMessage::Sync { version: 2, seq: 7, values: vec![(1, 5)] });
+        Ok(())
+    }
+}
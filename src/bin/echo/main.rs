@@ -1,10 +1,8 @@
-use std::time::Instant;
 use gossip_glomers::common::actor::Actor;
-use gossip_glomers::common::error::Error::UnexpectedMessage;
+use gossip_glomers::common::context::Context;
 use gossip_glomers::common::error::Result;
 use gossip_glomers::common::message::message::Message;
-use gossip_glomers::common::runner::{reply, run_actor, RunnerAction};
-use gossip_glomers::common::this_node::ThisNode;
+use gossip_glomers::common::runner::run_actor;
 
 use crate::message::EchoMessage;
 
@@ -16,20 +14,15 @@ impl Actor for EchoActor {
     type Msg = EchoMessage;
     type TimerKey = ();
 
-    fn new(_: ThisNode) -> Result<Self> {
+    fn new(_: &mut Context<Self>) -> Result<Self> {
         Ok(EchoActor)
     }
 
-    fn on_request(&mut self, request: Message<Self::Msg>, _now: Instant) -> Result<Vec<RunnerAction<Self::Msg, Self::TimerKey>>> {
-        let (body, address) = request.body_and_address();
-        match body {
-            EchoMessage::Echo { echo } => {
-                Ok(vec![
-                    reply(address, EchoMessage::EchoOk { echo })
-                ])
-            }
-            EchoMessage::EchoOk { .. } => Err(UnexpectedMessage("EchoOk".to_string()))
+    fn on_request(&mut self, context: &mut Context<Self>, request: Message<Self::Msg>) -> Result<()> {
+        if let (EchoMessage::Echo { echo }, address) = request.body_and_address() {
+            context.reply(address, EchoMessage::EchoOk { echo });
         }
+        Ok(())
     }
 }
 
@@ -1,13 +1,13 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
-#[serde(tag = "type", rename = "echo")]
+use gossip_glomers::common::message::MessageKind;
+
+#[derive(Serialize, Deserialize, MessageKind, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum EchoMessage {
-    #[serde(rename = "echo")]
     Echo {
         echo: String,
     },
-    #[serde(rename = "echo_ok")]
     EchoOk {
         echo: String,
     },
@@ -15,6 +15,8 @@ pub enum EchoMessage {
 
 #[cfg(test)]
 mod tests {
+    use serde_json::json;
+
     use gossip_glomers::common::error::Result;
     use gossip_glomers::common::message::{MessageId, NodeId};
     use gossip_glomers::common::message::message::{Message, MessageAddress};
@@ -24,15 +26,16 @@ mod tests {
     fn should_deserialize_init() -> Result<()> {
         let str = r#"{"id":0,"src":"c0","dest":"n0","body":{"type":"echo","echo":"text","msg_id":1}}"#;
 
-        let result: Message<EchoMessage> = serde_json::from_str(&str)?;
+        let result: Message<EchoMessage> = serde_json::from_str(str)?;
 
         assert_eq!(result, Message::new_request(MessageAddress {
             src: NodeId::from("c0"),
             dest: NodeId::from("n0"),
-            msg_id: MessageId(1),
+            msg_id: Some(MessageId(1)),
+            in_reply_to: None,
         }, EchoMessage::Echo {
             echo: "text".to_string(),
-        }));
+        }).with_field("id", json!(0)));
         Ok(())
     }
 
@@ -43,7 +46,8 @@ mod tests {
         let result = serde_json::to_string(&Message::new_reply(MessageAddress {
             src: NodeId::from("c0"),
             dest: NodeId::from("n0"),
-            msg_id: MessageId(1),
+            msg_id: Some(MessageId(1)),
+            in_reply_to: None,
         }.to_reply_address(), EchoMessage::EchoOk {
             echo: "text".to_string()
         }))?;
@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use gossip_glomers::common::error::Error::Config;
+use gossip_glomers::common::error::Result;
+use gossip_glomers::common::paxos::Paxos;
+use gossip_glomers::common::raft::Raft;
+use gossip_glomers::common::runner::{option, run_actor};
+use gossip_glomers::common::state_machine::StateMachine;
+
+use crate::message::KvMessage;
+
+mod message;
+
+const KEY_DOES_NOT_EXIST: u64 = 20;
+const PRECONDITION_FAILED: u64 = 22;
+const NOT_SUPPORTED: u64 = 10;
+const BACKEND_FLAG: &str = "--backend";
+const BACKEND_VAR: &str = "GOSSIP_GLOMERS_BACKEND";
+
+#[derive(Serialize, Deserialize, Default)]
+struct KvStore {
+    values: HashMap<i64, i64>,
+}
+
+impl StateMachine for KvStore {
+    type Command = KvMessage;
+    type Output = KvMessage;
+
+    fn apply(&mut self, command: KvMessage) -> KvMessage {
+        match command {
+            KvMessage::Read { key } => match self.values.get(&key) {
+                Some(value) => KvMessage::ReadOk { value: *value },
+                None => KvMessage::Error { code: KEY_DOES_NOT_EXIST, text: format!("No key {}", key) },
+            },
+            KvMessage::Write { key, value } => {
+                self.values.insert(key, value);
+                KvMessage::WriteOk
+            }
+            KvMessage::Cas { key, from, to, create_if_not_exists } => match self.values.get(&key) {
+                Some(value) if *value == from => {
+                    self.values.insert(key, to);
+                    KvMessage::CasOk
+                }
+                Some(value) => KvMessage::Error { code: PRECONDITION_FAILED, text: format!("Expected {} but was {}", from, value) },
+                None if create_if_not_exists => {
+                    self.values.insert(key, to);
+                    KvMessage::CasOk
+                }
+                None => KvMessage::Error { code: KEY_DOES_NOT_EXIST, text: format!("No key {}", key) },
+            },
+            reply => KvMessage::Error { code: NOT_SUPPORTED, text: format!("Unexpected command {:?}", reply) },
+        }
+    }
+}
+
+/// Replicates the store with `--backend raft` (the default) or `--backend paxos`.
+fn main() -> Result<()> {
+    match option(BACKEND_FLAG, BACKEND_VAR)?.as_deref() {
+        None | Some("raft") => run_actor::<Raft<KvStore>>(),
+        Some("paxos") => run_actor::<Paxos<KvStore>>(),
+        Some(backend) => Err(Config(format!("Unknown backend '{}'", backend))),
+    }
+}
@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+
+use gossip_glomers::common::message::MessageKind;
+
+#[derive(Serialize, Deserialize, MessageKind, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum KvMessage {
+    Read {
+        key: i64,
+    },
+    ReadOk {
+        value: i64,
+    },
+    Write {
+        key: i64,
+        value: i64,
+    },
+    WriteOk,
+    Cas {
+        key: i64,
+        from: i64,
+        to: i64,
+        #[serde(default)]
+        create_if_not_exists: bool,
+    },
+    CasOk,
+    Error {
+        code: u64,
+        text: String,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use gossip_glomers::common::error::Result;
+
+    use crate::message::KvMessage;
+
+    #[test]
+    fn should_deserialize_cas() -> Result<()> {
+        let result: KvMessage = serde_json::from_str(r#"{"type":"cas","key":1,"from":2,"to":3}"#)?;
+
+        assert_eq!(result, KvMessage::Cas { key: 1, from: 2, to: 3, create_if_not_exists: false });
+        Ok(())
+    }
+}
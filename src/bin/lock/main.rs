@@ -0,0 +1,404 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use log::debug;
+use serde_json::Value;
+
+use gossip_glomers::common::actor::Actor;
+use gossip_glomers::common::context::Context;
+use gossip_glomers::common::error::Result;
+use gossip_glomers::common::lease::{campaign, Candidate, LeaderElection, LeaseConfig};
+use gossip_glomers::common::kv::{Cas, Read};
+use gossip_glomers::common::message::message::{Message, MessageAddress};
+use gossip_glomers::common::message::{MessageKind, NodeId, ServiceKind};
+use gossip_glomers::common::runner::run_actor;
+use gossip_glomers::common::state_machine::forward;
+
+use crate::message::{Lock, LockMessage, LockTable};
+
+mod message;
+
+const LEASE_KEY: &str = "lock-leader";
+const TABLE_KEY: &str = "locks";
+const TIMEOUT: u64 = 0;
+const TEMPORARILY_UNAVAILABLE: u64 = 11;
+const MALFORMED_REQUEST: u64 = 12;
+const KEY_DOES_NOT_EXIST: u64 = 20;
+const PRECONDITION_FAILED: u64 = 22;
+
+/// A change to the table, and the expiry of the lock it changes once written.
+type Change = (LockTable, String, Option<Instant>);
+
+/// A lock service served by the holder of a lease in `lin-kv`, other nodes forwarding to it. The leader writes
+/// the lock table to `lin-kv` before replying, one change at a time, so a new leader picks up every lock and
+/// keeps handing out increasing fencing tokens.
+///
+/// Expiries are only known to the leader, on its own clock. A new leader counts every lock it loads as held for a
+/// whole `ttl` from its election, which can only extend them.
+struct LockActor {
+    election: LeaderElection,
+    /// The fencing token of the lease while this node leads.
+    term: Option<u64>,
+    /// The table as last written, once the leader has loaded it.
+    table: Option<LockTable>,
+    expiries: HashMap<String, Instant>,
+    queue: VecDeque<(LockMessage, MessageAddress)>,
+    writing: bool,
+}
+
+impl LockActor {
+    fn reply(context: &mut Context<Self>, address: MessageAddress, message: LockMessage) -> Result<()> {
+        context.reply(address, serde_json::to_value(message)?);
+        Ok(())
+    }
+
+    fn unavailable(text: &str) -> LockMessage {
+        LockMessage::Error { code: TEMPORARILY_UNAVAILABLE, text: text.to_string() }
+    }
+
+    fn malformed(text: String) -> LockMessage {
+        LockMessage::Error { code: MALFORMED_REQUEST, text }
+    }
+
+    fn load(&mut self, context: &mut Context<Self>) -> Result<()> {
+        let term = self.term;
+        context.rpc(NodeId::from(ServiceKind::LinKv), Read::<LockTable>::new(TABLE_KEY), move |actor: &mut LockActor, context, response| {
+            if actor.term != term {
+                return Ok(());
+            }
+            let table = match response {
+                Ok(read) => read.value,
+                Err(error) if error.code == KEY_DOES_NOT_EXIST => LockTable::default(),
+                Err(_) => return actor.load(context),
+            };
+            debug!("Loaded lock table: '{:?}'", table);
+            actor.install(table, context.now());
+            actor.process(context)
+        })
+    }
+
+    /// Takes over a table loaded by a new leader, counting every lock as held for a whole `ttl` from now.
+    fn install(&mut self, table: LockTable, now: Instant) {
+        self.expiries = table.locks.iter().map(|(name, lock)| (name.clone(), now + Duration::from_millis(lock.ttl))).collect();
+        self.table = Some(table);
+    }
+
+    fn apply(&mut self, (to, name, expiry): Change) {
+        self.table = Some(to);
+        match expiry {
+            Some(expiry) => self.expiries.insert(name, expiry),
+            None => self.expiries.remove(&name),
+        };
+    }
+
+    /// Runs the queued requests one after the other, each once the previous change is written.
+    fn process(&mut self, context: &mut Context<Self>) -> Result<()> {
+        while !self.writing {
+            let Some(table) = self.table.as_ref() else { return Ok(()) };
+            let Some((message, address)) = self.queue.pop_front() else { return Ok(()) };
+            let (change, output) = self.evaluate(table, message, context.now());
+            let Some(change) = change else {
+                LockActor::reply(context, address, output)?;
+                continue;
+            };
+            self.writing = true;
+            let term = self.term;
+            let request = Cas { key: TABLE_KEY.to_string(), from: Some(table.clone()), to: change.0.clone(), create_if_not_exists: true };
+            context.rpc(NodeId::from(ServiceKind::LinKv), request, move |actor: &mut LockActor, context, response| {
+                // A change which timed out may still have been written, and one written stays so past the term.
+                let failure = match &response {
+                    Ok(_) => None,
+                    Err(error) if error.code == TIMEOUT => Some(LockMessage::Error { code: TIMEOUT, text: "Lock table write timed out".to_string() }),
+                    Err(_) => Some(LockActor::unavailable("Lock table changed")),
+                };
+                if actor.term != term {
+                    return LockActor::reply(context, address, failure.unwrap_or(output));
+                }
+                actor.writing = false;
+                if let Some(failure) = failure {
+                    // Another node may have written the table: reload it rather than trust this copy.
+                    actor.table = None;
+                    LockActor::reply(context, address, failure)?;
+                    return actor.load(context);
+                }
+                actor.apply(change);
+                LockActor::reply(context, address, output)?;
+                actor.process(context)
+            })?;
+        }
+        Ok(())
+    }
+
+    /// The change a request makes to the table, if any, and the reply once it is written.
+    fn evaluate(&self, table: &LockTable, message: LockMessage, now: Instant) -> (Option<Change>, LockMessage) {
+        let held = |name: &str| table.locks.get(name).filter(|_| self.expiries.get(name).is_some_and(|expiry| now < *expiry));
+        let precondition_failed = |text: String| (None, LockMessage::Error { code: PRECONDITION_FAILED, text });
+        match message {
+            LockMessage::Acquire { lock, owner, ttl } => match held(&lock) {
+                Some(holder) => precondition_failed(format!("Lock {} held by {}", lock, holder.owner)),
+                None => {
+                    let mut to = table.clone();
+                    to.last_token += 1;
+                    let token = to.last_token;
+                    to.locks.insert(lock.clone(), Lock { owner, token, ttl });
+                    (Some((to, lock, Some(now + Duration::from_millis(ttl)))), LockMessage::AcquireOk { token })
+                }
+            },
+            LockMessage::Renew { lock, token, ttl } => match held(&lock) {
+                Some(holder) if holder.token == token => {
+                    let mut to = table.clone();
+                    to.locks.insert(lock.clone(), Lock { ttl, ..holder.clone() });
+                    (Some((to, lock, Some(now + Duration::from_millis(ttl)))), LockMessage::RenewOk)
+                }
+                _ => precondition_failed(format!("Lock {} not held with token {}", lock, token)),
+            },
+            LockMessage::Release { lock, token } => match table.locks.get(&lock) {
+                Some(holder) if holder.token == token => {
+                    let mut to = table.clone();
+                    to.locks.remove(&lock);
+                    (Some((to, lock, None)), LockMessage::ReleaseOk)
+                }
+                _ => precondition_failed(format!("Lock {} not held with token {}", lock, token)),
+            },
+            message => (None, LockActor::malformed(format!("Unexpected request '{}'", message.type_name()))),
+        }
+    }
+}
+
+impl Candidate for LockActor {
+    fn election(&mut self) -> &mut LeaderElection {
+        &mut self.election
+    }
+
+    fn on_elected(&mut self, context: &mut Context<Self>, token: u64) -> Result<()> {
+        debug!("Elected with token '{}'", token);
+        self.term = Some(token);
+        self.table = None;
+        self.writing = false;
+        self.load(context)
+    }
+
+    fn on_deposed(&mut self, context: &mut Context<Self>) -> Result<()> {
+        debug!("Deposed");
+        self.term = None;
+        self.table = None;
+        self.expiries.clear();
+        self.writing = false;
+        for (_, address) in std::mem::take(&mut self.queue) {
+            LockActor::reply(context, address, LockActor::unavailable("Not the leader anymore"))?;
+        }
+        Ok(())
+    }
+}
+
+impl Actor for LockActor {
+    type Msg = Value;
+    type TimerKey = ();
+
+    fn new(context: &mut Context<Self>) -> Result<Self> {
+        context.set_timer(Duration::ZERO, ());
+        Ok(LockActor {
+            election: LeaderElection::new(LeaseConfig { key: LEASE_KEY.to_string(), ..LeaseConfig::default() }),
+            term: None,
+            table: None,
+            expiries: HashMap::new(),
+            queue: VecDeque::new(),
+            writing: false,
+        })
+    }
+
+    fn on_request(&mut self, context: &mut Context<Self>, request: Message<Self::Msg>) -> Result<()> {
+        let (body, address) = request.body_and_address();
+        if self.term.is_some() && self.election.is_leader(context.now()) {
+            return match serde_json::from_value(body) {
+                Ok(message) => {
+                    self.queue.push_back((message, address));
+                    self.process(context)
+                }
+                Err(error) => LockActor::reply(context, address, LockActor::malformed(error.to_string())),
+            };
+        }
+        let this_node = &context.this_node().node_id;
+        let leader = self.election.lease()
+            .map(|lease| lease.holder.clone())
+            .filter(|holder| holder != this_node);
+        forward(context, leader, body, address)
+    }
+
+    fn on_timeout(&mut self, context: &mut Context<Self>, _: Self::TimerKey) -> Result<()> {
+        campaign(self, context)?;
+        context.set_timer(self.election.config().renew_interval, ());
+        Ok(())
+    }
+}
+
+fn main() -> Result<()> {
+    run_actor::<LockActor>()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeSet, HashMap, VecDeque};
+    use std::time::{Duration, Instant};
+
+    use serde_json::json;
+
+    use gossip_glomers::common::driver::{ClientDriver, DriverConfig, Transport};
+    use gossip_glomers::common::error::Result;
+    use gossip_glomers::common::lease::{LeaderElection, LeaseConfig};
+    use gossip_glomers::common::message::message::{Message, MessageAddress};
+    use gossip_glomers::common::message::{MessageId, NodeId};
+    use gossip_glomers::common::simulator::{Simulator, SimulatorConfig};
+    use gossip_glomers::common::workload::lock::LockWorkload;
+
+    use crate::message::{LockMessage, LockTable};
+    use crate::LockActor;
+
+    /// A leader which has loaded the table at `now`.
+    fn leader(table: LockTable, now: Instant) -> LockActor {
+        let mut actor = LockActor {
+            election: LeaderElection::new(LeaseConfig::default()),
+            term: Some(1),
+            table: None,
+            expiries: HashMap::new(),
+            queue: VecDeque::new(),
+            writing: false,
+        };
+        actor.install(table, now);
+        actor
+    }
+
+    /// Evaluates a request and applies its change as if written, returning the reply.
+    fn run(actor: &mut LockActor, message: LockMessage, now: Instant) -> LockMessage {
+        let table = actor.table.clone().unwrap();
+        let (change, output) = actor.evaluate(&table, message, now);
+        if let Some(change) = change {
+            actor.apply(change);
+        }
+        output
+    }
+
+    fn acquire(lock: &str, owner: &str) -> LockMessage {
+        LockMessage::Acquire { lock: lock.to_string(), owner: owner.to_string(), ttl: 100 }
+    }
+
+    fn is_precondition_failed(message: &LockMessage) -> bool {
+        matches!(message, LockMessage::Error { code: 22, .. })
+    }
+
+    #[test]
+    fn should_hand_expired_locks_out_again() {
+        let now = Instant::now();
+        let mut actor = leader(LockTable::default(), now);
+
+        let acquired = run(&mut actor, acquire("a", "o1"), now);
+        let held = run(&mut actor, acquire("a", "o2"), now + Duration::from_millis(99));
+        let expired = run(&mut actor, acquire("a", "o2"), now + Duration::from_millis(100));
+
+        assert_eq!(acquired, LockMessage::AcquireOk { token: 1 });
+        assert!(is_precondition_failed(&held));
+        assert_eq!(expired, LockMessage::AcquireOk { token: 2 });
+    }
+
+    #[test]
+    fn should_only_renew_and_release_with_the_current_token() {
+        let now = Instant::now();
+        let mut actor = leader(LockTable::default(), now);
+        run(&mut actor, acquire("a", "o1"), now);
+
+        let renewed_by_other = run(&mut actor, LockMessage::Renew { lock: "a".to_string(), token: 2, ttl: 100 }, now);
+        let released_by_other = run(&mut actor, LockMessage::Release { lock: "a".to_string(), token: 2 }, now);
+        let renewed = run(&mut actor, LockMessage::Renew { lock: "a".to_string(), token: 1, ttl: 100 }, now + Duration::from_millis(50));
+        let still_held = run(&mut actor, acquire("a", "o2"), now + Duration::from_millis(120));
+        let released = run(&mut actor, LockMessage::Release { lock: "a".to_string(), token: 1 }, now + Duration::from_millis(120));
+
+        assert!(is_precondition_failed(&renewed_by_other));
+        assert!(is_precondition_failed(&released_by_other));
+        assert_eq!(renewed, LockMessage::RenewOk);
+        assert!(is_precondition_failed(&still_held));
+        assert_eq!(released, LockMessage::ReleaseOk);
+        assert!(actor.table.unwrap().locks.is_empty());
+    }
+
+    #[test]
+    fn should_keep_tokens_rising_across_elections() {
+        let now = Instant::now();
+        let mut first = leader(LockTable::default(), now);
+        run(&mut first, acquire("a", "o1"), now);
+
+        // The next leader loads the table long after the lock expired on the clock of the first one.
+        let elected = now + Duration::from_millis(500);
+        let mut second = leader(first.table.clone().unwrap(), elected);
+        let held = run(&mut second, acquire("a", "o2"), elected + Duration::from_millis(99));
+        let other = run(&mut second, acquire("b", "o2"), elected);
+        let expired = run(&mut second, acquire("a", "o2"), elected + Duration::from_millis(100));
+
+        assert!(is_precondition_failed(&held));
+        assert_eq!(other, LockMessage::AcquireOk { token: 2 });
+        assert_eq!(expired, LockMessage::AcquireOk { token: 3 });
+    }
+
+    #[test]
+    fn should_refuse_unexpected_requests() {
+        let now = Instant::now();
+        let mut actor = leader(LockTable::default(), now);
+
+        let unexpected = run(&mut actor, LockMessage::AcquireOk { token: 1 }, now);
+
+        assert!(matches!(unexpected, LockMessage::Error { code: 12, .. }));
+        assert_eq!(actor.table.unwrap().last_token, 0);
+    }
+
+    #[test]
+    fn should_refuse_malformed_requests() -> Result<()> {
+        let mut simulator = Simulator::<LockActor>::new(SimulatorConfig {
+            node_count: 1,
+            latency: Duration::from_millis(5),
+            ..SimulatorConfig::default()
+        })?;
+        simulator.run_until(Duration::from_secs(1))?;
+        let n0 = NodeId::from("n0");
+        let request = |msg_id, body| Message::new_request(MessageAddress {
+            src: NodeId::from("c1"),
+            dest: n0.clone(),
+            msg_id: Some(MessageId(msg_id)),
+            in_reply_to: None,
+        }, body);
+
+        simulator.send(request(1, json!({"type": "acquire", "lock": 1})))?;
+        let malformed = simulator.receive(Duration::from_secs(2))?.unwrap();
+        simulator.send(request(2, json!({"type": "acquire", "lock": "a", "owner": "o1", "ttl": 100})))?;
+        let acquired = simulator.receive(Duration::from_secs(3))?.unwrap();
+
+        assert_eq!(malformed.body()["code"], json!(12));
+        assert_eq!(acquired.body()["type"], json!("acquire_ok"));
+        Ok(())
+    }
+
+    #[test]
+    fn should_never_hand_out_a_token_twice() -> Result<()> {
+        let mut simulator = Simulator::<LockActor>::new(SimulatorConfig {
+            node_count: 3,
+            latency: Duration::from_millis(5),
+            jitter: Duration::from_millis(5),
+            ..SimulatorConfig::default()
+        })?;
+        let history = ClientDriver::new(DriverConfig {
+            concurrency: 4,
+            rate: 50.0,
+            time_limit: Duration::from_secs(5),
+            ..DriverConfig::default()
+        }, LockWorkload::default()).run(&mut simulator)?;
+
+        let operations = history.operations();
+        let tokens: Vec<u64> = operations.iter()
+            .filter(|operation| operation.is_ok() && operation.f == "acquire")
+            .filter_map(|operation| operation.result().and_then(|result| result["token"].as_u64()))
+            .collect();
+        assert!(tokens.len() > 10);
+        assert_eq!(tokens.iter().collect::<BTreeSet<_>>().len(), tokens.len());
+        assert!(operations.iter().any(|operation| operation.is_ok() && operation.f == "renew"));
+        assert!(operations.iter().any(|operation| operation.is_ok() && operation.f == "release"));
+        Ok(())
+    }
+}
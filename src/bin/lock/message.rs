@@ -0,0 +1,66 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use gossip_glomers::common::message::MessageKind;
+
+#[derive(Serialize, Deserialize, MessageKind, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LockMessage {
+    /// Takes a free or expired lock for `ttl` milliseconds.
+    Acquire {
+        lock: String,
+        owner: String,
+        ttl: u64,
+    },
+    /// `token` is the fencing token clients pass to the writes they make under the lock.
+    AcquireOk {
+        token: u64,
+    },
+    /// Extends a lock which has not expired yet for another `ttl` milliseconds.
+    Renew {
+        lock: String,
+        token: u64,
+        ttl: u64,
+    },
+    RenewOk,
+    Release {
+        lock: String,
+        token: u64,
+    },
+    ReleaseOk,
+    Error {
+        code: u64,
+        text: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Lock {
+    pub owner: String,
+    pub token: u64,
+    pub ttl: u64,
+}
+
+/// The locks, stored in `lin-kv` under a single key so every change is one `cas`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct LockTable {
+    /// The last fencing token handed out, by any leader.
+    pub last_token: u64,
+    pub locks: BTreeMap<String, Lock>,
+}
+
+#[cfg(test)]
+mod tests {
+    use gossip_glomers::common::error::Result;
+
+    use crate::message::LockMessage;
+
+    #[test]
+    fn should_deserialize_renew() -> Result<()> {
+        let result: LockMessage = serde_json::from_str(r#"{"type":"renew","lock":"a","token":3,"ttl":500}"#)?;
+
+        assert_eq!(result, LockMessage::Renew { lock: "a".to_string(), token: 3, ttl: 500 });
+        Ok(())
+    }
+}
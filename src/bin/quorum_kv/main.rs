@@ -0,0 +1,490 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use log::debug;
+
+use gossip_glomers::common::actor::Actor;
+use gossip_glomers::common::context::Context;
+use gossip_glomers::common::error::Error::{Config, UnexpectedMessage};
+use gossip_glomers::common::error::Result;
+use gossip_glomers::common::failure_detector::FailureDetectorConfig;
+use gossip_glomers::common::message::message::{Message, MessageAddress};
+use gossip_glomers::common::message::{MessageKind, NodeId};
+use gossip_glomers::common::ring::Ring;
+use gossip_glomers::common::runner::{option, run_actor};
+
+use crate::message::{Get, GetOk, Put, QuorumMessage};
+use crate::version::{context as write_context, reconcile, resolve, Versioned};
+
+mod message;
+mod version;
+
+const OPERATION_TIMEOUT: Duration = Duration::from_millis(1000);
+const HANDOFF_INTERVAL: Duration = Duration::from_millis(500);
+const TIMEOUT: u64 = 0;
+const TEMPORARILY_UNAVAILABLE: u64 = 11;
+const KEY_DOES_NOT_EXIST: u64 = 20;
+const PRECONDITION_FAILED: u64 = 22;
+const REPLICAS_FLAG: &str = "--replicas";
+const REPLICAS_VAR: &str = "GOSSIP_GLOMERS_REPLICAS";
+const READ_QUORUM_FLAG: &str = "--read-quorum";
+const READ_QUORUM_VAR: &str = "GOSSIP_GLOMERS_READ_QUORUM";
+const WRITE_QUORUM_FLAG: &str = "--write-quorum";
+const WRITE_QUORUM_VAR: &str = "GOSSIP_GLOMERS_WRITE_QUORUM";
+
+/// `N` replicas per key, `R` of which answer a read and `W` of which acknowledge a write.
+#[derive(Debug)]
+struct QuorumConfig {
+    replicas: usize,
+    read_quorum: usize,
+    write_quorum: usize,
+}
+
+impl QuorumConfig {
+    /// Reads `--replicas`, `--read-quorum` and `--write-quorum`, defaulting to 3, 2 and 2. There are no more
+    /// replicas than nodes.
+    fn from_options(node_count: usize) -> Result<QuorumConfig> {
+        let config = QuorumConfig {
+            replicas: quorum_option(REPLICAS_FLAG, REPLICAS_VAR, 3)?.min(node_count),
+            read_quorum: quorum_option(READ_QUORUM_FLAG, READ_QUORUM_VAR, 2)?,
+            write_quorum: quorum_option(WRITE_QUORUM_FLAG, WRITE_QUORUM_VAR, 2)?,
+        };
+        if config.read_quorum > config.replicas || config.write_quorum > config.replicas {
+            return Err(Config(format!("Quorums larger than the replicas: '{:?}'", config)));
+        }
+        Ok(config)
+    }
+}
+
+fn quorum_option(flag: &str, var: &str, default: usize) -> Result<usize> {
+    match option(flag, var)? {
+        Some(value) => value.parse().ok().filter(|value| *value > 0).ok_or_else(|| Config(format!("Invalid value '{}' for '{}'", value, flag))),
+        None => Ok(default),
+    }
+}
+
+/// A node the coordinator sends to, and the replica it stands in for if that one is suspected.
+type Replica = (NodeId, Option<NodeId>);
+
+#[derive(Debug)]
+enum Kind {
+    Read,
+    Write { value: i64 },
+    Cas { from: i64, to: i64, create_if_not_exists: bool },
+}
+
+#[derive(Debug, PartialEq)]
+enum Phase {
+    Reading,
+    Writing,
+    Done,
+}
+
+/// A client operation coordinated by this node: a quorum read, then a quorum write of a version superseding
+/// every sibling read, unless it is a read or a failed `cas`.
+#[derive(Debug)]
+struct Operation {
+    address: MessageAddress,
+    key: i64,
+    kind: Kind,
+    phase: Phase,
+    replicas: Vec<Replica>,
+    /// The siblings every replica answered so far, reconciled.
+    versions: Vec<Versioned>,
+    responses: HashMap<NodeId, Vec<Versioned>>,
+    acks: usize,
+    output: Option<QuorumMessage>,
+}
+
+/// A Dynamo-style store: every node coordinates the operations it receives over the replicas of the key on a
+/// consistent hash ring. Concurrent writes are kept as siblings, and stale replicas repaired by the reads which
+/// notice them. Healthy nodes further along the ring stand in for suspected replicas, and hand their writes off
+/// once these recover.
+struct QuorumActor {
+    config: QuorumConfig,
+    ring: Ring,
+    store: HashMap<i64, Vec<Versioned>>,
+    hints: HashMap<NodeId, HashMap<i64, Vec<Versioned>>>,
+    /// The last write this node coordinated, counted in its entry of the vector clocks.
+    counter: u64,
+    operations: HashMap<u64, Operation>,
+    next_operation_id: u64,
+}
+
+#[derive(Debug)]
+enum QuorumTimer {
+    Operation(u64),
+    Handoff,
+}
+
+impl QuorumActor {
+    fn is_suspected(context: &Context<Self>, node_id: &NodeId) -> bool {
+        context.failure_detector().is_some_and(|failure_detector| failure_detector.is_suspected(node_id))
+    }
+
+    /// The replicas of the key, with the first healthy nodes past them on the ring standing in for suspected ones.
+    fn replicas(&self, context: &Context<Self>, key: i64) -> Vec<Replica> {
+        let walk = self.ring.walk(&key);
+        let (replicas, rest) = walk.split_at(self.config.replicas);
+        let mut fallbacks = rest.iter().filter(|node_id| !QuorumActor::is_suspected(context, node_id));
+        replicas.iter()
+            .filter_map(|node_id| match QuorumActor::is_suspected(context, node_id) {
+                false => Some((node_id.clone(), None)),
+                true => fallbacks.next().map(|fallback| (fallback.clone(), Some(node_id.clone()))),
+            })
+            .collect()
+    }
+
+    /// The siblings this node holds for the key, for itself or for the replicas it stands in for.
+    fn versions(&self, key: i64) -> Vec<Versioned> {
+        self.hints.values()
+            .filter_map(|hinted| hinted.get(&key))
+            .fold(self.store.get(&key).cloned().unwrap_or_default(), |versions, hinted| reconcile(&versions, hinted))
+    }
+
+    fn put(&mut self, context: &Context<Self>, key: i64, versions: &[Versioned], hint: Option<NodeId>) {
+        let store = match hint {
+            Some(hint) if hint != context.this_node().node_id => self.hints.entry(hint).or_default(),
+            _ => &mut self.store,
+        };
+        let merged = reconcile(store.get(&key).map(Vec::as_slice).unwrap_or_default(), versions);
+        store.insert(key, merged);
+    }
+
+    /// Answers a replica request, from a coordinator or from this node.
+    fn on_replica_request(&mut self, context: &Context<Self>, request: QuorumMessage) -> Result<QuorumMessage> {
+        match request {
+            QuorumMessage::Get { key } => Ok(QuorumMessage::GetOk { versions: self.versions(key) }),
+            QuorumMessage::Put { key, versions, hint } => {
+                self.put(context, key, &versions, hint);
+                Ok(QuorumMessage::PutOk)
+            }
+            request => Err(UnexpectedMessage(request.type_name().to_string())),
+        }
+    }
+
+    fn send_get(&mut self, context: &mut Context<Self>, operation_id: u64, node_id: NodeId, key: i64) -> Result<()> {
+        if node_id == context.this_node().node_id {
+            let versions = self.versions(key);
+            return self.on_get_ok(context, operation_id, node_id, versions);
+        }
+        context.rpc(node_id.clone(), Get { key }, move |actor: &mut QuorumActor, context, response| {
+            match response {
+                Ok(GetOk { versions }) => actor.on_get_ok(context, operation_id, node_id, versions),
+                Err(_) => Ok(()),
+            }
+        })
+    }
+
+    fn send_put(&mut self, context: &mut Context<Self>, operation_id: u64, node_id: NodeId, request: Put) -> Result<()> {
+        if node_id == context.this_node().node_id {
+            self.put(context, request.key, &request.versions, request.hint);
+            return self.on_put_ok(context, operation_id);
+        }
+        context.rpc(node_id, request, move |actor: &mut QuorumActor, context, response| {
+            match response {
+                Ok(_) => actor.on_put_ok(context, operation_id),
+                Err(_) => Ok(()),
+            }
+        })
+    }
+
+    fn on_client_request(&mut self, context: &mut Context<Self>, key: i64, kind: Kind, address: MessageAddress) -> Result<()> {
+        let operation_id = self.next_operation_id;
+        self.next_operation_id += 1;
+        let replicas = self.replicas(context, key);
+        debug!("Coordinating '{:?}' of key '{}' over '{:?}'", kind, key, replicas);
+        context.set_timer(OPERATION_TIMEOUT, QuorumTimer::Operation(operation_id));
+        self.operations.insert(operation_id, Operation {
+            address,
+            key,
+            kind,
+            phase: Phase::Reading,
+            replicas: replicas.clone(),
+            versions: Vec::new(),
+            responses: HashMap::new(),
+            acks: 0,
+            output: None,
+        });
+        for (node_id, _) in replicas {
+            self.send_get(context, operation_id, node_id, key)?;
+        }
+        Ok(())
+    }
+
+    fn on_get_ok(&mut self, context: &mut Context<Self>, operation_id: u64, node_id: NodeId, versions: Vec<Versioned>) -> Result<()> {
+        let Some(operation) = self.operations.get_mut(&operation_id) else { return Ok(()) };
+        if operation.phase == Phase::Writing {
+            return Ok(());
+        }
+        operation.versions = reconcile(&operation.versions, &versions);
+        operation.responses.insert(node_id.clone(), versions);
+        if operation.phase == Phase::Done {
+            self.repair(context, operation_id, Some(node_id))
+        } else if operation.responses.len() >= self.config.read_quorum {
+            self.on_read_quorum(context, operation_id)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn on_put_ok(&mut self, context: &mut Context<Self>, operation_id: u64) -> Result<()> {
+        let Some(operation) = self.operations.get_mut(&operation_id) else { return Ok(()) };
+        operation.acks += 1;
+        if operation.phase == Phase::Writing && operation.acks >= self.config.write_quorum {
+            operation.phase = Phase::Done;
+            if let Some(output) = operation.output.take() {
+                context.reply(operation.address.clone(), output);
+            }
+        }
+        Ok(())
+    }
+
+    fn on_read_quorum(&mut self, context: &mut Context<Self>, operation_id: u64) -> Result<()> {
+        let Some(operation) = self.operations.get_mut(&operation_id) else { return Ok(()) };
+        let current = resolve(&operation.versions);
+        let missing = || QuorumMessage::Error { code: KEY_DOES_NOT_EXIST, text: format!("No key {}", operation.key) };
+        let (write, output) = match operation.kind {
+            Kind::Read => (None, current.map_or_else(missing, |value| QuorumMessage::ReadOk { value })),
+            Kind::Write { value } => (Some(value), QuorumMessage::WriteOk),
+            Kind::Cas { from, to, create_if_not_exists } => match current {
+                Some(value) if value == from => (Some(to), QuorumMessage::CasOk),
+                Some(value) => (None, QuorumMessage::Error { code: PRECONDITION_FAILED, text: format!("Expected {} but was {}", from, value) }),
+                None if create_if_not_exists => (Some(to), QuorumMessage::CasOk),
+                None => (None, missing()),
+            },
+        };
+        let Some(value) = write else {
+            operation.phase = Phase::Done;
+            context.reply(operation.address.clone(), output);
+            return self.repair(context, operation_id, None);
+        };
+
+        let this_node = context.this_node().node_id.clone();
+        let mut clock = write_context(&operation.versions);
+        self.counter = self.counter.max(clock.get(&this_node)) + 1;
+        clock.set(this_node, self.counter);
+        operation.versions = vec![Versioned { clock, value }];
+        operation.phase = Phase::Writing;
+        operation.output = Some(output);
+        let key = operation.key;
+        let versions = operation.versions.clone();
+        for (node_id, hint) in operation.replicas.clone() {
+            self.send_put(context, operation_id, node_id, Put { key, versions: versions.clone(), hint })?;
+        }
+        Ok(())
+    }
+
+    /// Writes the reconciled siblings back to the replicas which answered with stale ones, or to the given one only.
+    fn repair(&mut self, context: &mut Context<Self>, operation_id: u64, node_id: Option<NodeId>) -> Result<()> {
+        let Some(operation) = self.operations.get(&operation_id) else { return Ok(()) };
+        let stale: Vec<Replica> = operation.replicas.iter()
+            .filter(|(replica, _)| node_id.as_ref().is_none_or(|node_id| node_id == replica))
+            .filter(|(replica, _)| operation.responses.get(replica).is_some_and(|versions| *versions != operation.versions))
+            .cloned()
+            .collect();
+        let key = operation.key;
+        let versions = operation.versions.clone();
+        for (replica, hint) in stale {
+            debug!("Repairing key '{}' on '{}'", key, replica);
+            context.metrics().increment("read_repairs");
+            self.send_put(context, operation_id, replica, Put { key, versions: versions.clone(), hint })?;
+        }
+        Ok(())
+    }
+
+    fn on_operation_timeout(&mut self, context: &mut Context<Self>, operation_id: u64) {
+        let Some(operation) = self.operations.remove(&operation_id) else { return };
+        let error = match operation.phase {
+            Phase::Reading => QuorumMessage::Error { code: TEMPORARILY_UNAVAILABLE, text: "Read quorum not reached".to_string() },
+            Phase::Writing => QuorumMessage::Error { code: TIMEOUT, text: "Write quorum not reached".to_string() },
+            Phase::Done => return,
+        };
+        context.reply(operation.address, error);
+    }
+
+    /// Sends the writes held for replicas which are not suspected anymore back to them.
+    fn hand_off(&mut self, context: &mut Context<Self>) -> Result<()> {
+        for (node_id, hinted) in &self.hints {
+            if QuorumActor::is_suspected(context, node_id) {
+                continue;
+            }
+            for (key, versions) in hinted {
+                let (node_id, key, versions) = (node_id.clone(), *key, versions.clone());
+                let request = Put { key, versions: versions.clone(), hint: None };
+                context.rpc(node_id.clone(), request, move |actor: &mut QuorumActor, _, response| {
+                    if response.is_ok() {
+                        actor.forget_hint(&node_id, key, &versions);
+                    }
+                    Ok(())
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops the handed off versions, unless newer writes were hinted meanwhile.
+    fn forget_hint(&mut self, node_id: &NodeId, key: i64, versions: &[Versioned]) {
+        let Some(hinted) = self.hints.get_mut(node_id) else { return };
+        if hinted.get(&key).is_some_and(|hinted| hinted == versions) {
+            hinted.remove(&key);
+        }
+        if hinted.is_empty() {
+            self.hints.remove(node_id);
+        }
+    }
+}
+
+impl Actor for QuorumActor {
+    type Msg = QuorumMessage;
+    type TimerKey = QuorumTimer;
+
+    fn new(context: &mut Context<Self>) -> Result<Self> {
+        let node_ids = &context.this_node().node_ids;
+        let actor = QuorumActor {
+            config: QuorumConfig::from_options(node_ids.len())?,
+            ring: Ring::new(node_ids),
+            store: HashMap::new(),
+            hints: HashMap::new(),
+            counter: 0,
+            operations: HashMap::new(),
+            next_operation_id: 0,
+        };
+        debug!("Replicating with '{:?}'", actor.config);
+        context.detect_failures(FailureDetectorConfig::default());
+        context.set_timer(HANDOFF_INTERVAL, QuorumTimer::Handoff);
+        Ok(actor)
+    }
+
+    fn on_request(&mut self, context: &mut Context<Self>, request: Message<Self::Msg>) -> Result<()> {
+        let (body, address) = request.body_and_address();
+        match body {
+            QuorumMessage::Read { key } => self.on_client_request(context, key, Kind::Read, address),
+            QuorumMessage::Write { key, value } => self.on_client_request(context, key, Kind::Write { value }, address),
+            QuorumMessage::Cas { key, from, to, create_if_not_exists } =>
+                self.on_client_request(context, key, Kind::Cas { from, to, create_if_not_exists }, address),
+            request => {
+                let response = self.on_replica_request(context, request)?;
+                context.reply(address, response);
+                Ok(())
+            }
+        }
+    }
+
+    fn on_timeout(&mut self, context: &mut Context<Self>, timer_key: Self::TimerKey) -> Result<()> {
+        match timer_key {
+            QuorumTimer::Operation(operation_id) => {
+                self.on_operation_timeout(context, operation_id);
+                Ok(())
+            }
+            QuorumTimer::Handoff => {
+                self.hand_off(context)?;
+                context.set_timer(HANDOFF_INTERVAL, QuorumTimer::Handoff);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Replicates every key with `--replicas N`, `--read-quorum R` and `--write-quorum W`.
+fn main() -> Result<()> {
+    run_actor::<QuorumActor>()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use gossip_glomers::common::driver::Transport;
+    use gossip_glomers::common::error::Result;
+    use gossip_glomers::common::message::message::{Message, MessageAddress};
+    use gossip_glomers::common::message::{MessageId, NodeId};
+    use gossip_glomers::common::simulator::{Simulator, SimulatorConfig};
+
+    use crate::QuorumActor;
+
+    const KEY: i64 = 1;
+
+    fn simulator() -> Result<Simulator<QuorumActor>> {
+        Simulator::new(SimulatorConfig {
+            node_count: 4,
+            latency: Duration::from_millis(5),
+            ..SimulatorConfig::default()
+        })
+    }
+
+    /// The replicas of the key, and the node left over.
+    fn replicas(simulator: &Simulator<QuorumActor>) -> (Vec<NodeId>, NodeId) {
+        let mut walk = simulator.node(&NodeId::from("n0")).unwrap().ring.walk(&KEY);
+        let spare = walk.pop().unwrap();
+        (walk, spare)
+    }
+
+    fn from_client(msg_id: u64, dest: &NodeId, body: serde_json::Value) -> Message<serde_json::Value> {
+        Message::new_request(MessageAddress {
+            src: NodeId::from("c1"),
+            dest: dest.clone(),
+            msg_id: Some(MessageId(msg_id)),
+            in_reply_to: None,
+        }, body)
+    }
+
+    fn cut_off(simulator: &mut Simulator<QuorumActor>, node_id: &NodeId) {
+        let others = simulator.node_ids().into_iter().filter(|other| other != node_id).collect();
+        simulator.partition(vec![vec![node_id.clone()], others]);
+    }
+
+    fn values(simulator: &Simulator<QuorumActor>, node_id: &NodeId) -> Vec<i64> {
+        let store = &simulator.node(node_id).unwrap().store;
+        store.get(&KEY).map(|versions| versions.iter().map(|versioned| versioned.value).collect()).unwrap_or_default()
+    }
+
+    #[test]
+    fn should_hand_hinted_writes_back_to_recovered_replica() -> Result<()> {
+        let mut simulator = simulator()?;
+        let (replicas, spare) = replicas(&simulator);
+        let suspected = replicas[0].clone();
+        simulator.run_until(Duration::from_secs(1))?;
+        cut_off(&mut simulator, &suspected);
+        simulator.run_until(Duration::from_secs(3))?;
+
+        simulator.send(from_client(1, &replicas[1], json!({"type": "write", "key": KEY, "value": 5})))?;
+        let reply = simulator.receive(Duration::from_secs(4))?.unwrap();
+        let hinted = simulator.node(&spare).unwrap().hints.get(&suspected).and_then(|hinted| hinted.get(&KEY)).cloned();
+        simulator.heal();
+        simulator.run_until(Duration::from_secs(8))?;
+
+        assert_eq!(reply.body()["type"], "write_ok");
+        assert_eq!(hinted.map(|versions| versions.len()), Some(1));
+        assert_eq!(values(&simulator, &suspected), vec![5]);
+        assert!(simulator.node_ids().iter().all(|node_id| simulator.node(node_id).unwrap().hints.is_empty()));
+        Ok(())
+    }
+
+    #[test]
+    fn should_repair_stale_replica_on_read() -> Result<()> {
+        let mut simulator = simulator()?;
+        let (replicas, _) = replicas(&simulator);
+        let stale = replicas[0].clone();
+        simulator.run_until(Duration::from_secs(1))?;
+
+        // Cut off for less than it takes to be suspected, so the write skips it without a hint.
+        cut_off(&mut simulator, &stale);
+        simulator.send(from_client(1, &replicas[1], json!({"type": "write", "key": KEY, "value": 5})))?;
+        let written = simulator.receive(Duration::from_millis(1100))?.unwrap();
+        simulator.heal();
+        let missed = values(&simulator, &stale);
+        simulator.send(from_client(2, &replicas[1], json!({"type": "read", "key": KEY})))?;
+        let read = simulator.receive(Duration::from_millis(1200))?.unwrap();
+        simulator.run_until(Duration::from_millis(1300))?;
+
+        assert_eq!(written.body()["type"], "write_ok");
+        assert!(missed.is_empty());
+        assert_eq!(read.body(), &json!({"type": "read_ok", "value": 5}));
+        assert_eq!(values(&simulator, &stale), vec![5]);
+        assert!(simulator.metrics(&replicas[1]).unwrap().get("read_repairs") > 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+use serde::{Deserialize, Serialize};
+
+use gossip_glomers::common::message::{MessageKind, NodeId};
+use gossip_glomers::common::rpc::Request;
+
+use crate::version::Versioned;
+
+#[derive(Serialize, Deserialize, MessageKind, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum QuorumMessage {
+    Read {
+        key: i64,
+    },
+    ReadOk {
+        value: i64,
+    },
+    Write {
+        key: i64,
+        value: i64,
+    },
+    WriteOk,
+    Cas {
+        key: i64,
+        from: i64,
+        to: i64,
+        #[serde(default)]
+        create_if_not_exists: bool,
+    },
+    CasOk,
+    Error {
+        code: u64,
+        text: String,
+    },
+    /// Asks a replica for every sibling it holds for the key, hinted ones included.
+    Get {
+        key: i64,
+    },
+    GetOk {
+        versions: Vec<Versioned>,
+    },
+    /// Merges the versions into a replica. A `hint` names the replica the versions are held for, until it recovers.
+    Put {
+        key: i64,
+        versions: Vec<Versioned>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        hint: Option<NodeId>,
+    },
+    PutOk,
+}
+
+/// The `get` a coordinator sends to a replica.
+#[derive(Serialize)]
+#[serde(tag = "type", rename = "get")]
+pub struct Get {
+    pub key: i64,
+}
+
+#[derive(Deserialize)]
+pub struct GetOk {
+    pub versions: Vec<Versioned>,
+}
+
+impl Request for Get {
+    type Response = GetOk;
+}
+
+/// The `put` a coordinator sends to a replica.
+#[derive(Serialize)]
+#[serde(tag = "type", rename = "put")]
+pub struct Put {
+    pub key: i64,
+    pub versions: Vec<Versioned>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<NodeId>,
+}
+
+impl Request for Put {
+    type Response = serde::de::IgnoredAny;
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use gossip_glomers::common::error::Result;
+    use gossip_glomers::common::message::NodeId;
+
+    use crate::message::QuorumMessage;
+    use crate::version::{VectorClock, Versioned};
+
+    #[test]
+    fn should_serialize_hinted_put() -> Result<()> {
+        let mut clock = VectorClock::default();
+        clock.set(NodeId::from("n1"), 2);
+        let message = QuorumMessage::Put { key: 1, versions: vec![Versioned { clock, value: 5 }], hint: Some(NodeId::from("n2")) };
+
+        let result = serde_json::to_value(&message)?;
+
+        assert_eq!(result, json!({"type": "put", "key": 1, "versions": [{"clock": {"n1": 2}, "value": 5}], "hint": "n2"}));
+        assert_eq!(serde_json::from_value::<QuorumMessage>(result)?, message);
+        Ok(())
+    }
+}
@@ -0,0 +1,91 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use gossip_glomers::common::message::NodeId;
+
+/// Counts, per coordinator, the writes a version has seen.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(transparent)]
+pub struct VectorClock(BTreeMap<NodeId, u64>);
+
+impl VectorClock {
+    pub fn get(&self, node_id: &NodeId) -> u64 {
+        self.0.get(node_id).copied().unwrap_or(0)
+    }
+
+    pub fn set(&mut self, node_id: NodeId, counter: u64) {
+        self.0.insert(node_id, counter);
+    }
+
+    pub fn merge(&mut self, other: &VectorClock) {
+        for (node_id, counter) in &other.0 {
+            let entry = self.0.entry(node_id.clone()).or_default();
+            *entry = (*entry).max(*counter);
+        }
+    }
+
+    /// Whether this clock has seen every write `other` has seen.
+    pub fn descends(&self, other: &VectorClock) -> bool {
+        other.0.iter().all(|(node_id, counter)| self.get(node_id) >= *counter)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Versioned {
+    pub clock: VectorClock,
+    pub value: i64,
+}
+
+/// Merges two sets of siblings, dropping every version another one descends from.
+pub fn reconcile(left: &[Versioned], right: &[Versioned]) -> Vec<Versioned> {
+    let mut versions: Vec<Versioned> = left.iter().chain(right).cloned().collect();
+    versions.sort();
+    versions.dedup();
+    versions.iter()
+        .filter(|version| !versions.iter().any(|other| other.clock != version.clock && other.clock.descends(&version.clock)))
+        .cloned()
+        .collect()
+}
+
+/// The value clients see for concurrent siblings: the greatest one, so every replica resolves them alike.
+pub fn resolve(versions: &[Versioned]) -> Option<i64> {
+    versions.iter().map(|version| version.value).max()
+}
+
+/// The clock of every sibling, so a write made with it supersedes them all.
+pub fn context(versions: &[Versioned]) -> VectorClock {
+    let mut clock = VectorClock::default();
+    for version in versions {
+        clock.merge(&version.clock);
+    }
+    clock
+}
+
+#[cfg(test)]
+mod tests {
+    use gossip_glomers::common::message::NodeId;
+
+    use crate::version::{context, reconcile, resolve, VectorClock, Versioned};
+
+    fn version(counters: &[(&str, u64)], value: i64) -> Versioned {
+        let mut clock = VectorClock::default();
+        for (node_id, counter) in counters {
+            clock.set(NodeId::from(*node_id), *counter);
+        }
+        Versioned { clock, value }
+    }
+
+    #[test]
+    fn should_keep_concurrent_siblings_only() {
+        let ancestor = version(&[("n0", 1)], 1);
+        let left = version(&[("n0", 2)], 2);
+        let right = version(&[("n0", 1), ("n1", 1)], 3);
+
+        let result = reconcile(&[ancestor, left.clone()], &[right.clone(), left.clone()]);
+
+        assert_eq!(result, vec![right.clone(), left.clone()]);
+        assert_eq!(resolve(&result), Some(3));
+        assert!(context(&result).descends(&left.clock) && context(&result).descends(&right.clock));
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::{BTreeMap, HashMap};
+
+use gossip_glomers::common::error::Result;
+use gossip_glomers::common::message::NodeId;
+use gossip_glomers::common::runner::run_actor;
+use gossip_glomers::common::twopc::{Shard, TwoPhaseCommit};
+
+use crate::message::{Function, MicroOp, TxnMessage};
+
+mod message;
+
+/// The micro-ops of a transaction run by one shard, with their position in the transaction.
+type Part = Vec<(usize, MicroOp)>;
+
+/// The registers of the keys a node owns on the ring. Reads run when the shard votes, against the registers it
+/// has locked, and writes apply once the transaction commits.
+#[derive(Default)]
+struct Registers {
+    values: HashMap<u64, u64>,
+}
+
+impl Shard for Registers {
+    type Key = u64;
+    type Txn = TxnMessage;
+    type Part = Part;
+    type Vote = Part;
+    type Output = TxnMessage;
+
+    fn split<F>(txn: &TxnMessage, owner: F) -> BTreeMap<NodeId, Part>
+        where F: Fn(&u64) -> NodeId {
+        let mut parts: BTreeMap<NodeId, Part> = BTreeMap::new();
+        if let TxnMessage::Txn { txn } = txn {
+            for (idx, micro_op) in txn.iter().enumerate() {
+                parts.entry(owner(&micro_op.1)).or_default().push((idx, micro_op.clone()));
+            }
+        }
+        parts
+    }
+
+    fn keys(part: &Part) -> Vec<u64> {
+        part.iter().map(|(_, MicroOp(_, key, _))| *key).collect()
+    }
+
+    fn prepare(&self, part: &Part) -> Part {
+        let mut written: HashMap<u64, u64> = HashMap::new();
+        part.iter().map(|(idx, micro_op)| {
+            let MicroOp(function, key, value) = micro_op.clone();
+            match (function, value) {
+                (Function::Write, Some(value)) => {
+                    written.insert(key, value);
+                    (*idx, micro_op.clone())
+                }
+                _ => (*idx, MicroOp(function, key, written.get(&key).or(self.values.get(&key)).copied())),
+            }
+        }).collect()
+    }
+
+    fn commit(&mut self, part: Part) {
+        for (_, MicroOp(function, key, value)) in part {
+            if let (Function::Write, Some(value)) = (function, value) {
+                self.values.insert(key, value);
+            }
+        }
+    }
+
+    fn output(_: TxnMessage, votes: BTreeMap<NodeId, Part>) -> TxnMessage {
+        let mut micro_ops: Part = votes.into_values().flatten().collect();
+        micro_ops.sort_by_key(|(idx, _)| *idx);
+        TxnMessage::TxnOk { txn: micro_ops.into_iter().map(|(_, micro_op)| micro_op).collect() }
+    }
+}
+
+/// Shards the registers of a `txn-rw-register` workload, committing transactions across shards with two-phase
+/// commit.
+fn main() -> Result<()> {
+    run_actor::<TwoPhaseCommit<Registers>>()
+}
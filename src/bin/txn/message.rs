@@ -0,0 +1,41 @@
+use serde::{Deserialize, Serialize};
+
+use gossip_glomers::common::message::MessageKind;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Function {
+    #[serde(rename = "r")]
+    Read,
+    #[serde(rename = "w")]
+    Write,
+}
+
+/// A read, whose value is `None` until it runs, or a write of a register.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MicroOp(pub Function, pub u64, pub Option<u64>);
+
+#[derive(Serialize, Deserialize, MessageKind, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TxnMessage {
+    Txn {
+        txn: Vec<MicroOp>,
+    },
+    TxnOk {
+        txn: Vec<MicroOp>,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use gossip_glomers::common::error::Result;
+
+    use crate::message::{Function, MicroOp, TxnMessage};
+
+    #[test]
+    fn should_deserialize_txn() -> Result<()> {
+        let result: TxnMessage = serde_json::from_str(r#"{"type":"txn","txn":[["r",1,null],["w",2,3]]}"#)?;
+
+        assert_eq!(result, TxnMessage::Txn { txn: vec![MicroOp(Function::Read, 1, None), MicroOp(Function::Write, 2, Some(3))] });
+        Ok(())
+    }
+}
@@ -1,17 +1,14 @@
-use std::time::Instant;
 use gossip_glomers::common::actor::Actor;
-use gossip_glomers::common::error::Error::UnexpectedMessage;
+use gossip_glomers::common::context::Context;
 use gossip_glomers::common::error::Result;
 use gossip_glomers::common::message::message::Message;
-use gossip_glomers::common::runner::{reply, run_actor, RunnerAction};
-use gossip_glomers::common::this_node::ThisNode;
+use gossip_glomers::common::runner::run_actor;
 
 use crate::message::GenerateMessage;
 
 mod message;
 
 struct UniqueIdActor {
-    this_node: ThisNode,
     counter: u64,
 }
 
@@ -19,25 +16,19 @@ impl Actor for UniqueIdActor {
     type Msg = GenerateMessage;
     type TimerKey = ();
 
-    fn new(this_node: ThisNode) -> Result<Self> {
+    fn new(_: &mut Context<Self>) -> Result<Self> {
         Ok(UniqueIdActor {
-            this_node,
             counter: 0,
         })
     }
 
-    fn on_request(&mut self, request: Message<Self::Msg>, _now: Instant) -> Result<Vec<RunnerAction<Self::Msg, Self::TimerKey>>> {
-        let (body, address) = request.body_and_address();
-        match body {
-            GenerateMessage::Generate => {
-                let id = format!("{}_{}", self.this_node.node_id, self.counter);
-                self.counter += 1;
-                Ok(vec![
-                    reply(address, GenerateMessage::GenerateOk { id })
-                ])
-            }
-            GenerateMessage::GenerateOk { .. } => Err(UnexpectedMessage("GenerateOk".to_string()))
+    fn on_request(&mut self, context: &mut Context<Self>, request: Message<Self::Msg>) -> Result<()> {
+        if let (GenerateMessage::Generate, address) = request.body_and_address() {
+            let id = format!("{}_{}", context.this_node().node_id, self.counter);
+            self.counter += 1;
+            context.reply(address, GenerateMessage::GenerateOk { id });
         }
+        Ok(())
     }
 }
 
@@ -1,11 +1,11 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
-#[serde(tag = "type")]
+use gossip_glomers::common::message::MessageKind;
+
+#[derive(Serialize, Deserialize, MessageKind, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum GenerateMessage {
-    #[serde(rename = "generate")]
     Generate,
-    #[serde(rename = "generate_ok")]
     GenerateOk {
         id: String
     },
@@ -1,25 +1,41 @@
 use std::fmt::Debug;
-use std::time::Instant;
 
+use log::warn;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 
+use crate::common::context::Context;
 use crate::common::message::message::Message;
-use crate::common::runner::RunnerAction;
-use crate::common::this_node::ThisNode;
+use crate::common::message::{MessageKind, NodeId};
 
 use super::error::Result;
 
 pub trait Actor
-    where Self: Sized {
-    type Msg: Debug + DeserializeOwned + Serialize;
+    where Self: Sized + 'static {
+    type Msg: Debug + DeserializeOwned + Serialize + MessageKind;
     type TimerKey: Debug;
 
-    fn new(this_node: ThisNode) -> Result<Self>;
+    fn new(context: &mut Context<Self>) -> Result<Self>;
 
-    fn on_request(&mut self, request: Message<Self::Msg>, now: Instant) -> Result<Vec<RunnerAction<Self::Msg, Self::TimerKey>>>;
+    fn on_request(&mut self, context: &mut Context<Self>, request: Message<Self::Msg>) -> Result<()>;
 
-    fn on_timeout(&mut self, _timer_key: Self::TimerKey, _now: Instant) -> Result<Vec<RunnerAction<Self::Msg, Self::TimerKey>>> {
-        Ok(vec![])
+    /// Receives the replies which do not answer an RPC sent through the context, dropping them by default.
+    fn on_reply(&mut self, _context: &mut Context<Self>, reply: Message<Self::Msg>) -> Result<()> {
+        warn!("Dropping an unexpected reply: '{:?}'", reply);
+        Ok(())
+    }
+
+    fn on_timeout(&mut self, _context: &mut Context<Self>, _timer_key: Self::TimerKey) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called when the failure detector starts suspecting a peer, see `Context::detect_failures`.
+    fn on_suspect(&mut self, _context: &mut Context<Self>, _node_id: NodeId) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called when a suspected peer is heard from again.
+    fn on_recover(&mut self, _context: &mut Context<Self>, _node_id: NodeId) -> Result<()> {
+        Ok(())
     }
 }
@@ -0,0 +1,182 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+
+use serde_json::Value;
+
+use crate::common::error::{Error, Result};
+use crate::common::history::{History, Operation};
+use crate::common::message::NodeId;
+use crate::common::simulator::NetworkStats;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Percentiles {
+    pub p0: Duration,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+    pub p100: Duration,
+}
+
+impl Percentiles {
+    fn of(mut values: Vec<Duration>) -> Option<Percentiles> {
+        if values.is_empty() {
+            return None;
+        }
+        values.sort();
+        let quantile = |q: f64| values[((values.len() - 1) as f64 * q).round() as usize];
+        Some(Percentiles {
+            p0: quantile(0.0),
+            p50: quantile(0.5),
+            p95: quantile(0.95),
+            p99: quantile(0.99),
+            p100: quantile(1.0),
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct BroadcastReport {
+    pub valid: bool,
+    /// Acknowledged values missing from the final read of some node.
+    pub lost: BTreeSet<i64>,
+    /// Read values which were never broadcast.
+    pub phantom: BTreeSet<i64>,
+    pub stable_latencies: Option<Percentiles>,
+    pub msgs_per_op: f64,
+    pub server_msgs_per_op: f64,
+}
+
+/// Checks a broadcast history whose last ok `read` on every node is the final read.
+/// A value becomes stable once every read invoked afterwards contains it; its stable latency is measured
+/// from the invocation of its `broadcast`.
+pub fn check(history: &History, stats: NetworkStats) -> Result<BroadcastReport> {
+    let operations = history.operations();
+    let mut broadcasts: BTreeMap<i64, &Operation> = BTreeMap::new();
+    let mut reads: Vec<(&Operation, BTreeSet<i64>)> = vec![];
+    for operation in &operations {
+        match operation.f.as_str() {
+            "broadcast" => {
+                broadcasts.insert(message_value(operation, &operation.value["message"])?, operation);
+            }
+            "read" if operation.is_ok() => {
+                let messages = operation.result()
+                    .and_then(|result| result["messages"].as_array())
+                    .ok_or_else(|| Error::MalformedHistory(format!("Read without messages: '{:?}'", operation)))?
+                    .iter()
+                    .map(|message| message_value(operation, message))
+                    .collect::<Result<BTreeSet<i64>>>()?;
+                reads.push((operation, messages));
+            }
+            _ => {}
+        }
+    }
+
+    let mut final_reads: BTreeMap<&NodeId, &BTreeSet<i64>> = BTreeMap::new();
+    for (operation, messages) in &reads {
+        final_reads.insert(&operation.node, messages);
+    }
+    let acknowledged: BTreeSet<i64> = broadcasts.iter()
+        .filter(|(_, operation)| operation.is_ok())
+        .map(|(message, _)| *message)
+        .collect();
+    let lost: BTreeSet<i64> = acknowledged.iter()
+        .filter(|message| final_reads.values().any(|messages| !messages.contains(message)))
+        .copied()
+        .collect();
+    let phantom: BTreeSet<i64> = reads.iter()
+        .flat_map(|(_, messages)| messages.iter())
+        .filter(|message| !broadcasts.contains_key(message))
+        .copied()
+        .collect();
+
+    let stable_latencies = acknowledged.iter()
+        .filter(|message| !lost.contains(message))
+        .map(|message| {
+            let invoke_time = broadcasts[message].invoke_time;
+            let stable_time = reads.iter()
+                .filter(|(_, messages)| !messages.contains(message))
+                .map(|(operation, _)| operation.invoke_time)
+                .max()
+                .unwrap_or(invoke_time)
+                .max(invoke_time);
+            stable_time - invoke_time
+        })
+        .collect();
+
+    let operation_count = operations.len().max(1) as f64;
+    Ok(BroadcastReport {
+        valid: lost.is_empty() && phantom.is_empty(),
+        lost,
+        phantom,
+        stable_latencies: Percentiles::of(stable_latencies),
+        msgs_per_op: stats.all as f64 / operation_count,
+        server_msgs_per_op: stats.servers as f64 / operation_count,
+    })
+}
+
+fn message_value(operation: &Operation, value: &Value) -> Result<i64> {
+    value.as_i64().ok_or_else(|| Error::MalformedHistory(format!("Expected an integer message: '{:?}'", operation)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use crate::common::check::broadcast::check;
+    use crate::common::error::Result;
+    use crate::common::history::{EventType, History};
+    use crate::common::message::NodeId;
+    use crate::common::simulator::NetworkStats;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn should_report_stable_latencies() -> Result<()> {
+        let mut history = History::new();
+        history.invoke(0, NodeId::from("n0"), "broadcast", json!({"message": 1}), ms(0));
+        history.complete(EventType::Ok, 0, NodeId::from("n0"), "broadcast", json!({}), ms(1));
+        history.invoke(1, NodeId::from("n1"), "read", json!({}), ms(50));
+        history.complete(EventType::Ok, 1, NodeId::from("n1"), "read", json!({"messages": []}), ms(51));
+        history.invoke(0, NodeId::from("n0"), "broadcast", json!({"message": 2}), ms(60));
+        history.complete(EventType::Ok, 0, NodeId::from("n0"), "broadcast", json!({}), ms(61));
+        history.invoke(1, NodeId::from("n1"), "read", json!({}), ms(100));
+        history.complete(EventType::Ok, 1, NodeId::from("n1"), "read", json!({"messages": [1, 2]}), ms(101));
+        history.invoke(0, NodeId::from("n0"), "read", json!({}), ms(110));
+        history.complete(EventType::Ok, 0, NodeId::from("n0"), "read", json!({"messages": [1, 2]}), ms(111));
+
+        let report = check(&history, NetworkStats { all: 15, clients: 10, servers: 5 })?;
+
+        assert!(report.valid);
+        let latencies = report.stable_latencies.unwrap();
+        assert_eq!(latencies.p0, ms(0));
+        assert_eq!(latencies.p100, ms(50));
+        assert_eq!(report.msgs_per_op, 3.0);
+        assert_eq!(report.server_msgs_per_op, 1.0);
+        Ok(())
+    }
+
+    #[test]
+    fn should_detect_lost_and_phantom_values() -> Result<()> {
+        let mut history = History::new();
+        history.invoke(0, NodeId::from("n0"), "broadcast", json!({"message": 1}), ms(0));
+        history.complete(EventType::Ok, 0, NodeId::from("n0"), "broadcast", json!({}), ms(1));
+        history.invoke(0, NodeId::from("n0"), "broadcast", json!({"message": 2}), ms(2));
+        history.complete(EventType::Info, 0, NodeId::from("n0"), "broadcast", json!(null), ms(3));
+        history.invoke(1, NodeId::from("n0"), "read", json!({}), ms(10));
+        history.complete(EventType::Ok, 1, NodeId::from("n0"), "read", json!({"messages": [1, 2]}), ms(11));
+        history.invoke(1, NodeId::from("n1"), "read", json!({}), ms(12));
+        history.complete(EventType::Ok, 1, NodeId::from("n1"), "read", json!({"messages": [2, 3]}), ms(13));
+
+        let report = check(&history, NetworkStats::default())?;
+
+        assert!(!report.valid);
+        assert_eq!(report.lost, BTreeSet::from([1]));
+        assert_eq!(report.phantom, BTreeSet::from([3]));
+        Ok(())
+    }
+}
@@ -0,0 +1,329 @@
+use std::collections::{BTreeMap, HashSet};
+use std::time::Duration;
+
+use serde_json::Value;
+
+use crate::common::error::{Error, Result};
+use crate::common::history::{History, Operation};
+
+#[derive(Clone, Debug, PartialEq)]
+enum RegisterOp {
+    Read(String),
+    Write(String),
+    Cas {
+        from: String,
+        to: String,
+    },
+}
+
+impl RegisterOp {
+    /// Applies the operation to a register, returning `None` when the operation is impossible in the given state.
+    /// Values are compared by their JSON representation, with `null` standing for a missing key.
+    fn step(&self, state: &str) -> Option<String> {
+        match self {
+            RegisterOp::Read(value) => (value == state).then(|| state.to_string()),
+            RegisterOp::Write(value) => Some(value.clone()),
+            RegisterOp::Cas { from, to } => (from == state).then(|| to.clone()),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Entry {
+    operation: Operation,
+    op: RegisterOp,
+    call: Duration,
+    ret: Option<Duration>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct NonLinearizableKey {
+    pub key: Value,
+    pub operations: Vec<Operation>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct LinearizabilityReport {
+    pub valid: bool,
+    pub failures: Vec<NonLinearizableKey>,
+}
+
+/// Checks a history of `read`/`write`/`cas` operations against a linearizable register per key.
+/// Failed operations are known not to have happened and are ignored. Operations without a definite outcome
+/// may take effect at any point after their invocation, or not at all.
+pub fn check(history: &History) -> Result<LinearizabilityReport> {
+    let mut keys: BTreeMap<String, (Value, Vec<Entry>)> = BTreeMap::new();
+    for operation in history.operations() {
+        if operation.is_fail() {
+            continue;
+        }
+        let key = operation.value.get("key")
+            .ok_or_else(|| Error::MalformedHistory(format!("Operation without a key: '{:?}'", operation)))?
+            .clone();
+        if let Some(op) = register_op(&operation)? {
+            let ret = if operation.is_ok() { operation.complete_time() } else { None };
+            keys.entry(key.to_string()).or_insert_with(|| (key, vec![])).1.push(Entry {
+                call: operation.invoke_time,
+                ret,
+                op,
+                operation,
+            });
+        }
+    }
+
+    let failures: Vec<NonLinearizableKey> = keys
+        .into_values()
+        .filter(|(_, entries)| !is_linearizable(entries))
+        .map(|(key, entries)| NonLinearizableKey {
+            key,
+            operations: minimize(entries).into_iter().map(|entry| entry.operation).collect(),
+        })
+        .collect();
+    Ok(LinearizabilityReport {
+        valid: failures.is_empty(),
+        failures,
+    })
+}
+
+fn register_op(operation: &Operation) -> Result<Option<RegisterOp>> {
+    let field = |value: &Value, name: &str| -> Result<String> {
+        value.get(name)
+            .map(|field| field.to_string())
+            .ok_or_else(|| Error::MalformedHistory(format!("Operation without '{}': '{:?}'", name, operation)))
+    };
+    match operation.f.as_str() {
+        "read" => match operation.result() {
+            Some(result) if operation.is_ok() => Ok(Some(RegisterOp::Read(field(result, "value")?))),
+            _ => Ok(None)
+        },
+        "write" => Ok(Some(RegisterOp::Write(field(&operation.value, "value")?))),
+        "cas" => Ok(Some(RegisterOp::Cas {
+            from: field(&operation.value, "from")?,
+            to: field(&operation.value, "to")?,
+        })),
+        f => Err(Error::MalformedHistory(format!("Unknown register operation: '{}'", f)))
+    }
+}
+
+fn is_linearizable(entries: &[Entry]) -> bool {
+    let mut search = Search {
+        entries,
+        required: entries.iter().filter(|entry| entry.ret.is_some()).count(),
+        visited: HashSet::new(),
+    };
+    let mut linearized = vec![false; entries.len()];
+    search.search(&mut linearized, 0, Value::Null.to_string())
+}
+
+/// Removes operations one by one for as long as the remaining history stays non-linearizable
+/// and every value it observes is still written by one of the remaining operations.
+fn minimize(mut entries: Vec<Entry>) -> Vec<Entry> {
+    let mut idx = 0;
+    while idx < entries.len() {
+        let mut candidate = entries.clone();
+        candidate.remove(idx);
+        if is_self_contained(&candidate) && !is_linearizable(&candidate) {
+            entries = candidate;
+        } else {
+            idx += 1;
+        }
+    }
+    entries
+}
+
+fn is_self_contained(entries: &[Entry]) -> bool {
+    let written: HashSet<&String> = entries
+        .iter()
+        .filter_map(|entry| match &entry.op {
+            RegisterOp::Write(value) | RegisterOp::Cas { to: value, .. } => Some(value),
+            RegisterOp::Read(_) => None
+        })
+        .collect();
+    let null = Value::Null.to_string();
+    entries
+        .iter()
+        .filter_map(|entry| match &entry.op {
+            RegisterOp::Read(value) | RegisterOp::Cas { from: value, .. } => Some(value),
+            RegisterOp::Write(_) => None
+        })
+        .all(|value| *value == null || written.contains(value))
+}
+
+/// Wing & Gong search: repeatedly linearize an operation that no pending operation must precede,
+/// memoizing the (linearized set, register state) configurations already known to be dead ends.
+struct Search<'a> {
+    entries: &'a [Entry],
+    required: usize,
+    visited: HashSet<(Vec<bool>, String)>,
+}
+
+impl Search<'_> {
+    fn search(&mut self, linearized: &mut Vec<bool>, completed: usize, state: String) -> bool {
+        if completed == self.required {
+            return true;
+        }
+        if !self.visited.insert((linearized.clone(), state.clone())) {
+            return false;
+        }
+        for idx in 0..self.entries.len() {
+            if linearized[idx] || !self.is_minimal(linearized, idx) {
+                continue;
+            }
+            let entry = &self.entries[idx];
+            if let Some(next_state) = entry.op.step(&state) {
+                linearized[idx] = true;
+                let completed = completed + usize::from(entry.ret.is_some());
+                if self.search(linearized, completed, next_state) {
+                    return true;
+                }
+                linearized[idx] = false;
+            }
+        }
+        false
+    }
+
+    fn is_minimal(&self, linearized: &[bool], idx: usize) -> bool {
+        let call = self.entries[idx].call;
+        self.entries
+            .iter()
+            .enumerate()
+            .all(|(other, entry)| other == idx || linearized[other] || entry.ret.is_none_or(|ret| ret > call))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::{json, Value};
+
+    use crate::common::actor::Actor;
+    use crate::common::check::linearizability::check;
+    use crate::common::context::Context;
+    use crate::common::driver::{ClientDriver, DriverConfig};
+    use crate::common::error::Result;
+    use crate::common::history::{EventType, History};
+    use crate::common::message::message::Message;
+    use crate::common::message::NodeId;
+    use crate::common::simulator::{Simulator, SimulatorConfig};
+    use crate::common::workload::lin_kv::LinKvWorkload;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    fn operation(history: &mut History, process: u64, f: &str, value: Value, result: Option<(EventType, Value)>, invoke: u64, complete: u64) {
+        history.invoke(process, NodeId::from("n0"), f, value, ms(invoke));
+        if let Some((event_type, result)) = result {
+            history.complete(event_type, process, NodeId::from("n0"), f, result, ms(complete));
+        }
+    }
+
+    #[test]
+    fn should_accept_concurrent_operations_in_any_order() -> Result<()> {
+        let mut history = History::new();
+        history.invoke(0, NodeId::from("n0"), "write", json!({"key": 1, "value": 1}), ms(0));
+        history.invoke(1, NodeId::from("n0"), "read", json!({"key": 1}), ms(1));
+        history.invoke(2, NodeId::from("n0"), "cas", json!({"key": 1, "from": 1, "to": 2}), ms(2));
+        history.complete(EventType::Ok, 1, NodeId::from("n0"), "read", json!({"value": 2}), ms(3));
+        history.complete(EventType::Ok, 0, NodeId::from("n0"), "write", json!({}), ms(4));
+        history.complete(EventType::Ok, 2, NodeId::from("n0"), "cas", json!({}), ms(5));
+
+        let report = check(&history)?;
+
+        assert!(report.valid);
+        Ok(())
+    }
+
+    #[test]
+    fn should_allow_indeterminate_operations_to_take_effect() -> Result<()> {
+        let mut history = History::new();
+        operation(&mut history, 0, "write", json!({"key": 1, "value": 1}), Some((EventType::Ok, json!({}))), 0, 1);
+        operation(&mut history, 1, "write", json!({"key": 1, "value": 2}), Some((EventType::Info, Value::Null)), 2, 3);
+        operation(&mut history, 2, "write", json!({"key": 1, "value": 3}), Some((EventType::Fail, json!({"code": 22}))), 4, 5);
+        operation(&mut history, 3, "read", json!({"key": 1}), Some((EventType::Ok, json!({"value": 2}))), 6, 7);
+        operation(&mut history, 4, "read", json!({"key": 1}), Some((EventType::Ok, json!({"value": 2}))), 8, 9);
+
+        let report = check(&history)?;
+
+        assert!(report.valid);
+        Ok(())
+    }
+
+    #[test]
+    fn should_report_minimal_non_linearizable_subhistory() -> Result<()> {
+        let mut history = History::new();
+        operation(&mut history, 0, "write", json!({"key": 1, "value": 1}), Some((EventType::Ok, json!({}))), 0, 1);
+        operation(&mut history, 1, "write", json!({"key": 2, "value": 5}), Some((EventType::Ok, json!({}))), 2, 3);
+        operation(&mut history, 0, "write", json!({"key": 1, "value": 2}), Some((EventType::Ok, json!({}))), 4, 5);
+        operation(&mut history, 1, "read", json!({"key": 2}), Some((EventType::Ok, json!({"value": 5}))), 6, 7);
+        operation(&mut history, 0, "read", json!({"key": 1}), Some((EventType::Ok, json!({"value": 1}))), 8, 9);
+        operation(&mut history, 1, "read", json!({"key": 1}), Some((EventType::Ok, json!({"value": 2}))), 10, 11);
+
+        let report = check(&history)?;
+
+        assert!(!report.valid);
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(report.failures[0].key, json!(1));
+        let operations: Vec<(&str, &Value)> = report.failures[0].operations.iter().map(|operation| (operation.f.as_str(), &operation.value)).collect();
+        assert_eq!(operations, vec![
+            ("write", &json!({"key": 1, "value": 1})),
+            ("write", &json!({"key": 1, "value": 2})),
+            ("read", &json!({"key": 1})),
+        ]);
+        Ok(())
+    }
+
+    struct KvActor {
+        values: std::collections::HashMap<String, Value>,
+    }
+
+    impl Actor for KvActor {
+        type Msg = Value;
+        type TimerKey = ();
+
+        fn new(_: &mut Context<Self>) -> Result<Self> {
+            Ok(KvActor { values: Default::default() })
+        }
+
+        fn on_request(&mut self, context: &mut Context<Self>, request: Message<Self::Msg>) -> Result<()> {
+            let (body, address) = request.body_and_address();
+            let key = body["key"].to_string();
+            let response = match (body["type"].as_str(), self.values.get(&key)) {
+                (Some("read"), Some(value)) => json!({"type": "read_ok", "value": value}),
+                (Some("write"), _) => {
+                    self.values.insert(key, body["value"].clone());
+                    json!({"type": "write_ok"})
+                }
+                (Some("cas"), Some(value)) if *value == body["from"] => {
+                    self.values.insert(key, body["to"].clone());
+                    json!({"type": "cas_ok"})
+                }
+                (Some("cas"), Some(_)) => json!({"type": "error", "code": 22}),
+                _ => json!({"type": "error", "code": 20}),
+            };
+            context.reply(address, response);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn should_accept_history_of_single_node_kv() -> Result<()> {
+        let mut simulator = Simulator::<KvActor>::new(SimulatorConfig {
+            latency: ms(5),
+            jitter: ms(10),
+            ..SimulatorConfig::default()
+        })?;
+        let history = ClientDriver::new(DriverConfig {
+            concurrency: 5,
+            rate: 100.0,
+            time_limit: Duration::from_secs(2),
+            ..DriverConfig::default()
+        }, LinKvWorkload).run(&mut simulator)?;
+
+        let report = check(&history)?;
+
+        assert!(report.valid);
+        Ok(())
+    }
+}
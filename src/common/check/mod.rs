@@ -0,0 +1,3 @@
+pub mod linearizability;
+pub mod txn;
+pub mod broadcast;
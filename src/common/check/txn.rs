@@ -0,0 +1,541 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+
+use serde_json::Value;
+
+use crate::common::error::{Error, Result};
+use crate::common::history::{History, Operation};
+
+static EMPTY_LIST: Vec<Value> = Vec::new();
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Dependency {
+    WriteWrite,
+    WriteRead,
+    ReadWrite,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Anomaly {
+    G0,
+    G1a,
+    G1b,
+    G1c,
+    GSingle,
+    G2,
+    /// Committed transactions which read the same version of a key and all overwrote it.
+    LostUpdate,
+    IncompatibleOrder,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConsistencyModel {
+    ReadUncommitted,
+    ReadCommitted,
+    SnapshotIsolation,
+    Serializable,
+}
+
+impl ConsistencyModel {
+    const ALL: [ConsistencyModel; 4] = [
+        ConsistencyModel::ReadUncommitted,
+        ConsistencyModel::ReadCommitted,
+        ConsistencyModel::SnapshotIsolation,
+        ConsistencyModel::Serializable,
+    ];
+
+    pub fn proscribed(&self) -> &'static [Anomaly] {
+        match self {
+            ConsistencyModel::ReadUncommitted => &[Anomaly::G0, Anomaly::IncompatibleOrder],
+            ConsistencyModel::ReadCommitted => &[Anomaly::G0, Anomaly::IncompatibleOrder, Anomaly::G1a, Anomaly::G1b, Anomaly::G1c],
+            ConsistencyModel::SnapshotIsolation => &[Anomaly::G0, Anomaly::IncompatibleOrder, Anomaly::G1a, Anomaly::G1b, Anomaly::G1c, Anomaly::GSingle, Anomaly::LostUpdate],
+            ConsistencyModel::Serializable => &[Anomaly::G0, Anomaly::IncompatibleOrder, Anomaly::G1a, Anomaly::G1b, Anomaly::G1c, Anomaly::GSingle, Anomaly::LostUpdate, Anomaly::G2],
+        }
+    }
+}
+
+/// A single anomaly. For dependency cycles, `transactions[i]` depends on `transactions[i + 1]` via `dependencies[i]`,
+/// wrapping around to the first transaction.
+#[derive(Debug, PartialEq)]
+pub struct AnomalyCase {
+    pub anomaly: Anomaly,
+    pub transactions: Vec<Operation>,
+    pub dependencies: Vec<Dependency>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct TxnReport {
+    pub anomalies: Vec<AnomalyCase>,
+    pub satisfied: Vec<ConsistencyModel>,
+}
+
+impl TxnReport {
+    pub fn satisfies(&self, model: ConsistencyModel) -> bool {
+        self.satisfied.contains(&model)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Status {
+    Committed,
+    Aborted,
+    Indeterminate,
+}
+
+struct MicroOp {
+    f: String,
+    key: String,
+    value: Value,
+}
+
+struct Txn {
+    operation: Operation,
+    status: Status,
+    micro_ops: Vec<MicroOp>,
+}
+
+impl Txn {
+    /// Reads which observe the state before the transaction's own writes to the key.
+    fn external_reads(&self) -> Vec<&MicroOp> {
+        let mut written: BTreeSet<&str> = BTreeSet::new();
+        let mut reads = vec![];
+        for micro_op in &self.micro_ops {
+            if micro_op.f == "r" {
+                if !written.contains(micro_op.key.as_str()) {
+                    reads.push(micro_op);
+                }
+            } else {
+                written.insert(&micro_op.key);
+            }
+        }
+        reads
+    }
+
+    /// Whether the transaction writes the key with `f`, which follows any external read of the key.
+    fn writes(&self, key: &str, f: &str) -> bool {
+        self.micro_ops.iter().any(|micro_op| micro_op.f == f && micro_op.key == key)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Write {
+    txn: usize,
+    is_final: bool,
+}
+
+#[derive(Default)]
+struct Analysis {
+    graph: BTreeMap<usize, BTreeMap<usize, BTreeSet<Dependency>>>,
+    cases: Vec<(Anomaly, Vec<usize>, Vec<Dependency>)>,
+}
+
+impl Analysis {
+    fn add_edge(&mut self, from: usize, to: usize, dependency: Dependency) {
+        if from != to {
+            self.graph.entry(from).or_default().entry(to).or_default().insert(dependency);
+        }
+    }
+}
+
+/// Checks a `txn-rw-register` history. The version order of a key is inferred from transactions
+/// which read the key before overwriting it, so writes are expected to be unique per key.
+pub fn check_rw_register(history: &History) -> Result<TxnReport> {
+    let txns = transactions(history)?;
+    let writes = index_writes(&txns, "w");
+    let mut analysis = Analysis::default();
+
+    let mut successors: HashMap<(&str, String), Vec<usize>> = HashMap::new();
+    for (idx, txn) in txns.iter().enumerate().filter(|(_, txn)| txn.status == Status::Committed) {
+        for read in txn.external_reads() {
+            observe_write(&mut analysis, &writes, idx, &read.key, &read.value);
+            if txn.writes(&read.key, "w") {
+                if let Some(writer) = writes.get(&(read.key.clone(), read.value.to_string())) {
+                    analysis.add_edge(writer.txn, idx, Dependency::WriteWrite);
+                }
+                successors.entry((&read.key, read.value.to_string())).or_default().push(idx);
+            }
+        }
+    }
+    for (idx, txn) in txns.iter().enumerate().filter(|(_, txn)| txn.status == Status::Committed) {
+        for read in txn.external_reads() {
+            for successor in successors.get(&(read.key.as_str(), read.value.to_string())).into_iter().flatten() {
+                analysis.add_edge(idx, *successor, Dependency::ReadWrite);
+            }
+        }
+    }
+    lost_updates(&mut analysis, successors.into_values());
+    Ok(report(&txns, analysis))
+}
+
+/// Checks a `txn-list-append` history. The version order of a key is the longest list read from it,
+/// and every other read of the key has to be a prefix of that list.
+pub fn check_list_append(history: &History) -> Result<TxnReport> {
+    let txns = transactions(history)?;
+    let appends = index_writes(&txns, "append");
+    let mut analysis = Analysis::default();
+
+    let mut orders: BTreeMap<&str, (usize, &Vec<Value>)> = BTreeMap::new();
+    for (idx, txn) in txns.iter().enumerate().filter(|(_, txn)| txn.status == Status::Committed) {
+        for micro_op in txn.micro_ops.iter().filter(|micro_op| micro_op.f == "r") {
+            let list = match &micro_op.value {
+                Value::Null => &EMPTY_LIST,
+                value => value.as_array().ok_or_else(|| Error::MalformedHistory(format!("Expected a list read: '{:?}'", txn.operation)))?,
+            };
+            let (longest_idx, longest) = orders.entry(&micro_op.key).or_insert((idx, list));
+            let (shorter, longer) = if list.len() > longest.len() { (*longest, list) } else { (list, *longest) };
+            if longer[..shorter.len()] != shorter[..] {
+                analysis.cases.push((Anomaly::IncompatibleOrder, vec![*longest_idx, idx], vec![]));
+            } else if list.len() > longest.len() {
+                *longest_idx = idx;
+                *longest = list;
+            }
+        }
+    }
+
+    for (key, (_, order)) in &orders {
+        for pair in order.windows(2) {
+            let writers = (appends.get(&(key.to_string(), pair[0].to_string())), appends.get(&(key.to_string(), pair[1].to_string())));
+            if let (Some(first), Some(second)) = writers {
+                analysis.add_edge(first.txn, second.txn, Dependency::WriteWrite);
+            }
+        }
+    }
+
+    let mut successors: HashMap<(&str, usize), Vec<usize>> = HashMap::new();
+    for (idx, txn) in txns.iter().enumerate().filter(|(_, txn)| txn.status == Status::Committed) {
+        for read in txn.external_reads() {
+            let list = read.value.as_array().map(Vec::as_slice).unwrap_or_default();
+            if txn.writes(&read.key, "append") {
+                successors.entry((&read.key, list.len())).or_default().push(idx);
+            }
+            for element in list {
+                if let Some(writer) = appends.get(&(read.key.clone(), element.to_string())) {
+                    if txns[writer.txn].status == Status::Aborted {
+                        analysis.cases.push((Anomaly::G1a, vec![writer.txn, idx], vec![]));
+                    }
+                }
+            }
+            observe_write(&mut analysis, &appends, idx, &read.key, list.last().unwrap_or(&Value::Null));
+            let next = orders.get(read.key.as_str()).and_then(|(_, order)| order.get(list.len()));
+            if let Some(writer) = next.and_then(|next| appends.get(&(read.key.clone(), next.to_string()))) {
+                analysis.add_edge(idx, writer.txn, Dependency::ReadWrite);
+            }
+        }
+    }
+    lost_updates(&mut analysis, successors.into_values());
+    Ok(report(&txns, analysis))
+}
+
+/// Records a lost update for every version more than one committed transaction read before overwriting it. Only
+/// shows up as a cycle of two read-write dependencies otherwise, which is G2 and allowed under snapshot isolation.
+fn lost_updates<I>(analysis: &mut Analysis, successors: I)
+    where I: Iterator<Item=Vec<usize>> {
+    for successors in successors.filter(|successors| successors.len() > 1) {
+        analysis.cases.push((Anomaly::LostUpdate, successors, vec![]));
+    }
+}
+
+fn transactions(history: &History) -> Result<Vec<Txn>> {
+    history.operations()
+        .into_iter()
+        .filter(|operation| operation.f == "txn")
+        .map(|operation| {
+            let status = if operation.is_ok() {
+                Status::Committed
+            } else if operation.is_fail() {
+                Status::Aborted
+            } else {
+                Status::Indeterminate
+            };
+            let source = if status == Status::Committed { operation.result() } else { Some(&operation.value) };
+            let micro_ops = source
+                .and_then(|value| value.get("txn"))
+                .and_then(Value::as_array)
+                .ok_or_else(|| Error::MalformedHistory(format!("Transaction without micro-operations: '{:?}'", operation)))?
+                .iter()
+                .map(|micro_op| match micro_op.as_array().map(Vec::as_slice) {
+                    Some([f, key, value]) => Ok(MicroOp {
+                        f: f.as_str().unwrap_or_default().to_string(),
+                        key: key.to_string(),
+                        value: value.clone(),
+                    }),
+                    _ => Err(Error::MalformedHistory(format!("Malformed micro-operation: '{:?}'", micro_op)))
+                })
+                .collect::<Result<Vec<MicroOp>>>()?;
+            Ok(Txn {
+                operation,
+                status,
+                micro_ops,
+            })
+        })
+        .collect()
+}
+
+fn index_writes(txns: &[Txn], f: &str) -> HashMap<(String, String), Write> {
+    let mut writes = HashMap::new();
+    for (idx, txn) in txns.iter().enumerate() {
+        for (position, micro_op) in txn.micro_ops.iter().enumerate().filter(|(_, micro_op)| micro_op.f == f) {
+            let is_final = !txn.micro_ops[position + 1..].iter().any(|later| later.f == f && later.key == micro_op.key);
+            writes.insert((micro_op.key.clone(), micro_op.value.to_string()), Write { txn: idx, is_final });
+        }
+    }
+    writes
+}
+
+/// Records the write-read dependency of a committed read, along with aborted and intermediate reads.
+fn observe_write(analysis: &mut Analysis, writes: &HashMap<(String, String), Write>, reader: usize, key: &str, value: &Value) {
+    if let Some(writer) = writes.get(&(key.to_string(), value.to_string())).filter(|writer| writer.txn != reader) {
+        if !writer.is_final {
+            analysis.cases.push((Anomaly::G1b, vec![writer.txn, reader], vec![]));
+        }
+        analysis.add_edge(writer.txn, reader, Dependency::WriteRead);
+    }
+}
+
+fn report(txns: &[Txn], mut analysis: Analysis) -> TxnReport {
+    analysis.cases.retain(|(anomaly, transactions, _)| *anomaly != Anomaly::G1b || txns[transactions[0]].status != Status::Aborted);
+    for (from, to) in aborted_reads(txns, &analysis) {
+        analysis.cases.push((Anomaly::G1a, vec![from, to], vec![]));
+    }
+    analysis.graph.retain(|from, _| txns[*from].status != Status::Aborted);
+    for edges in analysis.graph.values_mut() {
+        edges.retain(|to, _| txns[*to].status != Status::Aborted);
+    }
+    analysis.cases.extend(find_cycles(&analysis.graph));
+
+    let mut seen = BTreeSet::new();
+    let cases: Vec<(Anomaly, Vec<usize>, Vec<Dependency>)> = analysis.cases
+        .into_iter()
+        .filter(|(anomaly, transactions, _)| {
+            let mut members = transactions.clone();
+            members.sort();
+            seen.insert((*anomaly, members))
+        })
+        .collect();
+    let found: BTreeSet<Anomaly> = cases.iter().map(|(anomaly, _, _)| *anomaly).collect();
+    TxnReport {
+        anomalies: cases
+            .into_iter()
+            .map(|(anomaly, transactions, dependencies)| AnomalyCase {
+                anomaly,
+                transactions: transactions.into_iter().map(|idx| txns[idx].operation.clone()).collect(),
+                dependencies,
+            })
+            .collect(),
+        satisfied: ConsistencyModel::ALL
+            .into_iter()
+            .filter(|model| model.proscribed().iter().all(|anomaly| !found.contains(anomaly)))
+            .collect(),
+    }
+}
+
+fn aborted_reads(txns: &[Txn], analysis: &Analysis) -> Vec<(usize, usize)> {
+    analysis.graph
+        .iter()
+        .filter(|(from, _)| txns[**from].status == Status::Aborted)
+        .flat_map(|(from, edges)| edges
+            .iter()
+            .filter(|(_, dependencies)| dependencies.contains(&Dependency::WriteRead))
+            .map(|(to, _)| (*from, *to)))
+        .collect()
+}
+
+/// Classifies dependency cycles: a cycle of write-write edges is G0; of write-write and write-read edges is G1c;
+/// a cycle with a single read-write edge is G-single, and with several is G2.
+fn find_cycles(graph: &BTreeMap<usize, BTreeMap<usize, BTreeSet<Dependency>>>) -> Vec<(Anomaly, Vec<usize>, Vec<Dependency>)> {
+    let mut cycles = vec![];
+    let edges: Vec<(usize, usize, Dependency)> = graph
+        .iter()
+        .flat_map(|(from, edges)| edges
+            .iter()
+            .flat_map(move |(to, dependencies)| dependencies.iter().map(move |dependency| (*from, *to, *dependency))))
+        .collect();
+    let without_anti_dependencies = [Dependency::WriteWrite, Dependency::WriteRead];
+    for (from, to, dependency) in edges {
+        let cycle = match dependency {
+            Dependency::WriteWrite => path(graph, to, from, &[Dependency::WriteWrite])
+                .map(|path| (Anomaly::G0, path))
+                .or_else(|| path(graph, to, from, &without_anti_dependencies).map(|path| (Anomaly::G1c, path))),
+            Dependency::WriteRead => path(graph, to, from, &without_anti_dependencies).map(|path| (Anomaly::G1c, path)),
+            Dependency::ReadWrite => path(graph, to, from, &without_anti_dependencies)
+                .map(|path| (Anomaly::GSingle, path))
+                .or_else(|| path(graph, to, from, &[Dependency::WriteWrite, Dependency::WriteRead, Dependency::ReadWrite])
+                    .map(|path| (Anomaly::G2, path))),
+        };
+        if let Some((anomaly, path)) = cycle {
+            let mut transactions = vec![from];
+            let mut dependencies = vec![dependency];
+            for (txn, dependency) in path {
+                transactions.push(txn);
+                dependencies.push(dependency);
+            }
+            cycles.push((anomaly, transactions, dependencies));
+        }
+    }
+    cycles
+}
+
+/// Finds the shortest path using only the allowed dependencies, as a list of (transaction, outgoing dependency) steps
+/// ending with the step into `to`.
+fn path(graph: &BTreeMap<usize, BTreeMap<usize, BTreeSet<Dependency>>>, from: usize, to: usize, allowed: &[Dependency]) -> Option<Vec<(usize, Dependency)>> {
+    let mut parents: HashMap<usize, (usize, Dependency)> = HashMap::new();
+    let mut queue = VecDeque::from([from]);
+    while let Some(current) = queue.pop_front() {
+        for (next, dependencies) in graph.get(&current).into_iter().flatten() {
+            let Some(dependency) = dependencies.iter().find(|dependency| allowed.contains(dependency)) else {
+                continue;
+            };
+            if *next == from || parents.contains_key(next) {
+                continue;
+            }
+            parents.insert(*next, (current, *dependency));
+            if *next == to {
+                let mut steps = vec![];
+                let mut node = to;
+                while node != from {
+                    let (parent, dependency) = parents[&node];
+                    steps.push((parent, dependency));
+                    node = parent;
+                }
+                steps.reverse();
+                return Some(steps);
+            }
+            queue.push_back(*next);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::{json, Value};
+
+    use crate::common::check::txn::{Anomaly, check_list_append, check_rw_register, ConsistencyModel, Dependency};
+    use crate::common::error::Result;
+    use crate::common::history::{EventType, History};
+    use crate::common::message::NodeId;
+
+    fn txn(history: &mut History, process: u64, event_type: EventType, txn: Value) {
+        let time = Duration::from_millis(history.len() as u64);
+        history.invoke(process, NodeId::from("n0"), "txn", json!({"txn": txn}), time);
+        history.complete(event_type, process, NodeId::from("n0"), "txn", json!({"txn": txn}), time);
+    }
+
+    #[test]
+    fn should_accept_serializable_history() -> Result<()> {
+        let mut history = History::new();
+        txn(&mut history, 0, EventType::Ok, json!([["r", 1, null], ["w", 1, 1]]));
+        txn(&mut history, 1, EventType::Ok, json!([["r", 1, 1], ["w", 1, 2], ["w", 2, 3]]));
+        txn(&mut history, 0, EventType::Ok, json!([["r", 1, 2], ["r", 2, 3]]));
+
+        let report = check_rw_register(&history)?;
+
+        assert_eq!(report.anomalies, vec![]);
+        assert!(report.satisfies(ConsistencyModel::Serializable));
+        Ok(())
+    }
+
+    #[test]
+    fn should_detect_aborted_read() -> Result<()> {
+        let mut history = History::new();
+        txn(&mut history, 0, EventType::Fail, json!([["w", 1, 1]]));
+        txn(&mut history, 1, EventType::Ok, json!([["r", 1, 1]]));
+
+        let report = check_rw_register(&history)?;
+
+        assert_eq!(report.anomalies.len(), 1);
+        assert_eq!(report.anomalies[0].anomaly, Anomaly::G1a);
+        assert_eq!(report.satisfied, vec![ConsistencyModel::ReadUncommitted]);
+        Ok(())
+    }
+
+    #[test]
+    fn should_detect_intermediate_read() -> Result<()> {
+        let mut history = History::new();
+        txn(&mut history, 0, EventType::Ok, json!([["append", 1, 1], ["append", 1, 2]]));
+        txn(&mut history, 1, EventType::Ok, json!([["r", 1, [1]]]));
+        txn(&mut history, 1, EventType::Ok, json!([["r", 1, [1, 2]]]));
+
+        let report = check_list_append(&history)?;
+
+        assert!(report.anomalies.iter().any(|case| case.anomaly == Anomaly::G1b));
+        assert!(!report.satisfies(ConsistencyModel::ReadCommitted));
+        Ok(())
+    }
+
+    #[test]
+    fn should_detect_read_skew() -> Result<()> {
+        let mut history = History::new();
+        txn(&mut history, 0, EventType::Ok, json!([["r", 1, []], ["r", 2, [1]]]));
+        txn(&mut history, 1, EventType::Ok, json!([["append", 1, 1], ["append", 2, 1]]));
+        txn(&mut history, 2, EventType::Ok, json!([["r", 1, [1]]]));
+
+        let report = check_list_append(&history)?;
+
+        assert_eq!(report.anomalies.len(), 1);
+        assert_eq!(report.anomalies[0].anomaly, Anomaly::GSingle);
+        assert_eq!(report.anomalies[0].dependencies, vec![Dependency::ReadWrite, Dependency::WriteRead]);
+        assert_eq!(report.satisfied, vec![ConsistencyModel::ReadUncommitted, ConsistencyModel::ReadCommitted]);
+        Ok(())
+    }
+
+    #[test]
+    fn should_detect_write_skew() -> Result<()> {
+        let mut history = History::new();
+        txn(&mut history, 0, EventType::Ok, json!([["r", 1, null], ["r", 2, null], ["w", 1, 1]]));
+        txn(&mut history, 1, EventType::Ok, json!([["r", 1, null], ["r", 2, null], ["w", 2, 2]]));
+
+        let report = check_rw_register(&history)?;
+
+        assert_eq!(report.anomalies.len(), 1);
+        assert_eq!(report.anomalies[0].anomaly, Anomaly::G2);
+        assert_eq!(report.anomalies[0].dependencies, vec![Dependency::ReadWrite, Dependency::ReadWrite]);
+        assert!(report.satisfies(ConsistencyModel::SnapshotIsolation));
+        assert!(!report.satisfies(ConsistencyModel::Serializable));
+        Ok(())
+    }
+
+    #[test]
+    fn should_detect_lost_update() -> Result<()> {
+        let mut history = History::new();
+        txn(&mut history, 0, EventType::Ok, json!([["r", 1, null], ["w", 1, 1]]));
+        txn(&mut history, 1, EventType::Ok, json!([["r", 1, null], ["w", 1, 2]]));
+
+        let report = check_rw_register(&history)?;
+
+        assert!(report.anomalies.iter().any(|case| case.anomaly == Anomaly::LostUpdate && case.transactions.len() == 2));
+        assert!(!report.satisfies(ConsistencyModel::SnapshotIsolation));
+        assert!(report.satisfies(ConsistencyModel::ReadCommitted));
+        Ok(())
+    }
+
+    #[test]
+    fn should_read_missing_list_as_empty() -> Result<()> {
+        let mut history = History::new();
+        txn(&mut history, 0, EventType::Ok, json!([["r", 1, null], ["append", 1, 1]]));
+        txn(&mut history, 1, EventType::Ok, json!([["r", 1, [1]], ["append", 1, 2]]));
+        txn(&mut history, 0, EventType::Ok, json!([["r", 1, [1, 2]]]));
+
+        let report = check_list_append(&history)?;
+
+        assert_eq!(report.anomalies, vec![]);
+        assert!(report.satisfies(ConsistencyModel::Serializable));
+        Ok(())
+    }
+
+    #[test]
+    fn should_detect_incompatible_orders() -> Result<()> {
+        let mut history = History::new();
+        txn(&mut history, 0, EventType::Ok, json!([["append", 1, 1]]));
+        txn(&mut history, 1, EventType::Ok, json!([["append", 1, 2]]));
+        txn(&mut history, 2, EventType::Ok, json!([["r", 1, [1, 2]]]));
+        txn(&mut history, 3, EventType::Ok, json!([["r", 1, [2, 1]]]));
+
+        let report = check_list_append(&history)?;
+
+        assert_eq!(report.anomalies[0].anomaly, Anomaly::IncompatibleOrder);
+        assert_eq!(report.satisfied, vec![]);
+        Ok(())
+    }
+}
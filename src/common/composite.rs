@@ -0,0 +1,324 @@
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::time::Instant;
+
+use log::warn;
+use rand::Rng;
+use serde_json::Value;
+
+use crate::common::actor::Actor;
+use crate::common::context::{Action, ActorCell, Context, TimerId};
+use crate::common::error::Error::UnexpectedMessage;
+use crate::common::error::Result;
+use crate::common::message::message::{Message, MessageAddress};
+use crate::common::message::MessageKind;
+use crate::common::runner::RunnerAction;
+use crate::common::this_node::ThisNode;
+
+type Built = (Box<dyn Component>, Vec<Action>);
+
+/// An actor hosted in a `Composite`, with its messages converted from JSON.
+pub trait Component {
+    /// Whether the component accepts requests of the given `type`. Untyped components accept every request.
+    fn handles(&self, type_name: &str) -> bool;
+
+    /// Whether the component sent the RPC a reply answers, and still waits for it.
+    fn awaits(&self, reply: &MessageAddress) -> bool;
+
+    fn on_message(&mut self, message: Message<Value>, now: Instant) -> Result<Vec<Action>>;
+
+    fn on_timeout(&mut self, timer_id: TimerId, now: Instant) -> Result<Vec<Action>>;
+}
+
+impl<A> Component for ActorCell<A>
+    where A: Actor {
+    fn handles(&self, type_name: &str) -> bool {
+        let type_names = A::Msg::type_names();
+        type_names.is_empty() || type_names.contains(&type_name)
+    }
+
+    fn awaits(&self, reply: &MessageAddress) -> bool {
+        self.is_awaiting(reply)
+    }
+
+    fn on_message(&mut self, message: Message<Value>, now: Instant) -> Result<Vec<Action>> {
+        self.on_json_message(message, now)
+    }
+
+    fn on_timeout(&mut self, timer_id: TimerId, now: Instant) -> Result<Vec<Action>> {
+        ActorCell::on_timeout(self, timer_id, now)
+    }
+}
+
+/// A tuple of actors making up a `Composite`.
+pub trait Components {
+    fn build(this_node: &ThisNode, seed: u64, now: Instant) -> Result<Vec<Built>>;
+}
+
+fn build<A>(this_node: &ThisNode, seed: u64, now: Instant) -> Result<Built>
+    where A: Actor {
+    let (cell, actions) = ActorCell::<A>::new(this_node.clone(), seed, now)?;
+    Ok((Box::new(cell), actions))
+}
+
+macro_rules! components {
+    ($($component:ident),+) => {
+        impl<$($component),+> Components for ($($component,)+)
+            where $($component: Actor),+ {
+            fn build(this_node: &ThisNode, seed: u64, now: Instant) -> Result<Vec<Built>> {
+                let mut seed = seed;
+                Ok(vec![$({
+                    seed = seed.wrapping_add(1);
+                    build::<$component>(this_node, seed, now)?
+                }),+])
+            }
+        }
+    };
+}
+
+components!(A);
+components!(A, B);
+components!(A, B, C);
+components!(A, B, C, D);
+
+/// Hosts several actors in one node. Replies to an RPC go to the component waiting for them, other messages to
+/// the first component handling their `type`, and messages a component sends to its own node are delivered
+/// locally.
+pub struct Composite<L> {
+    components: Vec<Box<dyn Component>>,
+    list: PhantomData<L>,
+}
+
+impl<L> Composite<L>
+    where L: Components + 'static {
+    fn deliver(&mut self, context: &mut Context<Self>, message: Message<Value>) -> Result<()> {
+        let mut inbox = VecDeque::from([message]);
+        while let Some(message) = inbox.pop_front() {
+            let address = message.address();
+            let awaiting = self.components.iter().position(|component| component.awaits(&address));
+            let handling = || self.components.iter().position(|component| component.handles(message.body().type_name()));
+            let idx = match awaiting.or_else(handling) {
+                Some(idx) => idx,
+                // Such as a reply arriving after its RPC timed out.
+                None if message.body().is_reply() => {
+                    warn!("Dropping a reply no component expects: '{:?}'", message);
+                    continue;
+                }
+                None => return Err(UnexpectedMessage(message.body().type_name().to_string())),
+            };
+            let actions = self.components[idx].on_message(message, context.now())?;
+            inbox.extend(self.execute(context, idx, actions));
+        }
+        Ok(())
+    }
+
+    /// Executes the effects of a component, returning the messages addressed to this node.
+    fn execute(&mut self, context: &mut Context<Self>, idx: usize, actions: Vec<Action>) -> Vec<Message<Value>> {
+        let mut local = vec![];
+        for action in actions {
+            match action {
+                RunnerAction::SendMessage(message) => {
+                    if *message.dest() == context.this_node().node_id {
+                        local.push(message);
+                    } else {
+                        context.send_message(message);
+                    }
+                }
+                RunnerAction::SetTimer { delay, timer_key } => {
+                    context.set_timer(delay, (idx, timer_key));
+                }
+            }
+        }
+        local
+    }
+}
+
+impl<L> Actor for Composite<L>
+    where L: Components + 'static {
+    type Msg = Value;
+    type TimerKey = (usize, TimerId);
+
+    fn new(context: &mut Context<Self>) -> Result<Self> {
+        let seed = context.rng().gen();
+        let mut composite = Composite {
+            components: vec![],
+            list: PhantomData,
+        };
+        let mut local = vec![];
+        for (idx, (component, actions)) in L::build(context.this_node(), seed, context.now())?.into_iter().enumerate() {
+            composite.components.push(component);
+            local.push((idx, actions));
+        }
+        for (idx, actions) in local {
+            for message in composite.execute(context, idx, actions) {
+                composite.deliver(context, message)?;
+            }
+        }
+        Ok(composite)
+    }
+
+    fn on_request(&mut self, context: &mut Context<Self>, request: Message<Self::Msg>) -> Result<()> {
+        self.deliver(context, request)
+    }
+
+    fn on_reply(&mut self, context: &mut Context<Self>, reply: Message<Self::Msg>) -> Result<()> {
+        self.deliver(context, reply)
+    }
+
+    fn on_timeout(&mut self, context: &mut Context<Self>, (idx, timer_id): Self::TimerKey) -> Result<()> {
+        let actions = self.components[idx].on_timeout(timer_id, context.now())?;
+        for message in self.execute(context, idx, actions) {
+            self.deliver(context, message)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+
+    use crate::common::actor::Actor;
+    use crate::common::composite::Composite;
+    use crate::common::context::{ActorCell, Context};
+    use crate::common::error::Error::UnexpectedMessage;
+    use crate::common::error::Result;
+    use crate::common::message::message::{Message, MessageAddress};
+    use crate::common::message::{MessageId, MessageKind, NodeId};
+    use crate::common::rpc::Request;
+    use crate::common::runner::RunnerAction;
+    use crate::common::this_node::ThisNode;
+
+    #[derive(Serialize, Deserialize, MessageKind, Debug)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum CounterMessage {
+        Add {
+            delta: u64,
+        },
+        AddOk,
+        Read,
+        ReadOk {
+            value: u64,
+        },
+    }
+
+    struct Counter {
+        value: u64,
+    }
+
+    impl Actor for Counter {
+        type Msg = CounterMessage;
+        type TimerKey = ();
+
+        fn new(_: &mut Context<Self>) -> Result<Self> {
+            Ok(Counter { value: 0 })
+        }
+
+        fn on_request(&mut self, context: &mut Context<Self>, request: Message<Self::Msg>) -> Result<()> {
+            let (body, address) = request.body_and_address();
+            match body {
+                CounterMessage::Add { delta } => {
+                    self.value += delta;
+                    context.reply(address, CounterMessage::AddOk);
+                }
+                CounterMessage::Read => context.reply(address, CounterMessage::ReadOk { value: self.value }),
+                reply => return Err(UnexpectedMessage(reply.type_name().to_string())),
+            }
+            Ok(())
+        }
+    }
+
+    #[derive(Serialize, Deserialize, MessageKind, Debug)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum ReportMessage {
+        Report,
+        ReportOk {
+            value: u64,
+        },
+    }
+
+    #[derive(Serialize)]
+    #[serde(tag = "type", rename = "read")]
+    struct Read {}
+
+    #[derive(Deserialize)]
+    struct ReadOk {
+        value: u64,
+    }
+
+    impl Request for Read {
+        type Response = ReadOk;
+    }
+
+    /// Answers `report` by reading the counter hosted in the same node.
+    struct Reporter;
+
+    impl Actor for Reporter {
+        type Msg = ReportMessage;
+        type TimerKey = ();
+
+        fn new(_: &mut Context<Self>) -> Result<Self> {
+            Ok(Reporter)
+        }
+
+        fn on_request(&mut self, context: &mut Context<Self>, request: Message<Self::Msg>) -> Result<()> {
+            let (_, address) = request.body_and_address();
+            let this_node = context.this_node().node_id.clone();
+            context.rpc(this_node, Read {}, move |_: &mut Reporter, context, response| {
+                context.reply(address, ReportMessage::ReportOk { value: response.map_or(0, |read| read.value) });
+                Ok(())
+            })
+        }
+    }
+
+    fn request(msg_id: u64, body: serde_json::Value) -> Message<serde_json::Value> {
+        Message::new_request(MessageAddress {
+            src: NodeId::from("c1"),
+            dest: NodeId::from("n0"),
+            msg_id: Some(MessageId(msg_id)),
+            in_reply_to: None,
+        }, body)
+    }
+
+    #[test]
+    fn should_route_by_type_and_deliver_local_messages() -> Result<()> {
+        let this_node = ThisNode::new(NodeId::from("n0"), vec![NodeId::from("n0")]);
+        let (mut cell, _) = ActorCell::<Composite<(Counter, Reporter)>>::new(this_node, 0, Instant::now())?;
+
+        cell.on_message(request(1, json!({"type": "add", "delta": 3})), Instant::now())?;
+        let actions = cell.on_message(request(2, json!({"type": "report"})), Instant::now())?;
+
+        let [RunnerAction::SetTimer { .. }, RunnerAction::SendMessage(message)] = actions.as_slice() else { panic!("Expected a deadline and a message") };
+        assert_eq!(message.dest(), &NodeId::from("c1"));
+        assert_eq!(message.address().in_reply_to, Some(MessageId(2)));
+        assert_eq!(message.body(), &json!({"type": "report_ok", "value": 3}));
+        Ok(())
+    }
+
+    #[test]
+    fn should_reject_unknown_types() -> Result<()> {
+        let this_node = ThisNode::new(NodeId::from("n0"), vec![NodeId::from("n0")]);
+        let (mut cell, _) = ActorCell::<Composite<(Counter, Reporter)>>::new(this_node, 0, Instant::now())?;
+
+        assert!(cell.on_message(request(1, json!({"type": "topology"})), Instant::now()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn should_drop_replies_no_component_expects() -> Result<()> {
+        let this_node = ThisNode::new(NodeId::from("n0"), vec![NodeId::from("n0"), NodeId::from("n1")]);
+        let (mut cell, _) = ActorCell::<Composite<(Counter, Reporter)>>::new(this_node, 0, Instant::now())?;
+        let late = Message::new_request(MessageAddress {
+            src: NodeId::from("n1"),
+            dest: NodeId::from("n0"),
+            msg_id: None,
+            in_reply_to: Some(MessageId(7)),
+        }, json!({"type": "error", "code": 0}));
+
+        assert!(cell.on_message(late, Instant::now())?.is_empty());
+        Ok(())
+    }
+}
@@ -76,7 +76,7 @@ fn spawn_stdout_channel() -> Sender<Vec<u8>> {
         for bytes in receiver {
             let mut out_lock = io::stdout().lock();
             out_lock.write_all(bytes.as_slice()).unwrap();
-            out_lock.write_all(&[b'\n']).unwrap();
+            out_lock.write_all(b"\n").unwrap();
         }
     });
     sender
@@ -0,0 +1,541 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use log::warn;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde_json::{json, Value};
+
+use crate::common::actor::Actor;
+use crate::common::error::Error::UnexpectedError;
+use crate::common::error::{Error, Result};
+use crate::common::failure_detector::{FailureDetector, FailureDetectorConfig};
+use crate::common::message::message::{Message, MessageAddress};
+use crate::common::message::{MessageId, MessageKind, NodeId};
+use crate::common::rpc::{decode, Request, Rpc, RpcError, RpcResult, RPC_TIMEOUT};
+use crate::common::runner::{set_timer, RunnerAction};
+use crate::common::this_node::ThisNode;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TimerId(u64);
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Metrics {
+    counters: BTreeMap<String, u64>,
+}
+
+impl Metrics {
+    pub fn increment(&mut self, name: &str) {
+        self.add(name, 1)
+    }
+
+    pub fn add(&mut self, name: &str, value: u64) {
+        *self.counters.entry(name.to_string()).or_default() += value;
+    }
+
+    pub fn get(&self, name: &str) -> u64 {
+        self.counters.get(name).copied().unwrap_or_default()
+    }
+
+    pub fn counters(&self) -> &BTreeMap<String, u64> {
+        &self.counters
+    }
+}
+
+struct Timers<K> {
+    next_timer_id: u64,
+    active: HashMap<TimerId, K>,
+}
+
+impl<K> Default for Timers<K> {
+    fn default() -> Self {
+        Timers {
+            next_timer_id: 0,
+            active: HashMap::new(),
+        }
+    }
+}
+
+/// The timers of a cell: those set by the actor, the deadlines of RPCs and the periodic check of the failure
+/// detector.
+enum CellTimer<K> {
+    Actor(K),
+    Rpc(MessageId),
+    FailureDetector,
+}
+
+/// The `type` of the messages sent by the failure detector, which are consumed by the receiving cell.
+const HEARTBEAT: &str = "heartbeat";
+
+/// An effect of a cell, with its messages already serialized.
+pub type Action = RunnerAction<Value, TimerId>;
+
+/// The effects available to an actor while it handles an event. They are buffered and executed by the runtime
+/// once the handler returns.
+pub struct Context<'a, A>
+    where A: Actor {
+    now: Instant,
+    this_node: &'a ThisNode,
+    rng: &'a mut StdRng,
+    metrics: &'a mut Metrics,
+    rpc: &'a mut Rpc<A>,
+    timers: &'a mut Timers<CellTimer<A::TimerKey>>,
+    failure_detector: &'a mut Option<FailureDetector>,
+    actions: Vec<Action>,
+    /// The first message which failed to serialize, returned once the handler is done.
+    error: Option<Error>,
+}
+
+impl<A> Context<'_, A>
+    where A: Actor {
+    pub fn now(&self) -> Instant {
+        self.now
+    }
+
+    pub fn this_node(&self) -> &ThisNode {
+        self.this_node
+    }
+
+    pub fn rng(&mut self) -> &mut StdRng {
+        self.rng
+    }
+
+    pub fn metrics(&mut self) -> &mut Metrics {
+        self.metrics
+    }
+
+    pub fn send(&mut self, dest: NodeId, body: A::Msg) {
+        let address = self.this_node.new_destination_address(dest);
+        self.push_message(Message::new_request(address, body));
+    }
+
+    /// Sends a message as is, keeping its address.
+    pub fn send_message(&mut self, message: Message<A::Msg>) {
+        self.push_message(message);
+    }
+
+    pub fn reply(&mut self, request_address: MessageAddress, body: A::Msg) {
+        self.push_message(Message::new_reply(request_address.to_reply_address(), body));
+    }
+
+    fn push_message(&mut self, message: Message<A::Msg>) {
+        match message.try_map(serde_json::to_value) {
+            Ok(message) => self.actions.push(RunnerAction::SendMessage(message)),
+            Err(error) => {
+                self.error.get_or_insert(error.into());
+            }
+        }
+    }
+
+    /// Sends a request whose reply is decoded into `R::Response` and passed to the callback instead of `on_reply`.
+    /// The callback gets `RpcError::timeout` if no reply arrives within `RPC_TIMEOUT`.
+    pub fn rpc<R, F>(&mut self, dest: NodeId, request: R, callback: F) -> Result<()>
+        where R: Request,
+              F: FnOnce(&mut A, &mut Context<A>, RpcResult<R::Response>) -> Result<()> + 'static {
+        self.rpc_with_timeout(dest, request, RPC_TIMEOUT, callback)
+    }
+
+    pub fn rpc_with_timeout<R, F>(&mut self, dest: NodeId, request: R, timeout: Duration, callback: F) -> Result<()>
+        where R: Request,
+              F: FnOnce(&mut A, &mut Context<A>, RpcResult<R::Response>) -> Result<()> + 'static {
+        let address = self.this_node.new_destination_address(dest);
+        let msg_id = address.msg_id.clone()
+            .ok_or_else(|| UnexpectedError(format!("Request without msg_id: '{:?}'", address)))?;
+        let deadline = self.set_cell_timer(timeout, CellTimer::Rpc(msg_id));
+        let message = self.rpc.call(address, request, deadline, callback)?;
+        self.actions.push(RunnerAction::SendMessage(message));
+        Ok(())
+    }
+
+    /// Passes a reply to the callback of the RPC it answers, if any, returning whether there was one.
+    fn complete_rpc(&mut self, actor: &mut A, address: &MessageAddress, reply: Value) -> Result<bool> {
+        let Some((callback, deadline)) = self.rpc.take(address) else { return Ok(false) };
+        self.timers.active.remove(&deadline);
+        callback(actor, self, decode(reply)?)?;
+        Ok(true)
+    }
+
+    pub fn set_timer(&mut self, delay: Duration, timer_key: A::TimerKey) -> TimerId {
+        self.set_cell_timer(delay, CellTimer::Actor(timer_key))
+    }
+
+    /// Cancels a timer which has not fired yet, returning its key.
+    pub fn cancel_timer(&mut self, timer_id: TimerId) -> Option<A::TimerKey> {
+        match self.timers.active.remove(&timer_id) {
+            Some(CellTimer::Actor(timer_key)) => Some(timer_key),
+            _ => None,
+        }
+    }
+
+    /// Starts watching every other node, calling `on_suspect` and `on_recover` as they go silent and come back.
+    pub fn detect_failures(&mut self, config: FailureDetectorConfig) {
+        let peers: Vec<NodeId> = self.this_node.node_ids.iter().filter(|node_id| **node_id != self.this_node.node_id).cloned().collect();
+        let interval = config.interval;
+        *self.failure_detector = Some(FailureDetector::new(config, &peers, self.now));
+        self.set_cell_timer(interval, CellTimer::FailureDetector);
+    }
+
+    pub fn failure_detector(&self) -> Option<&FailureDetector> {
+        self.failure_detector.as_ref()
+    }
+
+    fn set_cell_timer(&mut self, delay: Duration, timer: CellTimer<A::TimerKey>) -> TimerId {
+        let timer_id = TimerId(self.timers.next_timer_id);
+        self.timers.next_timer_id += 1;
+        self.timers.active.insert(timer_id, timer);
+        self.actions.push(set_timer(delay, timer_id));
+        timer_id
+    }
+
+    /// Counts a heartbeat from a peer, calling `on_recover` if the peer was suspected. Without heartbeats, every
+    /// message counts as one.
+    fn observe(&mut self, actor: &mut A, src: &NodeId, heartbeat: bool) -> Result<()> {
+        let recovered = match self.failure_detector.as_mut() {
+            Some(failure_detector) if heartbeat || !failure_detector.config().heartbeats => failure_detector.heartbeat(src, self.now),
+            _ => false,
+        };
+        if recovered {
+            actor.on_recover(self, src.clone())?;
+        }
+        Ok(())
+    }
+
+    /// Suspects the peers which went silent and sends the next round of heartbeats.
+    fn check_failures(&mut self, actor: &mut A) -> Result<()> {
+        let Some(failure_detector) = self.failure_detector.as_mut() else { return Ok(()) };
+        let suspected = failure_detector.check(self.now);
+        let config = failure_detector.config().clone();
+        let mut peers: Vec<NodeId> = failure_detector.peers().cloned().collect();
+        peers.sort();
+        for node_id in suspected {
+            actor.on_suspect(self, node_id)?;
+        }
+        if config.heartbeats {
+            for node_id in peers {
+                let address = self.this_node.new_destination_address(node_id);
+                self.actions.push(RunnerAction::SendMessage(Message::new_request(address, json!({"type": HEARTBEAT}))));
+            }
+        }
+        self.set_cell_timer(config.interval, CellTimer::FailureDetector);
+        Ok(())
+    }
+}
+
+/// Hosts an actor together with its node, RNG, metrics, pending RPCs and timers, turning every event
+/// into the list of effects the runtime has to execute.
+pub struct ActorCell<A>
+    where A: Actor {
+    actor: A,
+    this_node: ThisNode,
+    rng: StdRng,
+    metrics: Metrics,
+    rpc: Rpc<A>,
+    timers: Timers<CellTimer<A::TimerKey>>,
+    failure_detector: Option<FailureDetector>,
+}
+
+impl<A> ActorCell<A>
+    where A: Actor {
+    pub fn new(this_node: ThisNode, seed: u64, now: Instant) -> Result<(ActorCell<A>, Vec<Action>)> {
+        let mut rng = StdRng::seed_from_u64(node_seed(seed, &this_node.node_id));
+        let mut metrics = Metrics::default();
+        let mut rpc = Rpc::new();
+        let mut timers = Timers::default();
+        let mut failure_detector = None;
+        let mut context = Context {
+            now,
+            this_node: &this_node,
+            rng: &mut rng,
+            metrics: &mut metrics,
+            rpc: &mut rpc,
+            timers: &mut timers,
+            failure_detector: &mut failure_detector,
+            actions: vec![],
+            error: None,
+        };
+        let actor = A::new(&mut context)?;
+        if let Some(error) = context.error {
+            return Err(error);
+        }
+        let actions = context.actions;
+        Ok((ActorCell { actor, this_node, rng, metrics, rpc, timers, failure_detector }, actions))
+    }
+
+    pub fn actor(&self) -> &A {
+        &self.actor
+    }
+
+    pub fn this_node(&self) -> &ThisNode {
+        &self.this_node
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub fn failure_detector(&self) -> Option<&FailureDetector> {
+        self.failure_detector.as_ref()
+    }
+
+    /// Whether a reply answers an RPC of the actor which has not timed out yet.
+    pub fn is_awaiting(&self, reply: &MessageAddress) -> bool {
+        self.rpc.is_pending(reply)
+    }
+
+    /// Passes replies to outstanding RPCs to their callbacks, other replies to `on_reply` and requests to `on_request`.
+    pub fn on_message(&mut self, message: Message<A::Msg>, now: Instant) -> Result<Vec<Action>> {
+        self.with_context(now, |actor, context| {
+            context.observe(actor, message.src(), false)?;
+            if !message.body().is_reply() {
+                return actor.on_request(context, message);
+            }
+            if context.complete_rpc(actor, &message.address(), serde_json::to_value(message.body())?)? {
+                return Ok(());
+            }
+            actor.on_reply(context, message)
+        })
+    }
+
+    /// Like `on_message`, for a message not decoded yet. Replies to outstanding RPCs are only decoded into the
+    /// response of their request, so `A::Msg` does not need to model them, and other replies it does not model,
+    /// such as those arriving after their RPC timed out, are dropped.
+    /// Heartbeats of the failure detector are consumed here.
+    pub fn on_json_message(&mut self, message: Message<Value>, now: Instant) -> Result<Vec<Action>> {
+        if message.body().type_name() == HEARTBEAT {
+            return self.with_context(now, |actor, context| context.observe(actor, message.src(), true));
+        }
+        if message.body().is_reply() && self.rpc.is_pending(&message.address()) {
+            let src = message.src().clone();
+            let (body, address) = message.body_and_address();
+            return self.with_context(now, |actor, context| {
+                context.observe(actor, &src, false)?;
+                context.complete_rpc(actor, &address, body)?;
+                Ok(())
+            });
+        }
+        let is_reply = message.body().is_reply();
+        match serde_json::from_value(serde_json::to_value(&message)?) {
+            Ok(message) => self.on_message(message, now),
+            Err(error) if is_reply => {
+                warn!("Dropping an unexpected reply: '{:?}': {}", message, error);
+                Ok(vec![])
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Fires a timer, unless it has been cancelled.
+    pub fn on_timeout(&mut self, timer_id: TimerId, now: Instant) -> Result<Vec<Action>> {
+        self.with_context(now, |actor, context| {
+            match context.timers.active.remove(&timer_id) {
+                Some(CellTimer::Actor(timer_key)) => actor.on_timeout(context, timer_key),
+                Some(CellTimer::Rpc(msg_id)) => match context.rpc.expire(&msg_id) {
+                    Some(callback) => callback(actor, context, Err(RpcError::timeout())),
+                    None => Ok(()),
+                },
+                Some(CellTimer::FailureDetector) => context.check_failures(actor),
+                None => Ok(()),
+            }
+        })
+    }
+
+    fn with_context<F>(&mut self, now: Instant, handler: F) -> Result<Vec<Action>>
+        where F: FnOnce(&mut A, &mut Context<A>) -> Result<()> {
+        let ActorCell { actor, this_node, rng, metrics, rpc, timers, failure_detector } = self;
+        let mut context = Context {
+            now,
+            this_node,
+            rng,
+            metrics,
+            rpc,
+            timers,
+            failure_detector,
+            actions: vec![],
+            error: None,
+        };
+        handler(actor, &mut context)?;
+        match context.error {
+            Some(error) => Err(error),
+            None => Ok(context.actions),
+        }
+    }
+}
+
+/// Mixes the node id into the seed, so that nodes sharing a seed still draw different numbers.
+fn node_seed(seed: u64, node_id: &NodeId) -> u64 {
+    node_id.to_string().bytes().fold(seed ^ 0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use rand::Rng;
+    use serde::{Deserialize, Serialize};
+    use serde_json::{json, Value};
+
+    use crate::common::actor::Actor;
+    use crate::common::context::{ActorCell, Context};
+    use crate::common::error::Result;
+    use crate::common::failure_detector::FailureDetectorConfig;
+    use crate::common::message::message::{Message, MessageAddress};
+    use crate::common::message::{MessageId, MessageKind, NodeId};
+    use crate::common::runner::RunnerAction;
+    use crate::common::this_node::ThisNode;
+
+    struct PingActor;
+
+    impl Actor for PingActor {
+        type Msg = Value;
+        type TimerKey = &'static str;
+
+        fn new(context: &mut Context<Self>) -> Result<Self> {
+            context.set_timer(Duration::from_millis(10), "cancelled");
+            context.set_timer(Duration::from_millis(20), "ping");
+            Ok(PingActor)
+        }
+
+        fn on_request(&mut self, context: &mut Context<Self>, request: Message<Self::Msg>) -> Result<()> {
+            let (_, address) = request.body_and_address();
+            context.metrics().increment("requests");
+            context.reply(address, json!({"type": "ping_ok"}));
+            Ok(())
+        }
+
+        fn on_timeout(&mut self, context: &mut Context<Self>, timer_key: Self::TimerKey) -> Result<()> {
+            context.send(NodeId::from("n1"), json!({"type": timer_key}));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn should_buffer_effects_and_skip_cancelled_timers() -> Result<()> {
+        let now = Instant::now();
+        let (mut cell, actions) = ActorCell::<PingActor>::new(ThisNode::new(NodeId::from("n0"), vec![NodeId::from("n0"), NodeId::from("n1")]), 0, now)?;
+        let timer_ids: Vec<_> = actions.into_iter().map(|action| match action {
+            RunnerAction::SetTimer { timer_key, .. } => timer_key,
+            RunnerAction::SendMessage(message) => panic!("Unexpected message: '{:?}'", message),
+        }).collect();
+        cell.with_context(now, |_, context| {
+            context.cancel_timer(timer_ids[0]);
+            Ok(())
+        })?;
+
+        assert!(cell.on_timeout(timer_ids[0], now)?.is_empty());
+        let actions = cell.on_timeout(timer_ids[1], now)?;
+        let [RunnerAction::SendMessage(message)] = actions.as_slice() else { panic!("Expected a single message") };
+        assert_eq!(message.body(), &json!({"type": "ping"}));
+
+        let request = Message::new_request(MessageAddress {
+            src: NodeId::from("c1"),
+            dest: NodeId::from("n0"),
+            msg_id: Some(MessageId(1)),
+            in_reply_to: None,
+        }, json!({"type": "ping"}));
+        let actions = cell.on_message(request, now)?;
+        let [RunnerAction::SendMessage(message)] = actions.as_slice() else { panic!("Expected a single message") };
+        assert_eq!(message.address().in_reply_to, Some(MessageId(1)));
+        assert_eq!(cell.metrics().get("requests"), 1);
+        Ok(())
+    }
+
+    #[derive(Serialize, Deserialize, MessageKind, Debug)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum WatchMessage {
+        Ping,
+    }
+
+    #[derive(Default)]
+    struct WatchActor {
+        events: Vec<String>,
+    }
+
+    impl Actor for WatchActor {
+        type Msg = WatchMessage;
+        type TimerKey = ();
+
+        fn new(context: &mut Context<Self>) -> Result<Self> {
+            context.detect_failures(FailureDetectorConfig::default());
+            Ok(WatchActor::default())
+        }
+
+        fn on_request(&mut self, _: &mut Context<Self>, _: Message<Self::Msg>) -> Result<()> {
+            Ok(())
+        }
+
+        fn on_suspect(&mut self, _: &mut Context<Self>, node_id: NodeId) -> Result<()> {
+            self.events.push(format!("suspect {}", node_id));
+            Ok(())
+        }
+
+        fn on_recover(&mut self, _: &mut Context<Self>, node_id: NodeId) -> Result<()> {
+            self.events.push(format!("recover {}", node_id));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn should_suspect_silent_peers_and_only_count_heartbeats() -> Result<()> {
+        let now = Instant::now();
+        let this_node = ThisNode::new(NodeId::from("n0"), vec![NodeId::from("n0"), NodeId::from("n1")]);
+        let (mut cell, actions) = ActorCell::<WatchActor>::new(this_node, 0, now)?;
+        let [RunnerAction::SetTimer { timer_key, .. }] = actions.as_slice() else { panic!("Expected a single timer") };
+
+        let actions = cell.on_timeout(*timer_key, now + Duration::from_secs(2))?;
+        let [RunnerAction::SendMessage(heartbeat), RunnerAction::SetTimer { .. }] = actions.as_slice() else { panic!("Expected a heartbeat and a timer") };
+        assert_eq!(heartbeat.dest(), &NodeId::from("n1"));
+        assert_eq!(heartbeat.body(), &json!({"type": "heartbeat"}));
+        assert!(cell.failure_detector().is_some_and(|failure_detector| failure_detector.is_suspected(&NodeId::from("n1"))));
+
+        let from_n1 = |msg_id, body| Message::new_request(MessageAddress {
+            src: NodeId::from("n1"),
+            dest: NodeId::from("n0"),
+            msg_id: Some(MessageId(msg_id)),
+            in_reply_to: None,
+        }, body);
+        cell.on_json_message(from_n1(1, json!({"type": "ping"})), now + Duration::from_secs(2))?;
+        assert_eq!(cell.actor().events, vec!["suspect n1"]);
+        assert!(cell.on_json_message(from_n1(2, json!({"type": "heartbeat"})), now + Duration::from_secs(2))?.is_empty());
+        assert_eq!(cell.actor().events, vec!["suspect n1", "recover n1"]);
+        Ok(())
+    }
+
+    #[test]
+    fn should_drop_replies_the_actor_does_not_model() -> Result<()> {
+        let now = Instant::now();
+        let this_node = ThisNode::new(NodeId::from("n0"), vec![NodeId::from("n0"), NodeId::from("n1")]);
+        let (mut cell, _) = ActorCell::<WatchActor>::new(this_node, 0, now)?;
+        let from_n1 = |in_reply_to, body| Message::new_request(MessageAddress {
+            src: NodeId::from("n1"),
+            dest: NodeId::from("n0"),
+            msg_id: None,
+            in_reply_to,
+        }, body);
+
+        let late = cell.on_json_message(from_n1(Some(MessageId(7)), json!({"type": "error", "code": 11})), now)?;
+        let unknown = cell.on_json_message(from_n1(None, json!({"type": "pong"})), now);
+
+        assert!(late.is_empty());
+        assert!(unknown.is_err());
+        Ok(())
+    }
+
+    fn draw(node_id: &str, seed: u64) -> Result<u64> {
+        let this_node = ThisNode::new(NodeId::from(node_id), vec![NodeId::from(node_id)]);
+        let (mut cell, _) = ActorCell::<PingActor>::new(this_node, seed, Instant::now())?;
+        let mut value = 0;
+        cell.with_context(Instant::now(), |_, context| {
+            value = context.rng().gen();
+            Ok(())
+        })?;
+        Ok(value)
+    }
+
+    #[test]
+    fn should_seed_rng_from_seed_and_node_id() -> Result<()> {
+        assert_eq!(draw("n0", 1)?, draw("n0", 1)?);
+        assert_ne!(draw("n0", 1)?, draw("n1", 1)?);
+        assert_ne!(draw("n0", 1)?, draw("n0", 2)?);
+        Ok(())
+    }
+}
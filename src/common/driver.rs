@@ -0,0 +1,311 @@
+use std::ops::Add;
+use std::time::Duration;
+
+use log::debug;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use serde_json::Value;
+
+use crate::common::error::Error::Config;
+use crate::common::error::Result;
+use crate::common::history::{EventType, History};
+use crate::common::message::{MessageId, NodeId};
+use crate::common::message::message::{Message, MessageAddress};
+use crate::common::workload::{payload, request_type, Workload};
+
+/// Maelstrom error codes after which the operation may still have taken effect.
+const INDEFINITE_ERROR_CODES: [u64; 2] = [0, 13];
+
+pub trait Transport {
+    fn node_ids(&self) -> Vec<NodeId>;
+
+    fn now(&self) -> Duration;
+
+    fn send(&mut self, message: Message<Value>) -> Result<()>;
+
+    /// Waits for a message addressed to a client until the given time.
+    fn receive(&mut self, until: Duration) -> Result<Option<Message<Value>>>;
+}
+
+#[derive(Clone, Debug)]
+pub struct DriverConfig {
+    pub concurrency: usize,
+    pub rate: f64,
+    pub time_limit: Duration,
+    pub timeout: Duration,
+    pub seed: u64,
+}
+
+impl DriverConfig {
+    fn validate(&self, node_ids: &[NodeId]) -> Result<()> {
+        if self.concurrency == 0 || !(self.rate.is_finite() && self.rate > 0.0) {
+            return Err(Config(format!("Expected a positive concurrency and rate: '{:?}'", self)));
+        }
+        if node_ids.is_empty() {
+            return Err(Config("No nodes to send requests to".to_string()));
+        }
+        Ok(())
+    }
+}
+
+impl Default for DriverConfig {
+    fn default() -> Self {
+        DriverConfig {
+            concurrency: 2,
+            rate: 10.0,
+            time_limit: Duration::from_secs(10),
+            timeout: Duration::from_secs(5),
+            seed: 0,
+        }
+    }
+}
+
+struct PendingRequest {
+    msg_id: MessageId,
+    request: Value,
+    deadline: Duration,
+}
+
+struct Client {
+    client_id: NodeId,
+    process: u64,
+    node_id: NodeId,
+    next_msg_id: MessageId,
+    pending: Option<PendingRequest>,
+}
+
+/// Issues workload requests from synthetic clients `c1`, `c2`... and records an invoke/ok/fail/info history. One more
+/// client, past the `concurrency` ones, sends the setup and finish requests.
+pub struct ClientDriver<W> {
+    config: DriverConfig,
+    workload: W,
+    rng: StdRng,
+    clients: Vec<Client>,
+    history: History,
+}
+
+impl<W> ClientDriver<W>
+    where W: Workload {
+    pub fn new(config: DriverConfig, workload: W) -> ClientDriver<W> {
+        ClientDriver {
+            rng: StdRng::seed_from_u64(config.seed),
+            config,
+            workload,
+            clients: vec![],
+            history: History::new(),
+        }
+    }
+
+    pub fn run<T>(mut self, transport: &mut T) -> Result<History>
+        where T: Transport {
+        let node_ids = transport.node_ids();
+        self.config.validate(&node_ids)?;
+        self.clients = (0..=self.config.concurrency)
+            .map(|idx| Client {
+                client_id: NodeId::Client(format!("c{}", idx + 1)),
+                process: idx as u64,
+                node_id: node_ids[idx % node_ids.len()].clone(),
+                next_msg_id: MessageId(1),
+                pending: None,
+            })
+            .collect();
+
+        let setup = self.workload.setup(&node_ids);
+        self.perform_all(transport, setup)?;
+
+        let interval = Duration::from_secs_f64(1.0 / self.config.rate);
+        let time_limit = transport.now().add(self.config.time_limit);
+        let mut next_invoke = transport.now();
+        while transport.now() < time_limit {
+            let now = transport.now();
+            self.expire(now);
+            if now >= next_invoke {
+                if let Some(idx) = self.idle_client() {
+                    let request = self.workload.generate(&mut self.rng);
+                    self.invoke(transport, idx, request)?;
+                    next_invoke = next_invoke.add(interval);
+                }
+            }
+            let next_invoke_or_limit = if self.idle_client_exists() { next_invoke.min(time_limit) } else { time_limit };
+            let until = self.next_deadline().map_or(next_invoke_or_limit, |deadline| deadline.min(next_invoke_or_limit));
+            self.receive(transport, until.max(transport.now()))?;
+        }
+
+        self.drain(transport)?;
+        let finish = self.workload.finish(&node_ids);
+        self.perform_all(transport, finish)?;
+        Ok(self.history)
+    }
+
+    /// Sends requests to the given nodes one by one, waiting for each one to complete.
+    fn perform_all<T>(&mut self, transport: &mut T, requests: Vec<(NodeId, Value)>) -> Result<()>
+        where T: Transport {
+        let idx = self.config.concurrency;
+        for (node_id, request) in requests {
+            self.clients[idx].node_id = node_id;
+            self.invoke(transport, idx, request)?;
+            self.drain(transport)?;
+        }
+        Ok(())
+    }
+
+    fn drain<T>(&mut self, transport: &mut T) -> Result<()>
+        where T: Transport {
+        while let Some(deadline) = self.next_deadline() {
+            self.receive(transport, deadline)?;
+            self.expire(transport.now());
+        }
+        Ok(())
+    }
+
+    fn invoke<T>(&mut self, transport: &mut T, idx: usize, request: Value) -> Result<()>
+        where T: Transport {
+        let now = transport.now();
+        let client = &mut self.clients[idx];
+        let msg_id = client.next_msg_id.clone();
+        client.next_msg_id = msg_id.inc();
+        self.history.invoke(client.process, client.node_id.clone(), request_type(&request), payload(&request), now);
+        let message = Message::new_request(MessageAddress {
+            src: client.client_id.clone(),
+            dest: client.node_id.clone(),
+            msg_id: Some(msg_id.clone()),
+            in_reply_to: None,
+        }, request.clone());
+        client.pending = Some(PendingRequest {
+            msg_id,
+            request,
+            deadline: now.add(self.config.timeout),
+        });
+        transport.send(message)
+    }
+
+    fn receive<T>(&mut self, transport: &mut T, until: Duration) -> Result<()>
+        where T: Transport {
+        if let Some(message) = transport.receive(until)? {
+            let now = transport.now();
+            let (response, address) = message.body_and_address();
+            let client = self.clients.iter_mut()
+                .find(|client| client.client_id == address.dest
+                    && client.pending.as_ref().is_some_and(|pending| address.in_reply_to.as_ref() == Some(&pending.msg_id)));
+            match client {
+                Some(client) => {
+                    let pending = client.pending.take().unwrap();
+                    let event_type = match request_type(&response) {
+                        "error" if INDEFINITE_ERROR_CODES.contains(&response["code"].as_u64().unwrap_or_default()) => EventType::Info,
+                        "error" => EventType::Fail,
+                        _ => EventType::Ok,
+                    };
+                    if event_type == EventType::Ok {
+                        self.workload.observe(&pending.request, &response);
+                    }
+                    self.history.complete(event_type, client.process, client.node_id.clone(), request_type(&pending.request), payload(&response), now);
+                    if event_type == EventType::Info {
+                        client.process += self.config.concurrency as u64 + 1;
+                    }
+                }
+                None => debug!("Dropping an unexpected response: '{:?}'", response)
+            }
+        }
+        Ok(())
+    }
+
+    /// Times out pending requests. As in Jepsen, a client with an indeterminate request continues as a new process.
+    fn expire(&mut self, now: Duration) {
+        for client in &mut self.clients {
+            if let Some(pending) = client.pending.take_if(|pending| pending.deadline <= now) {
+                self.history.complete(EventType::Info, client.process, client.node_id.clone(), request_type(&pending.request), Value::Null, now);
+                client.process += self.config.concurrency as u64 + 1;
+            }
+        }
+    }
+
+    fn idle_client(&mut self) -> Option<usize> {
+        let idle: Vec<usize> = self.clients[..self.config.concurrency].iter().enumerate()
+            .filter(|(_, client)| client.pending.is_none())
+            .map(|(idx, _)| idx)
+            .collect();
+        if idle.is_empty() {
+            None
+        } else {
+            Some(idle[self.rng.gen_range(0..idle.len())])
+        }
+    }
+
+    fn idle_client_exists(&self) -> bool {
+        self.clients[..self.config.concurrency].iter().any(|client| client.pending.is_none())
+    }
+
+    fn next_deadline(&self) -> Option<Duration> {
+        self.clients.iter().filter_map(|client| client.pending.as_ref().map(|pending| pending.deadline)).min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::common::actor::Actor;
+    use crate::common::context::Context;
+    use crate::common::driver::{ClientDriver, DriverConfig};
+    use crate::common::error::Error::Config;
+    use crate::common::error::Result;
+    use crate::common::history::EventType;
+    use crate::common::message::message::Message;
+    use crate::common::simulator::{Simulator, SimulatorConfig};
+    use crate::common::workload::echo::EchoWorkload;
+
+    struct EchoActor;
+
+    impl Actor for EchoActor {
+        type Msg = serde_json::Value;
+        type TimerKey = ();
+
+        fn new(_: &mut Context<Self>) -> Result<Self> {
+            Ok(EchoActor)
+        }
+
+        fn on_request(&mut self, context: &mut Context<Self>, request: Message<Self::Msg>) -> Result<()> {
+            let (mut body, address) = request.body_and_address();
+            body["type"] = "echo_ok".into();
+            context.reply(address, body);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn should_record_history_against_simulator() -> Result<()> {
+        let mut simulator = Simulator::<EchoActor>::new(SimulatorConfig {
+            node_count: 2,
+            latency: Duration::from_millis(10),
+            ..SimulatorConfig::default()
+        })?;
+        let driver = ClientDriver::new(DriverConfig {
+            concurrency: 2,
+            rate: 10.0,
+            time_limit: Duration::from_secs(1),
+            ..DriverConfig::default()
+        }, EchoWorkload::new());
+
+        let history = driver.run(&mut simulator)?;
+
+        let operations = history.operations();
+        assert_eq!(operations.len(), 10);
+        assert!(operations.iter().all(|operation| operation.is_ok() && operation.value == *operation.result().unwrap()));
+        assert_eq!(operations[0].complete_time().unwrap() - operations[0].invoke_time, Duration::from_millis(20));
+        assert_eq!(history.events().iter().filter(|event| event.event_type == EventType::Invoke).count(), 10);
+        assert_eq!(simulator.stats().clients, 20);
+        Ok(())
+    }
+
+    #[test]
+    fn should_reject_invalid_config() -> Result<()> {
+        let mut simulator = Simulator::<EchoActor>::new(SimulatorConfig::default())?;
+
+        for config in [DriverConfig { rate: 0.0, ..DriverConfig::default() }, DriverConfig { concurrency: 0, ..DriverConfig::default() }] {
+            let result = ClientDriver::new(config, EchoWorkload::new()).run(&mut simulator);
+
+            assert!(matches!(result, Err(Config(_))));
+        }
+        Ok(())
+    }
+}
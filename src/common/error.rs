@@ -11,6 +11,10 @@ pub enum Error {
     Serde(#[from] serde_json::Error),
     #[error("Console error: '{0}'")]
     Console(String),
+    #[error("Invalid configuration: '{0}'")]
+    Config(String),
+    #[error("Malformed history: '{0}'")]
+    MalformedHistory(String),
     #[error("Unexpected error: '{0}'")]
     UnexpectedError(String),
 }
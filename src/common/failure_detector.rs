@@ -0,0 +1,180 @@
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::common::message::NodeId;
+
+#[derive(Clone, Debug)]
+pub struct FailureDetectorConfig {
+    /// How often suspicion is re-evaluated and, if enabled, heartbeats are sent to every peer.
+    pub interval: Duration,
+    /// Send `heartbeat` messages, for workloads whose traffic does not reach every peer regularly.
+    pub heartbeats: bool,
+    /// The phi above which a peer is suspected.
+    pub threshold: f64,
+    /// How many heartbeat intervals are kept per peer.
+    pub window: usize,
+    /// A floor on the deviation of intervals, so that very regular traffic does not make the detector trigger happy.
+    pub min_std_dev: Duration,
+}
+
+impl Default for FailureDetectorConfig {
+    fn default() -> Self {
+        FailureDetectorConfig {
+            interval: Duration::from_millis(100),
+            heartbeats: true,
+            threshold: 8.0,
+            window: 100,
+            min_std_dev: Duration::from_millis(50),
+        }
+    }
+}
+
+/// The arrival intervals of heartbeats from one peer, in milliseconds.
+#[derive(Debug)]
+struct History {
+    last: Instant,
+    intervals: VecDeque<f64>,
+}
+
+impl History {
+    fn mean_and_std_dev(&self) -> (f64, f64) {
+        let count = self.intervals.len() as f64;
+        let mean = self.intervals.iter().sum::<f64>() / count;
+        let variance = self.intervals.iter().map(|interval| (interval - mean).powi(2)).sum::<f64>() / count;
+        (mean, variance.sqrt())
+    }
+}
+
+/// Phi-accrual failure detection: every heartbeat from a peer, or every message when heartbeats are off, counts as
+/// one, and the suspicion of a peer grows with the time since its last heartbeat relative to the distribution of
+/// the previous intervals.
+#[derive(Debug)]
+pub struct FailureDetector {
+    config: FailureDetectorConfig,
+    histories: HashMap<NodeId, History>,
+    suspects: BTreeSet<NodeId>,
+}
+
+impl FailureDetector {
+    /// Starts watching the peers as if each had just sent a heartbeat, so peers which never answer get suspected.
+    pub fn new(config: FailureDetectorConfig, peers: &[NodeId], now: Instant) -> FailureDetector {
+        // Seed every history with an estimate of one interval, so phi is defined from the first heartbeat.
+        let estimate = config.interval.as_secs_f64() * 1000.0;
+        let histories = peers.iter().map(|node_id| (node_id.clone(), History {
+            last: now,
+            intervals: VecDeque::from([estimate * 0.75, estimate * 1.25]),
+        })).collect();
+        FailureDetector {
+            config,
+            histories,
+            suspects: BTreeSet::new(),
+        }
+    }
+
+    pub fn config(&self) -> &FailureDetectorConfig {
+        &self.config
+    }
+
+    pub fn peers(&self) -> impl Iterator<Item = &NodeId> {
+        self.histories.keys()
+    }
+
+    /// Records a heartbeat from a peer, returning whether it was suspected until now.
+    pub fn heartbeat(&mut self, node_id: &NodeId, now: Instant) -> bool {
+        let Some(history) = self.histories.get_mut(node_id) else { return false };
+        let interval = now.saturating_duration_since(history.last).as_secs_f64() * 1000.0;
+        history.last = now;
+        history.intervals.push_back(interval);
+        while history.intervals.len() > self.config.window {
+            history.intervals.pop_front();
+        }
+        self.suspects.remove(node_id)
+    }
+
+    /// The suspicion of a peer: a phi of 1 means a 10% chance that suspecting it is a mistake, 2 a 1% chance, and so on.
+    pub fn phi(&self, node_id: &NodeId, now: Instant) -> f64 {
+        let Some(history) = self.histories.get(node_id) else { return 0.0 };
+        let elapsed = now.saturating_duration_since(history.last).as_secs_f64() * 1000.0;
+        let (mean, std_dev) = history.mean_and_std_dev();
+        let std_dev = std_dev.max(self.config.min_std_dev.as_secs_f64() * 1000.0);
+        // A logistic approximation of the normal CDF, as used by Akka and Cassandra.
+        let y = (elapsed - mean) / std_dev;
+        let e = (-y * (1.5976 + 0.070566 * y * y)).exp();
+        if elapsed > mean {
+            -(e / (1.0 + e)).log10()
+        } else {
+            -(1.0 - 1.0 / (1.0 + e)).log10()
+        }
+    }
+
+    pub fn is_suspected(&self, node_id: &NodeId) -> bool {
+        self.suspects.contains(node_id)
+    }
+
+    pub fn suspects(&self) -> &BTreeSet<NodeId> {
+        &self.suspects
+    }
+
+    /// The peers which are not suspected.
+    pub fn available(&self) -> Vec<NodeId> {
+        let mut available: Vec<NodeId> = self.histories.keys().filter(|node_id| !self.suspects.contains(node_id)).cloned().collect();
+        available.sort();
+        available
+    }
+
+    /// Re-evaluates every peer, returning the ones which just became suspected.
+    pub fn check(&mut self, now: Instant) -> Vec<NodeId> {
+        let mut suspected: Vec<NodeId> = self.histories.keys()
+            .filter(|node_id| !self.suspects.contains(node_id) && self.phi(node_id, now) > self.config.threshold)
+            .cloned()
+            .collect();
+        suspected.sort();
+        self.suspects.extend(suspected.iter().cloned());
+        suspected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::common::failure_detector::{FailureDetector, FailureDetectorConfig};
+    use crate::common::message::NodeId;
+
+    #[test]
+    fn should_suspect_silent_peers_and_recover_on_heartbeat() {
+        let start = Instant::now();
+        let peers = [NodeId::from("n1"), NodeId::from("n2")];
+        let mut detector = FailureDetector::new(FailureDetectorConfig::default(), &peers, start);
+
+        for tick in 1..=20 {
+            let now = start + Duration::from_millis(100 * tick);
+            detector.heartbeat(&peers[0], now);
+            detector.heartbeat(&peers[1], now);
+            assert!(detector.check(now).is_empty());
+        }
+        let silence = start + Duration::from_millis(2000 + 150);
+        detector.heartbeat(&peers[0], silence);
+        assert!(detector.phi(&peers[1], silence) < 8.0);
+
+        let later = start + Duration::from_millis(3000);
+        detector.heartbeat(&peers[0], later);
+        assert!(detector.phi(&peers[1], later) > 8.0);
+        assert_eq!(detector.check(later), vec![peers[1].clone()]);
+        assert!(detector.check(later).is_empty());
+        assert_eq!(detector.available(), vec![peers[0].clone()]);
+
+        assert!(detector.heartbeat(&peers[1], later));
+        assert!(!detector.is_suspected(&peers[1]));
+    }
+
+    #[test]
+    fn should_suspect_peers_which_never_answer() {
+        let start = Instant::now();
+        let peers = [NodeId::from("n1")];
+        let mut detector = FailureDetector::new(FailureDetectorConfig::default(), &peers, start);
+
+        assert!(detector.check(start + Duration::from_millis(100)).is_empty());
+        assert_eq!(detector.check(start + Duration::from_secs(2)), vec![peers[0].clone()]);
+    }
+}
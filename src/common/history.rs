@@ -0,0 +1,378 @@
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::common::error::Result;
+use crate::common::message::NodeId;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EventType {
+    Invoke,
+    Ok,
+    Fail,
+    Info,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Event {
+    pub index: usize,
+    #[serde(rename = "type")]
+    pub event_type: EventType,
+    pub process: u64,
+    pub node: NodeId,
+    pub f: String,
+    pub value: Value,
+    pub time: u64,
+}
+
+impl Event {
+    pub fn time(&self) -> Duration {
+        Duration::from_nanos(self.time)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Completion {
+    pub event_type: EventType,
+    pub value: Value,
+    pub time: Duration,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Operation {
+    pub process: u64,
+    pub node: NodeId,
+    pub f: String,
+    pub value: Value,
+    pub invoke_time: Duration,
+    pub completion: Option<Completion>,
+}
+
+impl Operation {
+    pub fn is_ok(&self) -> bool {
+        self.event_type() == Some(EventType::Ok)
+    }
+
+    pub fn is_fail(&self) -> bool {
+        self.event_type() == Some(EventType::Fail)
+    }
+
+    pub fn event_type(&self) -> Option<EventType> {
+        self.completion.as_ref().map(|completion| completion.event_type)
+    }
+
+    pub fn result(&self) -> Option<&Value> {
+        self.completion.as_ref().map(|completion| &completion.value)
+    }
+
+    pub fn complete_time(&self) -> Option<Duration> {
+        self.completion.as_ref().map(|completion| completion.time)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct History {
+    events: Vec<Event>,
+}
+
+impl History {
+    pub fn new() -> History {
+        History {
+            events: vec![]
+        }
+    }
+
+    pub fn invoke(&mut self, process: u64, node: NodeId, f: &str, value: Value, time: Duration) {
+        self.push(EventType::Invoke, process, node, f, value, time)
+    }
+
+    pub fn complete(&mut self, event_type: EventType, process: u64, node: NodeId, f: &str, value: Value, time: Duration) {
+        self.push(event_type, process, node, f, value, time)
+    }
+
+    fn push(&mut self, event_type: EventType, process: u64, node: NodeId, f: &str, value: Value, time: Duration) {
+        self.events.push(Event {
+            index: self.events.len(),
+            event_type,
+            process,
+            node,
+            f: f.to_string(),
+            value,
+            time: time.as_nanos() as u64,
+        })
+    }
+
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Pairs every invocation with the next completion of the same process.
+    pub fn operations(&self) -> Vec<Operation> {
+        let mut operations: Vec<Operation> = vec![];
+        let mut pending: HashMap<u64, usize> = HashMap::new();
+        for event in &self.events {
+            match event.event_type {
+                EventType::Invoke => {
+                    pending.insert(event.process, operations.len());
+                    operations.push(Operation {
+                        process: event.process,
+                        node: event.node.clone(),
+                        f: event.f.clone(),
+                        value: event.value.clone(),
+                        invoke_time: event.time(),
+                        completion: None,
+                    });
+                }
+                event_type => {
+                    if let Some(idx) = pending.remove(&event.process) {
+                        operations[idx].completion = Some(Completion {
+                            event_type,
+                            value: event.value.clone(),
+                            time: event.time(),
+                        });
+                    }
+                }
+            }
+        }
+        operations
+    }
+
+    pub fn write_jsonl<W>(&self, mut out: W) -> Result<()>
+        where W: Write {
+        for event in &self.events {
+            serde_json::to_writer(&mut out, event)?;
+            out.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    pub fn read_jsonl<R>(input: R) -> Result<History>
+        where R: BufRead {
+        let mut events = vec![];
+        for line in input.lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                events.push(serde_json::from_str(&line)?);
+            }
+        }
+        Ok(History { events })
+    }
+
+    /// Writes the history in the `history.edn` format of Jepsen and Maelstrom, one operation map per line.
+    /// Transactions are exported as Elle expects them: the value is the list of micro-operations with keyword functions.
+    /// Failed operations keep the value of their invocation, with the error returned alongside it.
+    pub fn write_edn<W>(&self, mut out: W) -> Result<()>
+        where W: Write {
+        let mut invocations: HashMap<u64, &Value> = HashMap::new();
+        for event in &self.events {
+            let invocation = match event.event_type {
+                EventType::Invoke => invocations.insert(event.process, &event.value),
+                _ => invocations.remove(&event.process),
+            };
+            let mut line = format!("{{:index {}, :type :{}, :process {}, :f {}, :value ",
+                                   event.index, event_type_name(event.event_type), event.process, edn_keyword(&event.f));
+            if event.event_type == EventType::Fail {
+                write_edn_operation_value(&mut line, &event.f, invocation.unwrap_or(&Value::Null));
+                line.push_str(", :error ");
+                write_edn_value(&mut line, &event.value);
+            } else {
+                write_edn_operation_value(&mut line, &event.f, &event.value);
+            }
+            line.push_str(&format!(", :time {}, :node {}}}\n", event.time, edn_string(&event.node.to_string())));
+            out.write_all(line.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+fn event_type_name(event_type: EventType) -> &'static str {
+    match event_type {
+        EventType::Invoke => "invoke",
+        EventType::Ok => "ok",
+        EventType::Fail => "fail",
+        EventType::Info => "info",
+    }
+}
+
+fn write_edn_operation_value(out: &mut String, f: &str, value: &Value) {
+    match value.get("txn").and_then(Value::as_array) {
+        Some(micro_ops) if f == "txn" => write_edn_txn(out, micro_ops),
+        _ => write_edn_value(out, value),
+    }
+}
+
+fn write_edn_txn(out: &mut String, micro_ops: &[Value]) {
+    out.push('[');
+    for (idx, micro_op) in micro_ops.iter().enumerate() {
+        if idx > 0 {
+            out.push(' ');
+        }
+        match micro_op.as_array().map(Vec::as_slice) {
+            Some([Value::String(f), rest @ ..]) => {
+                out.push_str(&format!("[:{}", f));
+                for value in rest {
+                    out.push(' ');
+                    write_edn_value(out, value);
+                }
+                out.push(']');
+            }
+            _ => write_edn_value(out, micro_op)
+        }
+    }
+    out.push(']');
+}
+
+fn write_edn_value(out: &mut String, value: &Value) {
+    match value {
+        Value::Null => out.push_str("nil"),
+        Value::Bool(value) => out.push_str(&value.to_string()),
+        Value::Number(value) => out.push_str(&value.to_string()),
+        Value::String(value) => out.push_str(&edn_string(value)),
+        Value::Array(values) => {
+            out.push('[');
+            for (idx, value) in values.iter().enumerate() {
+                if idx > 0 {
+                    out.push(' ');
+                }
+                write_edn_value(out, value);
+            }
+            out.push(']');
+        }
+        Value::Object(fields) => {
+            out.push('{');
+            for (idx, (name, value)) in fields.iter().enumerate() {
+                if idx > 0 {
+                    out.push_str(", ");
+                }
+                if is_keyword(name) {
+                    out.push_str(&format!("{} ", edn_keyword(name)));
+                } else {
+                    out.push_str(&format!("{} ", edn_string(name)));
+                }
+                write_edn_value(out, value);
+            }
+            out.push('}');
+        }
+    }
+}
+
+fn is_keyword(name: &str) -> bool {
+    name.chars().next().is_some_and(|first| first.is_ascii_alphabetic())
+        && name.chars().all(|char| char.is_ascii_alphanumeric() || matches!(char, '_' | '-' | '?' | '!'))
+}
+
+/// Jepsen keywords are kebab-case where Maelstrom names are snake_case.
+fn edn_keyword(name: &str) -> String {
+    format!(":{}", name.replace('_', "-"))
+}
+
+fn edn_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for char in value.chars() {
+        match char {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            char => escaped.push(char),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+impl FromIterator<Event> for History {
+    fn from_iter<T: IntoIterator<Item=Event>>(iter: T) -> Self {
+        History {
+            events: iter.into_iter().collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::{json, Value};
+
+    use crate::common::error::Result;
+    use crate::common::history::{EventType, History};
+    use crate::common::message::NodeId;
+
+    #[test]
+    fn should_export_edn() -> Result<()> {
+        let mut history = History::new();
+        history.invoke(0, NodeId::from("n0"), "txn", json!({"txn": [["r", 1, null], ["append", 2, 3]]}), Duration::from_nanos(10));
+        history.complete(EventType::Ok, 0, NodeId::from("n0"), "txn", json!({"txn": [["r", 1, [1, 2]], ["append", 2, 3]]}), Duration::from_nanos(20));
+        history.invoke(1, NodeId::from("n1"), "add", json!({"delta": 1}), Duration::from_nanos(30));
+        history.complete(EventType::Fail, 1, NodeId::from("n1"), "add", json!({"code": 11, "text": "Say \"hi\""}), Duration::from_nanos(40));
+        history.invoke(2, NodeId::from("n1"), "list_committed_offsets", json!({"key_name": "a"}), Duration::from_nanos(50));
+        history.complete(EventType::Info, 2, NodeId::from("n1"), "list_committed_offsets", Value::Null, Duration::from_nanos(60));
+        history.invoke(0, NodeId::from("n0"), "txn", json!({"txn": [["w", 1, 4]]}), Duration::from_nanos(70));
+        history.complete(EventType::Fail, 0, NodeId::from("n0"), "txn", json!({"code": 30}), Duration::from_nanos(80));
+
+        let mut out = vec![];
+        history.write_edn(&mut out)?;
+
+        assert_eq!(String::from_utf8(out).unwrap(), [
+            r#"{:index 0, :type :invoke, :process 0, :f :txn, :value [[:r 1 nil] [:append 2 3]], :time 10, :node "n0"}"#,
+            r#"{:index 1, :type :ok, :process 0, :f :txn, :value [[:r 1 [1 2]] [:append 2 3]], :time 20, :node "n0"}"#,
+            r#"{:index 2, :type :invoke, :process 1, :f :add, :value {:delta 1}, :time 30, :node "n1"}"#,
+            r#"{:index 3, :type :fail, :process 1, :f :add, :value {:delta 1}, :error {:code 11, :text "Say \"hi\""}, :time 40, :node "n1"}"#,
+            r#"{:index 4, :type :invoke, :process 2, :f :list-committed-offsets, :value {:key-name "a"}, :time 50, :node "n1"}"#,
+            r#"{:index 5, :type :info, :process 2, :f :list-committed-offsets, :value nil, :time 60, :node "n1"}"#,
+            r#"{:index 6, :type :invoke, :process 0, :f :txn, :value [[:w 1 4]], :time 70, :node "n0"}"#,
+            r#"{:index 7, :type :fail, :process 0, :f :txn, :value [[:w 1 4]], :error {:code 30}, :time 80, :node "n0"}"#,
+            "",
+        ].join("\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn should_round_trip_jsonl() -> Result<()> {
+        let mut history = History::new();
+        history.invoke(0, NodeId::from("n0"), "broadcast", json!({"message": 1}), Duration::from_millis(1));
+        history.complete(EventType::Ok, 0, NodeId::from("n0"), "broadcast", json!({}), Duration::from_millis(2));
+
+        let mut out = vec![];
+        history.write_jsonl(&mut out)?;
+
+        assert_eq!(String::from_utf8(out.clone()).unwrap().lines().next().unwrap(),
+                   r#"{"index":0,"type":"invoke","process":0,"node":"n0","f":"broadcast","value":{"message":1},"time":1000000}"#);
+        assert_eq!(History::read_jsonl(out.as_slice())?, history);
+        Ok(())
+    }
+
+    #[test]
+    fn should_pair_invocations_with_completions() {
+        let mut history = History::new();
+        history.invoke(0, NodeId::from("n0"), "read", json!({}), Duration::from_millis(1));
+        history.invoke(1, NodeId::from("n1"), "add", json!({"delta": 1}), Duration::from_millis(2));
+        history.complete(EventType::Ok, 1, NodeId::from("n1"), "add", json!({}), Duration::from_millis(3));
+        history.complete(EventType::Ok, 0, NodeId::from("n0"), "read", json!({"value": 1}), Duration::from_millis(4));
+        history.invoke(1, NodeId::from("n1"), "add", json!({"delta": 2}), Duration::from_millis(5));
+
+        let operations = history.operations();
+
+        assert_eq!(operations.len(), 3);
+        assert_eq!(operations[0].result(), Some(&json!({"value": 1})));
+        assert_eq!(operations[0].complete_time(), Some(Duration::from_millis(4)));
+        assert!(operations[1].is_ok());
+        assert_eq!(operations[2].value, json!({"delta": 2}));
+        assert_eq!(operations[2].completion, None);
+    }
+}
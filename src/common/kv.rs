@@ -0,0 +1,67 @@
+use std::marker::PhantomData;
+
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::{Deserialize, Serialize};
+
+use crate::common::rpc::Request;
+
+pub const KEY_DOES_NOT_EXIST: u64 = 20;
+pub const PRECONDITION_FAILED: u64 = 22;
+
+/// Reads a key of the `seq-kv`, `lin-kv` or `lww-kv` service, whose value deserializes into `V`.
+#[derive(Serialize)]
+#[serde(tag = "type", rename = "read")]
+pub struct Read<V> {
+    pub key: String,
+    #[serde(skip)]
+    value: PhantomData<fn() -> V>,
+}
+
+impl<V> Read<V> {
+    pub fn new(key: &str) -> Read<V> {
+        Read { key: key.to_string(), value: PhantomData }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ReadOk<V> {
+    pub value: V,
+}
+
+impl<V> Request for Read<V>
+    where V: DeserializeOwned {
+    type Response = ReadOk<V>;
+}
+
+/// Replaces the value of a key if it is still `from`, `None` standing for a missing key.
+#[derive(Serialize)]
+#[serde(tag = "type", rename = "cas")]
+pub struct Cas<V> {
+    pub key: String,
+    pub from: Option<V>,
+    pub to: V,
+    pub create_if_not_exists: bool,
+}
+
+impl<V> Request for Cas<V>
+    where V: Serialize {
+    type Response = IgnoredAny;
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::common::error::Result;
+    use crate::common::kv::{Cas, Read};
+
+    #[test]
+    fn should_serialize_requests() -> Result<()> {
+        let read = serde_json::to_value(Read::<u64>::new("k"))?;
+        let cas = serde_json::to_value(Cas { key: "k".to_string(), from: None, to: 2, create_if_not_exists: true })?;
+
+        assert_eq!(read, json!({"type": "read", "key": "k"}));
+        assert_eq!(cas, json!({"type": "cas", "key": "k", "from": null, "to": 2, "create_if_not_exists": true}));
+        Ok(())
+    }
+}
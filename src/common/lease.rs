@@ -0,0 +1,394 @@
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::common::actor::Actor;
+use crate::common::context::Context;
+use crate::common::error::Result;
+use crate::common::kv::{Cas, Read, KEY_DOES_NOT_EXIST};
+use crate::common::message::{NodeId, ServiceKind};
+
+#[derive(Clone, Debug)]
+pub struct LeaseConfig {
+    /// The `lin-kv` key holding the lease.
+    pub key: String,
+    /// How long a lease lasts without being renewed.
+    pub duration: Duration,
+    /// How often the leader renews the lease and the other nodes read it. Shorter than `duration`.
+    pub renew_interval: Duration,
+}
+
+impl Default for LeaseConfig {
+    fn default() -> Self {
+        LeaseConfig {
+            key: "leader".to_string(),
+            duration: Duration::from_millis(1000),
+            renew_interval: Duration::from_millis(250),
+        }
+    }
+}
+
+/// The value of the lease key. Every renewal bumps `renewal`, so other nodes can tell a renewed lease from an
+/// abandoned one without comparing clocks. Every acquisition bumps `token`, the fencing token of the new leader.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Lease {
+    pub holder: NodeId,
+    pub token: u64,
+    pub renewal: u64,
+}
+
+/// The next request an election sends to `lin-kv`.
+#[derive(Debug, PartialEq)]
+enum LeaseRequest {
+    Read,
+    Cas {
+        from: Option<Lease>,
+        to: Lease,
+    },
+}
+
+/// Leader election over a lease in `lin-kv`. A node only takes a lease over once it has seen it unchanged for a
+/// whole `duration`, while the leader counts its lease from the moment it sent the renewal, so the leader always
+/// steps down before anyone else takes over, provided clocks run at the same rate. Owned by a `Candidate`, which
+/// drives it with `campaign`.
+#[derive(Debug)]
+pub struct LeaderElection {
+    config: LeaseConfig,
+    /// The lease last read or written, `None` once read missing.
+    lease: Option<Lease>,
+    /// Since when `lease` has been seen unchanged, `None` until the key is read.
+    observed_at: Option<Instant>,
+    held_until: Option<Instant>,
+    /// Until when the request in flight is waited for.
+    in_flight_until: Option<Instant>,
+}
+
+impl LeaderElection {
+    pub fn new(config: LeaseConfig) -> LeaderElection {
+        LeaderElection {
+            config,
+            lease: None,
+            observed_at: None,
+            held_until: None,
+            in_flight_until: None,
+        }
+    }
+
+    pub fn config(&self) -> &LeaseConfig {
+        &self.config
+    }
+
+    pub fn is_leader(&self, now: Instant) -> bool {
+        self.held_until.is_some_and(|held_until| now < held_until)
+    }
+
+    /// The fencing token of this node while it holds the lease.
+    pub fn token(&self) -> Option<u64> {
+        self.held_until.and(self.lease.as_ref()).map(|lease| lease.token)
+    }
+
+    /// The holder of the lease as last seen, which may have expired since.
+    pub fn lease(&self) -> Option<&Lease> {
+        self.lease.as_ref()
+    }
+
+    /// Renews the lease while holding it, takes it over once abandoned, and reads it otherwise.
+    fn next_request(&mut self, this_node: &NodeId, now: Instant) -> Option<LeaseRequest> {
+        if self.in_flight_until.is_some_and(|in_flight_until| now < in_flight_until) {
+            return None;
+        }
+        let acquire = |token| Lease { holder: this_node.clone(), token, renewal: 0 };
+        let request = match (&self.lease, self.observed_at) {
+            (Some(lease), _) if self.is_leader(now) => LeaseRequest::Cas {
+                from: Some(lease.clone()),
+                to: Lease { renewal: lease.renewal + 1, ..lease.clone() },
+            },
+            (None, Some(_)) => LeaseRequest::Cas { from: None, to: acquire(1) },
+            (Some(lease), Some(observed_at)) if now >= observed_at + self.config.duration => LeaseRequest::Cas {
+                from: Some(lease.clone()),
+                to: acquire(lease.token + 1),
+            },
+            _ => LeaseRequest::Read,
+        };
+        self.in_flight_until = Some(now + self.config.duration);
+        Some(request)
+    }
+
+    fn on_read(&mut self, lease: Option<Lease>, now: Instant) {
+        self.in_flight_until = None;
+        if self.observed_at.is_none() || lease != self.lease {
+            self.lease = lease;
+            self.observed_at = Some(now);
+        }
+    }
+
+    /// Records a lease written at `sent_at`, returning whether this node has just been elected.
+    fn on_written(&mut self, lease: Lease, sent_at: Instant, now: Instant) -> bool {
+        self.in_flight_until = None;
+        let was_leader = self.held_until.is_some();
+        let held_until = sent_at + self.config.duration;
+        self.lease = Some(lease);
+        self.observed_at = Some(sent_at);
+        self.held_until = (now < held_until).then_some(held_until);
+        !was_leader && self.held_until.is_some()
+    }
+
+    /// Forgets the lease after a failed write, returning whether this node has just been deposed.
+    fn on_rejected(&mut self) -> bool {
+        self.in_flight_until = None;
+        self.lease = None;
+        self.observed_at = None;
+        self.held_until.take().is_some()
+    }
+
+    /// Steps down once the lease has run out, returning whether this node has just been deposed.
+    fn expire(&mut self, now: Instant) -> bool {
+        if self.held_until.is_some_and(|held_until| now >= held_until) {
+            self.held_until = None;
+            return true;
+        }
+        false
+    }
+}
+
+/// An actor campaigning for a lease with the `LeaderElection` it owns. It calls `campaign` once it starts, then
+/// from a timer of its own every `renew_interval`.
+pub trait Candidate: Actor {
+    fn election(&mut self) -> &mut LeaderElection;
+
+    /// Called when this node acquires the lease, with its fencing token.
+    fn on_elected(&mut self, context: &mut Context<Self>, token: u64) -> Result<()>;
+
+    /// Called when this node loses the lease or lets it run out.
+    fn on_deposed(&mut self, context: &mut Context<Self>) -> Result<()>;
+}
+
+/// Steps down if the lease ran out, then renews, takes over or reads the lease.
+pub fn campaign<A>(actor: &mut A, context: &mut Context<A>) -> Result<()>
+    where A: Candidate {
+    let now = context.now();
+    let election = actor.election();
+    let deposed = election.expire(now);
+    let request = election.next_request(&context.this_node().node_id, now);
+    let key = election.config().key.clone();
+    if deposed {
+        actor.on_deposed(context)?;
+    }
+    let lin_kv = NodeId::from(ServiceKind::LinKv);
+    match request {
+        Some(LeaseRequest::Read) => context.rpc(lin_kv, Read::<Lease>::new(&key), |actor: &mut A, context, response| {
+            let now = context.now();
+            match response {
+                Ok(read) => actor.election().on_read(Some(read.value), now),
+                Err(error) if error.code == KEY_DOES_NOT_EXIST => actor.election().on_read(None, now),
+                Err(_) => {}
+            }
+            Ok(())
+        }),
+        Some(LeaseRequest::Cas { from, to }) => {
+            let request = Cas { key, create_if_not_exists: from.is_none(), from, to: to.clone() };
+            // The lease is counted from the moment the request is sent, as `lin-kv` may apply it right away.
+            let sent_at = now;
+            context.rpc(lin_kv, request, move |actor: &mut A, context, response| {
+                let now = context.now();
+                match response {
+                    Ok(_) if actor.election().on_written(to, sent_at, now) => {
+                        let token = actor.election().token().unwrap_or_default();
+                        actor.on_elected(context, token)
+                    }
+                    Ok(_) => Ok(()),
+                    Err(_) if actor.election().on_rejected() => actor.on_deposed(context),
+                    Err(_) => Ok(()),
+                }
+            })
+        }
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use serde_json::{json, Value};
+
+    use crate::common::actor::Actor;
+    use crate::common::context::{ActorCell, Context, TimerId};
+    use crate::common::error::Result;
+    use crate::common::lease::{campaign, Candidate, LeaderElection, Lease, LeaseConfig, LeaseRequest};
+    use crate::common::message::message::Message;
+    use crate::common::message::{NodeId, ServiceKind};
+    use crate::common::runner::RunnerAction;
+    use crate::common::simulator::{Simulator, SimulatorConfig};
+    use crate::common::this_node::ThisNode;
+
+    fn lease(holder: &str, token: u64, renewal: u64) -> Lease {
+        Lease { holder: NodeId::from(holder), token, renewal }
+    }
+
+    #[test]
+    fn should_take_over_abandoned_lease_only() {
+        let this_node = NodeId::from("n0");
+        let mut election = LeaderElection::new(LeaseConfig::default());
+        let now = Instant::now();
+        let later = |millis| now + Duration::from_millis(millis);
+
+        assert_eq!(election.next_request(&this_node, now), Some(LeaseRequest::Read));
+        assert_eq!(election.next_request(&this_node, later(100)), None);
+        election.on_read(Some(lease("n1", 3, 0)), later(100));
+        election.on_read(Some(lease("n1", 3, 1)), later(600));
+        election.on_read(Some(lease("n1", 3, 1)), later(1500));
+        assert_eq!(election.next_request(&this_node, later(1500)), Some(LeaseRequest::Read));
+        election.on_read(Some(lease("n1", 3, 1)), later(1600));
+
+        let request = election.next_request(&this_node, later(1600));
+        assert_eq!(request, Some(LeaseRequest::Cas { from: Some(lease("n1", 3, 1)), to: lease("n0", 4, 0) }));
+        assert!(election.on_written(lease("n0", 4, 0), later(1600), later(1700)));
+        assert_eq!(election.token(), Some(4));
+        assert!(election.is_leader(later(2500)));
+        assert!(election.expire(later(2600)));
+        assert_eq!(election.token(), None);
+    }
+
+    struct CandidateActor {
+        election: LeaderElection,
+        events: Vec<String>,
+        /// Whether this node led, every time it campaigned.
+        samples: Vec<(Instant, bool)>,
+    }
+
+    impl Candidate for CandidateActor {
+        fn election(&mut self) -> &mut LeaderElection {
+            &mut self.election
+        }
+
+        fn on_elected(&mut self, _: &mut Context<Self>, token: u64) -> Result<()> {
+            self.events.push(format!("elected {}", token));
+            Ok(())
+        }
+
+        fn on_deposed(&mut self, _: &mut Context<Self>) -> Result<()> {
+            self.events.push("deposed".to_string());
+            Ok(())
+        }
+    }
+
+    impl Actor for CandidateActor {
+        type Msg = Value;
+        type TimerKey = ();
+
+        fn new(context: &mut Context<Self>) -> Result<Self> {
+            context.set_timer(Duration::ZERO, ());
+            Ok(CandidateActor { election: LeaderElection::new(LeaseConfig::default()), events: vec![], samples: vec![] })
+        }
+
+        fn on_request(&mut self, _: &mut Context<Self>, _: Message<Self::Msg>) -> Result<()> {
+            Ok(())
+        }
+
+        fn on_timeout(&mut self, context: &mut Context<Self>, _: Self::TimerKey) -> Result<()> {
+            campaign(self, context)?;
+            self.samples.push((context.now(), self.election.is_leader(context.now())));
+            context.set_timer(self.election.config().renew_interval, ());
+            Ok(())
+        }
+    }
+
+    impl CandidateActor {
+        fn is_leading(&self) -> bool {
+            self.samples.last().is_some_and(|(_, leading)| *leading)
+        }
+
+        fn led_since(&self) -> Option<Instant> {
+            self.samples.iter().find(|(_, leading)| *leading).map(|(at, _)| *at)
+        }
+
+        fn led_until(&self) -> Option<Instant> {
+            self.samples.iter().rev().find(|(_, leading)| *leading).map(|(at, _)| *at)
+        }
+    }
+
+    /// Fires the election timer, returning the request sent to `lin-kv` and the next timer.
+    fn run_campaign(cell: &mut ActorCell<CandidateActor>, timer_id: TimerId, now: Instant) -> Result<(Message<Value>, TimerId)> {
+        let mut actions = cell.on_timeout(timer_id, now)?.into_iter();
+        let (Some(RunnerAction::SetTimer { .. }), Some(RunnerAction::SendMessage(request)), Some(RunnerAction::SetTimer { timer_key, .. }), None) = (actions.next(), actions.next(), actions.next(), actions.next()) else {
+            panic!("Expected a request with its deadline and a timer")
+        };
+        assert_eq!(request.dest(), &NodeId::from("lin-kv"));
+        Ok((request, timer_key))
+    }
+
+    #[test]
+    fn should_acquire_lease_and_step_down_once_expired() -> Result<()> {
+        let now = Instant::now();
+        let this_node = ThisNode::new(NodeId::from("n0"), vec![NodeId::from("n0"), NodeId::from("n1")]);
+        let (mut cell, actions) = ActorCell::<CandidateActor>::new(this_node, 0, now)?;
+        let [RunnerAction::SetTimer { timer_key, .. }] = actions.as_slice() else { panic!("Expected a single timer") };
+
+        let (read, timer_id) = run_campaign(&mut cell, *timer_key, now)?;
+        assert_eq!(read.body(), &json!({"type": "read", "key": "leader"}));
+        cell.on_json_message(Message::new_reply(read.address().to_reply_address(), json!({"type": "error", "code": 20})), now)?;
+        let (cas, timer_id) = run_campaign(&mut cell, timer_id, now)?;
+        assert_eq!(cas.body(), &json!({
+            "type": "cas", "key": "leader", "from": null, "to": {"holder": "n0", "token": 1, "renewal": 0}, "create_if_not_exists": true,
+        }));
+        cell.on_json_message(Message::new_reply(cas.address().to_reply_address(), json!({"type": "cas_ok"})), now)?;
+        let (renewal, _) = run_campaign(&mut cell, timer_id, now + Duration::from_millis(250))?;
+        assert_eq!(renewal.body()["to"], json!({"holder": "n0", "token": 1, "renewal": 1}));
+
+        assert!(cell.actor().election.is_leader(now + Duration::from_millis(999)));
+        cell.on_json_message(Message::new_reply(renewal.address().to_reply_address(), json!({"type": "error", "code": 22})), now + Duration::from_millis(300))?;
+        assert_eq!(cell.actor().events, vec!["elected 1", "deposed"]);
+        Ok(())
+    }
+
+    fn simulator() -> Result<(Simulator<CandidateActor>, NodeId, NodeId)> {
+        let mut simulator = Simulator::<CandidateActor>::new(SimulatorConfig {
+            node_count: 2,
+            latency: Duration::from_millis(5),
+            ..SimulatorConfig::default()
+        })?;
+        simulator.run_until(Duration::from_secs(2))?;
+        let leaders: Vec<NodeId> = simulator.node_ids().into_iter().filter(|node_id| simulator.node(node_id).unwrap().is_leading()).collect();
+        assert_eq!(leaders.len(), 1);
+        let follower = simulator.node_ids().into_iter().find(|node_id| *node_id != leaders[0]).unwrap();
+        Ok((simulator, leaders[0].clone(), follower))
+    }
+
+    #[test]
+    fn should_let_another_node_take_over_once_the_lease_lapses() -> Result<()> {
+        let (mut simulator, leader, follower) = simulator()?;
+
+        simulator.partition(vec![vec![leader.clone()], vec![follower.clone(), NodeId::from(ServiceKind::LinKv)]]);
+        simulator.run_until(Duration::from_secs(6))?;
+
+        let (leader, follower) = (simulator.node(&leader).unwrap(), simulator.node(&follower).unwrap());
+        assert!(!leader.is_leading() && follower.is_leading());
+        assert_eq!(leader.events, vec!["elected 1", "deposed"]);
+        assert_eq!(follower.events, vec!["elected 2"]);
+        assert!(leader.led_until().unwrap() < follower.led_since().unwrap());
+        Ok(())
+    }
+
+    #[test]
+    fn should_lapse_while_partitioned_from_lin_kv() -> Result<()> {
+        let (mut simulator, leader, _) = simulator()?;
+
+        simulator.partition(vec![simulator.node_ids(), vec![NodeId::from(ServiceKind::LinKv)]]);
+        simulator.run_until(Duration::from_secs(4))?;
+        let lapsed = simulator.node_ids().iter().all(|node_id| !simulator.node(node_id).unwrap().is_leading());
+        simulator.heal();
+        simulator.run_until(Duration::from_secs(8))?;
+
+        assert!(lapsed);
+        assert_eq!(simulator.node(&leader).unwrap().events[..2], ["elected 1", "deposed"]);
+        let mut elected: Vec<String> = simulator.node_ids().iter()
+            .flat_map(|node_id| simulator.node(node_id).unwrap().events.clone())
+            .filter(|event| event.starts_with("elected"))
+            .collect();
+        elected.sort();
+        assert_eq!(elected, vec!["elected 1", "elected 2"]);
+        assert_eq!(simulator.node_ids().iter().filter(|node_id| simulator.node(node_id).unwrap().is_leading()).count(), 1);
+        Ok(())
+    }
+}
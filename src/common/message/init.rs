@@ -1,22 +1,21 @@
 use serde::{Deserialize, Serialize};
 
-use crate::common::message::NodeId;
+use crate::common::message::{MessageKind, NodeId};
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
-#[serde(tag = "type")]
+#[derive(Serialize, Deserialize, MessageKind, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum InitMessage {
-    #[serde(rename = "init")]
     Init {
         node_id: NodeId,
         node_ids: Vec<NodeId>,
     },
-    #[serde(rename = "init_ok")]
     InitOk,
 }
 
 #[cfg(test)]
 mod tests {
-    use std::collections::BTreeSet;
+    use serde_json::json;
+
     use crate::common::error::Result;
     use crate::common::message::{MessageId, NodeId};
     use crate::common::message::init::{InitMessage};
@@ -26,16 +25,17 @@ mod tests {
     fn should_deserialize_init() -> Result<()> {
         let str = r#"{"id":0,"src":"c0","dest":"n0","body":{"type":"init","node_id":"n0","node_ids":["n0"],"msg_id":1}}"#;
 
-        let result: Message<InitMessage> = serde_json::from_str(&str)?;
+        let result: Message<InitMessage> = serde_json::from_str(str)?;
 
         assert_eq!(result, Message::new_request(MessageAddress {
             src: NodeId::from("c0"),
             dest: NodeId::from("n0"),
-            msg_id: MessageId(1),
+            msg_id: Some(MessageId(1)),
+            in_reply_to: None,
         }, InitMessage::Init {
             node_id: NodeId::from("n0"),
             node_ids: vec![NodeId::from("n0")],
-        }));
+        }).with_field("id", json!(0)));
 
         Ok(())
     }
@@ -47,7 +47,8 @@ mod tests {
         let result = serde_json::to_string(&Message::new_reply(MessageAddress {
             src: NodeId::from("c0"),
             dest: NodeId::from("n0"),
-            msg_id: MessageId(1),
+            msg_id: Some(MessageId(1)),
+            in_reply_to: None,
         }.to_reply_address(), InitMessage::InitOk))?;
 
         assert_eq!(result, expected);
@@ -1,17 +1,19 @@
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use crate::common::message::{MessageId, NodeId};
 
 pub struct ReplyAddress {
     src: NodeId,
     dest: NodeId,
-    in_reply_to: MessageId,
+    in_reply_to: Option<MessageId>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct MessageAddress {
     pub src: NodeId,
     pub dest: NodeId,
-    pub msg_id: MessageId,
+    pub msg_id: Option<MessageId>,
+    pub in_reply_to: Option<MessageId>,
 }
 
 impl MessageAddress {
@@ -25,18 +27,13 @@ impl MessageAddress {
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
-#[serde(untagged)]
-enum MessageBody<A> {
-    Request {
-        msg_id: MessageId,
-        #[serde(flatten)]
-        value: A,
-    },
-    Reply {
-        in_reply_to: MessageId,
-        #[serde(flatten)]
-        value: A,
-    },
+struct MessageBody<A> {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    msg_id: Option<MessageId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    in_reply_to: Option<MessageId>,
+    #[serde(flatten)]
+    value: A,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
@@ -44,17 +41,23 @@ pub struct Message<A> {
     src: NodeId,
     dest: NodeId,
     body: MessageBody<A>,
+    /// Top-level fields other than `src`, `dest` and `body`, such as the `id` Maelstrom assigns.
+    #[serde(flatten)]
+    fields: Map<String, Value>,
 }
 
 impl<A> Message<A> {
+    /// Creates a message with the ids of the address, which may also be a reply when `in_reply_to` is set.
     pub fn new_request(address: MessageAddress, value: A) -> Message<A> {
         Message {
             src: address.src,
             dest: address.dest,
-            body: MessageBody::Request {
+            body: MessageBody {
                 msg_id: address.msg_id,
+                in_reply_to: address.in_reply_to,
                 value,
             },
+            fields: Map::new(),
         }
     }
 
@@ -62,36 +65,95 @@ impl<A> Message<A> {
         Message {
             src: address.src,
             dest: address.dest,
-            body: MessageBody::Reply {
+            body: MessageBody {
+                msg_id: None,
                 in_reply_to: address.in_reply_to,
                 value,
             },
+            fields: Map::new(),
         }
     }
 
+    pub fn with_field(mut self, name: &str, value: Value) -> Message<A> {
+        self.fields.insert(name.to_string(), value);
+        self
+    }
+
+    pub fn field(&self, name: &str) -> Option<&Value> {
+        self.fields.get(name)
+    }
+
+    pub fn src(&self) -> &NodeId {
+        &self.src
+    }
+
+    pub fn dest(&self) -> &NodeId {
+        &self.dest
+    }
+
+    pub fn body(&self) -> &A {
+        &self.body.value
+    }
+
     pub fn body_and_address(self) -> (A, MessageAddress) {
-        let Message { src, dest, body } = self;
-        let (msg_id, value) = match body {
-            MessageBody::Request { msg_id, value } => (msg_id, value),
-            MessageBody::Reply { in_reply_to, value } => (in_reply_to, value)
-        };
+        let Message { src, dest, body: MessageBody { msg_id, in_reply_to, value }, .. } = self;
         (value, MessageAddress {
             src,
             dest,
             msg_id,
+            in_reply_to,
+        })
+    }
+
+    /// Converts the body, keeping the address and the other fields.
+    pub fn try_map<B, E, F>(self, f: F) -> Result<Message<B>, E>
+        where F: FnOnce(A) -> Result<B, E> {
+        let Message { src, dest, body: MessageBody { msg_id, in_reply_to, value }, fields } = self;
+        Ok(Message {
+            src,
+            dest,
+            body: MessageBody {
+                msg_id,
+                in_reply_to,
+                value: f(value)?,
+            },
+            fields,
         })
     }
 
     pub fn address(&self) -> MessageAddress {
-        let Message { src, dest, body } = self;
-        let msg_id = match body {
-            MessageBody::Request { msg_id, .. } => msg_id,
-            MessageBody::Reply { in_reply_to, .. } => in_reply_to
-        };
         MessageAddress {
-            src: src.clone(),
-            dest: dest.clone(),
-            msg_id: msg_id.clone(),
+            src: self.src.clone(),
+            dest: self.dest.clone(),
+            msg_id: self.body.msg_id.clone(),
+            in_reply_to: self.body.in_reply_to.clone(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use crate::common::error::Result;
+    use crate::common::message::{MessageId, NodeId};
+    use crate::common::message::message::{Message, MessageAddress};
+
+    #[test]
+    fn should_round_trip_both_ids_and_unknown_fields() -> Result<()> {
+        let str = r#"{"src":"lin-kv","dest":"n0","body":{"msg_id":4,"in_reply_to":2,"type":"read_ok","value":1},"id":7}"#;
+
+        let result: Message<Value> = serde_json::from_str(str)?;
+
+        assert_eq!(result.address(), MessageAddress {
+            src: NodeId::from("lin-kv"),
+            dest: NodeId::from("n0"),
+            msg_id: Some(MessageId(4)),
+            in_reply_to: Some(MessageId(2)),
+        });
+        assert_eq!(result.body(), &json!({"type": "read_ok", "value": 1}));
+        assert_eq!(result.field("id"), Some(&json!(7)));
+        assert_eq!(serde_json::to_string(&result)?, str);
+        Ok(())
+    }
+}
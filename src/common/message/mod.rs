@@ -1,36 +1,136 @@
 pub mod init;
+#[allow(clippy::module_inception)]
 pub mod message;
 
 use std::fmt::{Display, Formatter};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::Value;
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
-#[serde(untagged)]
-#[serde(from = "&str")]
+pub use gossip_glomers_derive::MessageKind;
+
+/// Classifies the bodies of a message enum by their Maelstrom `type`. Usually derived with `#[derive(MessageKind)]`.
+pub trait MessageKind {
+    fn type_name(&self) -> &str;
+
+    fn is_reply(&self) -> bool;
+
+    /// The `type` of the reply expected for a request.
+    fn response_type(&self) -> Option<&'static str>;
+
+    /// All the `type`s the enum can be deserialized from.
+    fn type_names() -> &'static [&'static str];
+}
+
+/// Untyped bodies are classified by their `type` field, treating `..._ok` and `error` as replies.
+impl MessageKind for Value {
+    fn type_name(&self) -> &str {
+        self.get("type").and_then(Value::as_str).unwrap_or_default()
+    }
+
+    fn is_reply(&self) -> bool {
+        let type_name = self.type_name();
+        type_name.ends_with("_ok") || type_name == "error"
+    }
+
+    fn response_type(&self) -> Option<&'static str> {
+        None
+    }
+
+    fn type_names() -> &'static [&'static str] {
+        &[]
+    }
+}
+
+/// The services Maelstrom runs next to the nodes under test.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ServiceKind {
+    SeqKv,
+    LinKv,
+    LwwKv,
+    LinTso,
+}
+
+impl ServiceKind {
+    pub const ALL: [ServiceKind; 4] = [ServiceKind::SeqKv, ServiceKind::LinKv, ServiceKind::LwwKv, ServiceKind::LinTso];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ServiceKind::SeqKv => "seq-kv",
+            ServiceKind::LinKv => "lin-kv",
+            ServiceKind::LwwKv => "lww-kv",
+            ServiceKind::LinTso => "lin-tso",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ServiceKind> {
+        ServiceKind::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(from = "String")]
 pub enum NodeId {
     Server(String),
     Client(String),
+    Service(ServiceKind),
+}
+
+impl NodeId {
+    /// Whether the node is another server of the cluster, as opposed to a client or a service.
+    pub fn is_peer(&self) -> bool {
+        matches!(self, NodeId::Server(_))
+    }
+
+    pub fn is_client(&self) -> bool {
+        matches!(self, NodeId::Client(_))
+    }
+
+    pub fn is_service(&self) -> bool {
+        matches!(self, NodeId::Service(_))
+    }
 }
 
 impl Display for NodeId {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.write_str(match self {
             NodeId::Server(v) => v,
-            NodeId::Client(v) => v
+            NodeId::Client(v) => v,
+            NodeId::Service(kind) => kind.name(),
         })
     }
 }
 
+impl Serialize for NodeId {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+        where S: Serializer {
+        serializer.collect_str(self)
+    }
+}
+
 impl From<&str> for NodeId {
     fn from(value: &str) -> Self {
-        if value.starts_with("c") {
-            NodeId::Client(value.to_string())
+        NodeId::from(value.to_string())
+    }
+}
+
+impl From<String> for NodeId {
+    fn from(value: String) -> Self {
+        if let Some(kind) = ServiceKind::from_name(&value) {
+            NodeId::Service(kind)
+        } else if value.strip_prefix('c').is_some_and(|idx| !idx.is_empty() && idx.bytes().all(|byte| byte.is_ascii_digit())) {
+            NodeId::Client(value)
         } else {
-            NodeId::Server(value.to_string())
+            NodeId::Server(value)
         }
     }
 }
 
+impl From<ServiceKind> for NodeId {
+    fn from(kind: ServiceKind) -> Self {
+        NodeId::Service(kind)
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct MessageId(pub u64);
 
@@ -40,3 +140,26 @@ impl MessageId {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use crate::common::error::Result;
+    use crate::common::message::{NodeId, ServiceKind};
+
+    #[test]
+    fn should_classify_node_ids() {
+        assert_eq!(NodeId::from("n1"), NodeId::Server("n1".to_string()));
+        assert_eq!(NodeId::from("c12"), NodeId::Client("c12".to_string()));
+        assert_eq!(NodeId::from("lin-kv"), NodeId::Service(ServiceKind::LinKv));
+        assert_eq!(NodeId::from("lin-tso"), NodeId::Service(ServiceKind::LinTso));
+        assert!(NodeId::from("coordinator").is_peer());
+    }
+
+    #[test]
+    fn should_deserialize_escaped_node_ids() -> Result<()> {
+        let result: Vec<NodeId> = serde_json::from_str(r#"["seq-kv", "n\u0031", "c\u0032"]"#)?;
+
+        assert_eq!(result, vec![NodeId::Service(ServiceKind::SeqKv), NodeId::from("n1"), NodeId::from("c2")]);
+        assert_eq!(serde_json::to_string(&result)?, r#"["seq-kv","n1","c2"]"#);
+        Ok(())
+    }
+}
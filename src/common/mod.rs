@@ -1,8 +1,26 @@
 pub mod error;
 pub mod message;
 pub mod runner;
+pub mod rpc;
+pub mod kv;
 pub mod this_node;
+pub mod topology;
+pub mod ring;
 pub mod actor;
+pub mod context;
+pub mod composite;
+pub mod failure_detector;
+pub mod lease;
+pub mod swim;
+pub mod state_machine;
+pub mod raft;
+pub mod paxos;
+pub mod twopc;
 mod console;
 mod timer;
 pub mod record;
+pub mod history;
+pub mod workload;
+pub mod driver;
+pub mod simulator;
+pub mod check;
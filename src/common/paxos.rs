@@ -0,0 +1,706 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::time::Duration;
+
+use log::{debug, warn};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::common::actor::Actor;
+use crate::common::context::{Context, TimerId};
+use crate::common::error::Result;
+use crate::common::message::message::{Message, MessageAddress};
+use crate::common::message::{MessageKind, NodeId};
+use crate::common::state_machine::{abandon, forward, is_well_formed, SnapshotConfig, StateMachine};
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(50);
+const ELECTION_TIMEOUT_MS: std::ops::Range<u64> = 150..300;
+const MAX_CATCH_UP: usize = 100;
+
+/// Ballots are ordered by round, then by proposer, so two proposers never share one.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Ballot {
+    pub round: u64,
+    pub node_id: NodeId,
+}
+
+/// A command accepted in a slot. `None` is the no-op a new leader fills gaps with.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Accepted {
+    pub slot: u64,
+    pub ballot: Ballot,
+    pub command: Option<Value>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Decision {
+    pub slot: u64,
+    pub command: Option<Value>,
+}
+
+#[derive(Serialize, Deserialize, MessageKind, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PaxosMessage {
+    /// Phase 1a, asking for the commands accepted in slots after `decided_through`.
+    Prepare {
+        ballot: Ballot,
+        decided_through: u64,
+    },
+    /// Phase 1b. `ballot` is the one the acceptor has promised, which is higher than the prepared one if `ok` is false.
+    PrepareOk {
+        ballot: Ballot,
+        ok: bool,
+        accepted: Vec<Accepted>,
+    },
+    /// Phase 2a.
+    Accept {
+        ballot: Ballot,
+        slot: u64,
+        command: Option<Value>,
+    },
+    /// Phase 2b, with the same meaning of `ballot` as `prepare_ok`.
+    AcceptOk {
+        ballot: Ballot,
+        slot: u64,
+        ok: bool,
+    },
+    /// Decided commands, sent by the leader as they are decided and as heartbeats.
+    Decide {
+        ballot: Ballot,
+        decisions: Vec<Decision>,
+    },
+    /// Tells the leader up to which slot the node has decided every command, so it can send the missing ones.
+    DecideOk {
+        decided_through: u64,
+    },
+    /// A chunk of the state machine after applying every slot up to `slot`, for nodes missing compacted decisions.
+    Snapshot {
+        slot: u64,
+        offset: usize,
+        data: String,
+        done: bool,
+    },
+    /// Acknowledges the first `offset` bytes of a snapshot.
+    SnapshotOk {
+        slot: u64,
+        offset: usize,
+    },
+}
+
+#[derive(Debug)]
+pub enum PaxosTimer {
+    Election,
+    Heartbeat,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+/// A command the leader is getting accepted.
+struct Proposal {
+    command: Option<Value>,
+    acks: BTreeSet<NodeId>,
+}
+
+/// The snapshot a node is receiving.
+struct IncomingSnapshot {
+    slot: u64,
+    data: String,
+}
+
+/// Replicates a `StateMachine` with Multi-Paxos. Every node is an acceptor and a learner. A node which stops
+/// hearing from the leader runs phase 1 over every undecided slot with a higher ballot; once a majority has
+/// promised, it is a stable leader which skips phase 1 and only runs phase 2 for every new command, until a higher
+/// ballot shows up. Commands are handled as with `Raft`.
+///
+/// Every `threshold` applied slots, the state machine is serialized and the decided and accepted commands up to
+/// the last applied slot dropped. Nodes which need compacted decisions receive the snapshot instead, in chunks,
+/// and acceptors do not promise to a candidate missing them, since they cannot tell it what they accepted there.
+pub struct Paxos<S>
+    where S: StateMachine {
+    config: SnapshotConfig,
+    state_machine: S,
+    role: Role,
+    /// The highest round seen, which the next ballot of this node exceeds.
+    round: u64,
+    /// The ballot this node leads or campaigns with.
+    ballot: Option<Ballot>,
+    leader: Option<NodeId>,
+    promised: Option<Ballot>,
+    accepted: BTreeMap<u64, Accepted>,
+    decided: BTreeMap<u64, Option<Value>>,
+    /// Every slot up to `applied` is decided and applied.
+    applied: u64,
+    /// The slot up to which decided and accepted commands have been dropped.
+    snapshot_slot: u64,
+    /// The serialized state machine after applying every slot up to `snapshot_slot`.
+    snapshot: String,
+    incoming_snapshot: Option<IncomingSnapshot>,
+    /// How many bytes of the snapshot each lagging node has acknowledged.
+    snapshot_offsets: HashMap<NodeId, usize>,
+    promises: BTreeMap<NodeId, Vec<Accepted>>,
+    next_slot: u64,
+    proposals: BTreeMap<u64, Proposal>,
+    /// The requests the leader answers once their slot is applied.
+    pending: HashMap<u64, MessageAddress>,
+    election_timer: Option<TimerId>,
+    heartbeat_timer: Option<TimerId>,
+}
+
+impl<S> Paxos<S>
+    where S: StateMachine {
+    pub fn state_machine(&self) -> &S {
+        &self.state_machine
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    pub fn leader(&self) -> Option<&NodeId> {
+        self.leader.as_ref()
+    }
+
+    pub fn applied(&self) -> u64 {
+        self.applied
+    }
+
+    pub fn snapshot_slot(&self) -> u64 {
+        self.snapshot_slot
+    }
+
+    /// How many decided and accepted commands the node holds.
+    pub fn log_len(&self) -> usize {
+        self.decided.len() + self.accepted.len()
+    }
+
+    fn peers(context: &Context<Self>) -> Vec<NodeId> {
+        let this_node = context.this_node();
+        this_node.node_ids.iter().filter(|node_id| **node_id != this_node.node_id).cloned().collect()
+    }
+
+    fn is_majority(context: &Context<Self>, count: usize) -> bool {
+        count * 2 > context.this_node().node_ids.len()
+    }
+
+    fn send(context: &mut Context<Self>, dest: NodeId, message: PaxosMessage) -> Result<()> {
+        context.send(dest, serde_json::to_value(message)?);
+        Ok(())
+    }
+
+    fn reply(context: &mut Context<Self>, address: MessageAddress, message: PaxosMessage) -> Result<()> {
+        context.reply(address, serde_json::to_value(message)?);
+        Ok(())
+    }
+
+    fn reset_election_timer(&mut self, context: &mut Context<Self>) {
+        if let Some(timer_id) = self.election_timer.take() {
+            context.cancel_timer(timer_id);
+        }
+        let timeout = Duration::from_millis(context.rng().gen_range(ELECTION_TIMEOUT_MS));
+        self.election_timer = Some(context.set_timer(timeout, PaxosTimer::Election));
+    }
+
+    /// Notes a ballot seen in a message, stepping down if it beats the one this node leads or campaigns with.
+    fn observe(&mut self, context: &mut Context<Self>, ballot: &Ballot) {
+        self.round = self.round.max(ballot.round);
+        if self.role != Role::Follower && self.ballot.as_ref().is_some_and(|own| own < ballot) {
+            debug!("Stepping down for ballot {:?}", ballot);
+            self.role = Role::Follower;
+            self.ballot = None;
+            // Proposed commands may lose their slot to the new leader, so they cannot be answered anymore.
+            self.proposals.clear();
+            abandon(context, std::mem::take(&mut self.pending));
+            if let Some(timer_id) = self.heartbeat_timer.take() {
+                context.cancel_timer(timer_id);
+            }
+            // Taking the lead cancelled the election timer, which watches the new leader from now on.
+            self.reset_election_timer(context);
+        }
+    }
+
+    fn start_election(&mut self, context: &mut Context<Self>) -> Result<()> {
+        self.round += 1;
+        let ballot = Ballot { round: self.round, node_id: context.this_node().node_id.clone() };
+        debug!("Preparing ballot {:?}", ballot);
+        self.role = Role::Candidate;
+        self.leader = None;
+        self.ballot = Some(ballot.clone());
+        self.promised = Some(ballot.clone());
+        self.promises = BTreeMap::from([(context.this_node().node_id.clone(), self.accepted_after(self.applied))]);
+        for node_id in Paxos::peers(context) {
+            Paxos::send(context, node_id, PaxosMessage::Prepare { ballot: ballot.clone(), decided_through: self.applied })?;
+        }
+        self.reset_election_timer(context);
+        self.count_promises(context)
+    }
+
+    fn accepted_after(&self, slot: u64) -> Vec<Accepted> {
+        self.accepted.range(slot + 1..).map(|(_, accepted)| accepted.clone()).collect()
+    }
+
+    /// Takes the lead once a majority has promised, proposing again the commands they accepted with the highest
+    /// ballot, and no-ops in the gaps.
+    fn count_promises(&mut self, context: &mut Context<Self>) -> Result<()> {
+        if self.role != Role::Candidate || !Paxos::is_majority(context, self.promises.len()) {
+            return Ok(());
+        }
+        let Some(ballot) = self.ballot.clone() else { return Ok(()) };
+        debug!("Leading with ballot {:?}", ballot);
+        self.role = Role::Leader;
+        self.leader = Some(context.this_node().node_id.clone());
+        if let Some(timer_id) = self.election_timer.take() {
+            context.cancel_timer(timer_id);
+        }
+        let mut recovered: BTreeMap<u64, Accepted> = BTreeMap::new();
+        for accepted in std::mem::take(&mut self.promises).into_values().flatten() {
+            if recovered.get(&accepted.slot).is_none_or(|known| known.ballot < accepted.ballot) {
+                recovered.insert(accepted.slot, accepted);
+            }
+        }
+        let last_slot = recovered.keys().last().copied().unwrap_or_default().max(self.decided.keys().last().copied().unwrap_or_default());
+        self.next_slot = last_slot.max(self.applied) + 1;
+        for slot in self.applied + 1..self.next_slot {
+            if !self.decided.contains_key(&slot) {
+                let command = recovered.remove(&slot).and_then(|accepted| accepted.command);
+                self.propose(context, slot, command)?;
+            }
+        }
+        self.heartbeat_timer = Some(context.set_timer(HEARTBEAT_INTERVAL, PaxosTimer::Heartbeat));
+        Ok(())
+    }
+
+    /// Runs phase 2 for a slot, with this node accepting first.
+    fn propose(&mut self, context: &mut Context<Self>, slot: u64, command: Option<Value>) -> Result<()> {
+        let Some(ballot) = self.ballot.clone() else { return Ok(()) };
+        self.accepted.insert(slot, Accepted { slot, ballot: ballot.clone(), command: command.clone() });
+        self.proposals.insert(slot, Proposal { command: command.clone(), acks: BTreeSet::from([context.this_node().node_id.clone()]) });
+        for node_id in Paxos::peers(context) {
+            Paxos::send(context, node_id, PaxosMessage::Accept { ballot: ballot.clone(), slot, command: command.clone() })?;
+        }
+        self.count_acks(context, slot)
+    }
+
+    fn count_acks(&mut self, context: &mut Context<Self>, slot: u64) -> Result<()> {
+        let Some(proposal) = self.proposals.get(&slot).filter(|proposal| Paxos::is_majority(context, proposal.acks.len())) else {
+            return Ok(());
+        };
+        let decision = Decision { slot, command: proposal.command.clone() };
+        self.proposals.remove(&slot);
+        let Some(ballot) = self.ballot.clone() else { return Ok(()) };
+        for node_id in Paxos::peers(context) {
+            Paxos::send(context, node_id, PaxosMessage::Decide { ballot: ballot.clone(), decisions: vec![decision.clone()] })?;
+        }
+        self.decide(context, vec![decision])
+    }
+
+    fn decide(&mut self, context: &mut Context<Self>, decisions: Vec<Decision>) -> Result<()> {
+        for decision in decisions {
+            if decision.slot > self.applied {
+                self.decided.insert(decision.slot, decision.command);
+            }
+        }
+        while let Some(command) = self.decided.get(&(self.applied + 1)) {
+            self.applied += 1;
+            let output = match command {
+                Some(command) => Some(self.state_machine.apply(serde_json::from_value(command.clone())?)),
+                None => None,
+            };
+            if let Some(address) = self.pending.remove(&self.applied) {
+                if let Some(output) = output {
+                    context.reply(address, serde_json::to_value(output)?);
+                }
+            }
+        }
+        self.compact()
+    }
+
+    /// Serializes the state machine and drops the applied slots, once enough of them have accumulated.
+    fn compact(&mut self) -> Result<()> {
+        if self.applied - self.snapshot_slot < self.config.threshold.max(1) as u64 {
+            return Ok(());
+        }
+        self.decided = self.decided.split_off(&(self.applied + 1));
+        self.accepted = self.accepted.split_off(&(self.applied + 1));
+        self.snapshot_slot = self.applied;
+        self.snapshot = serde_json::to_string(&self.state_machine)?;
+        // Transfers in progress are restarted with the new snapshot.
+        self.snapshot_offsets.clear();
+        debug!("Compacted the log up to {}", self.snapshot_slot);
+        Ok(())
+    }
+
+    /// Sends the chunk of the snapshot following what the node has acknowledged.
+    fn send_snapshot(&self, context: &mut Context<Self>, node_id: NodeId) -> Result<()> {
+        let offset = self.snapshot_offsets.get(&node_id).copied().unwrap_or_default();
+        let mut end = (offset + self.config.chunk_size.max(1)).min(self.snapshot.len());
+        while !self.snapshot.is_char_boundary(end) {
+            end += 1;
+        }
+        Paxos::send(context, node_id, PaxosMessage::Snapshot {
+            slot: self.snapshot_slot,
+            offset,
+            data: self.snapshot[offset..end].to_string(),
+            done: end == self.snapshot.len(),
+        })
+    }
+
+    /// Buffers a chunk of a snapshot and installs it once complete, returning how many bytes have been received.
+    fn receive_snapshot(&mut self, context: &mut Context<Self>, slot: u64, offset: usize, data: String, done: bool) -> Result<usize> {
+        if offset == 0 {
+            self.incoming_snapshot = Some(IncomingSnapshot { slot, data: String::new() });
+        }
+        let Some(incoming) = self.incoming_snapshot.as_mut().filter(|incoming| incoming.slot == slot) else {
+            return Ok(0);
+        };
+        if offset != incoming.data.len() {
+            return Ok(incoming.data.len());
+        }
+        incoming.data.push_str(&data);
+        let received = incoming.data.len();
+        if !done {
+            return Ok(received);
+        }
+        let Some(incoming) = self.incoming_snapshot.take() else { return Ok(received) };
+        if incoming.slot <= self.applied {
+            return Ok(received);
+        }
+        self.state_machine = serde_json::from_str(&incoming.data)?;
+        self.decided = self.decided.split_off(&(incoming.slot + 1));
+        self.accepted = self.accepted.split_off(&(incoming.slot + 1));
+        self.snapshot = incoming.data;
+        self.snapshot_slot = incoming.slot;
+        self.applied = incoming.slot;
+        debug!("Installed a snapshot up to {}", self.snapshot_slot);
+        // Decisions received while the snapshot was in flight may follow it.
+        self.decide(context, vec![])?;
+        Ok(received)
+    }
+
+    fn on_command(&mut self, context: &mut Context<Self>, command: Value, address: MessageAddress) -> Result<()> {
+        if self.role != Role::Leader {
+            return forward(context, self.leader.clone(), command, address);
+        }
+        if !is_well_formed::<S, Self>(context, &command, &address) {
+            return Ok(());
+        }
+        let slot = self.next_slot;
+        self.next_slot += 1;
+        self.pending.insert(slot, address);
+        self.propose(context, slot, Some(command))
+    }
+
+    /// Sends the decisions a node is missing, and proposals it has not acknowledged yet.
+    fn catch_up(&self, context: &mut Context<Self>, node_id: NodeId, decided_through: u64) -> Result<()> {
+        let Some(ballot) = self.ballot.clone() else { return Ok(()) };
+        if decided_through < self.snapshot_slot {
+            return self.send_snapshot(context, node_id);
+        }
+        let decisions: Vec<Decision> = self.decided.range(decided_through + 1..)
+            .take(MAX_CATCH_UP)
+            .map(|(slot, command)| Decision { slot: *slot, command: command.clone() })
+            .collect();
+        if !decisions.is_empty() {
+            Paxos::send(context, node_id.clone(), PaxosMessage::Decide { ballot: ballot.clone(), decisions })?;
+        }
+        for (slot, proposal) in &self.proposals {
+            if !proposal.acks.contains(&node_id) {
+                Paxos::send(context, node_id.clone(), PaxosMessage::Accept { ballot: ballot.clone(), slot: *slot, command: proposal.command.clone() })?;
+            }
+        }
+        Ok(())
+    }
+
+    fn on_paxos_message(&mut self, context: &mut Context<Self>, message: PaxosMessage, address: MessageAddress) -> Result<()> {
+        match message {
+            PaxosMessage::Prepare { ballot, decided_through } => {
+                self.observe(context, &ballot);
+                if decided_through < self.snapshot_slot {
+                    self.send_snapshot(context, address.src.clone())?;
+                    return Paxos::reply(context, address, PaxosMessage::PrepareOk { ballot, ok: false, accepted: vec![] });
+                }
+                let ok = self.promised.as_ref().is_none_or(|promised| *promised < ballot);
+                if ok {
+                    self.promised = Some(ballot.clone());
+                    self.leader = None;
+                    self.reset_election_timer(context);
+                }
+                let promised = self.promised.clone().unwrap_or(ballot);
+                Paxos::reply(context, address, PaxosMessage::PrepareOk { ballot: promised, ok, accepted: self.accepted_after(decided_through) })?;
+            }
+            PaxosMessage::PrepareOk { ballot, ok, accepted } => {
+                self.observe(context, &ballot);
+                if ok && self.role == Role::Candidate && self.ballot.as_ref() == Some(&ballot) {
+                    self.promises.insert(address.src, accepted);
+                    self.count_promises(context)?;
+                }
+            }
+            PaxosMessage::Accept { ballot, slot, command } => {
+                self.observe(context, &ballot);
+                let ok = self.promised.as_ref().is_none_or(|promised| *promised <= ballot);
+                if ok {
+                    self.promised = Some(ballot.clone());
+                    self.leader = Some(ballot.node_id.clone());
+                    if slot > self.applied {
+                        self.accepted.insert(slot, Accepted { slot, ballot: ballot.clone(), command });
+                    }
+                    self.reset_election_timer(context);
+                }
+                let promised = self.promised.clone().unwrap_or(ballot);
+                Paxos::reply(context, address, PaxosMessage::AcceptOk { ballot: promised, slot, ok })?;
+            }
+            PaxosMessage::AcceptOk { ballot, slot, ok } => {
+                self.observe(context, &ballot);
+                if ok && self.role == Role::Leader && self.ballot.as_ref() == Some(&ballot) {
+                    if let Some(proposal) = self.proposals.get_mut(&slot) {
+                        proposal.acks.insert(address.src);
+                    }
+                    self.count_acks(context, slot)?;
+                }
+            }
+            PaxosMessage::Decide { ballot, decisions } => {
+                self.observe(context, &ballot);
+                if self.promised.as_ref().is_none_or(|promised| *promised <= ballot) {
+                    self.promised = Some(ballot.clone());
+                    self.leader = Some(ballot.node_id.clone());
+                    self.reset_election_timer(context);
+                }
+                self.decide(context, decisions)?;
+                Paxos::reply(context, address, PaxosMessage::DecideOk { decided_through: self.applied })?;
+            }
+            PaxosMessage::DecideOk { decided_through } => {
+                if self.role == Role::Leader {
+                    self.catch_up(context, address.src, decided_through)?;
+                }
+            }
+            PaxosMessage::Snapshot { slot, offset, data, done } => {
+                let received = self.receive_snapshot(context, slot, offset, data, done)?;
+                Paxos::reply(context, address, PaxosMessage::SnapshotOk { slot, offset: received })?;
+            }
+            PaxosMessage::SnapshotOk { slot, offset } => {
+                if slot == self.snapshot_slot {
+                    if offset >= self.snapshot.len() {
+                        self.snapshot_offsets.remove(&address.src);
+                    } else {
+                        self.snapshot_offsets.insert(address.src.clone(), offset);
+                        self.send_snapshot(context, address.src)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<S> Actor for Paxos<S>
+    where S: StateMachine {
+    type Msg = Value;
+    type TimerKey = PaxosTimer;
+
+    fn new(context: &mut Context<Self>) -> Result<Self> {
+        let mut paxos = Paxos {
+            config: S::snapshot_config(),
+            state_machine: S::default(),
+            role: Role::Follower,
+            round: 0,
+            ballot: None,
+            leader: None,
+            promised: None,
+            accepted: BTreeMap::new(),
+            decided: BTreeMap::new(),
+            applied: 0,
+            snapshot_slot: 0,
+            snapshot: String::new(),
+            incoming_snapshot: None,
+            snapshot_offsets: HashMap::new(),
+            promises: BTreeMap::new(),
+            next_slot: 1,
+            proposals: BTreeMap::new(),
+            pending: HashMap::new(),
+            election_timer: None,
+            heartbeat_timer: None,
+        };
+        paxos.reset_election_timer(context);
+        Ok(paxos)
+    }
+
+    fn on_request(&mut self, context: &mut Context<Self>, request: Message<Self::Msg>) -> Result<()> {
+        let (body, address) = request.body_and_address();
+        if PaxosMessage::type_names().contains(&body.type_name()) {
+            self.on_paxos_message(context, serde_json::from_value(body)?, address)
+        } else {
+            self.on_command(context, body, address)
+        }
+    }
+
+    fn on_reply(&mut self, context: &mut Context<Self>, reply: Message<Self::Msg>) -> Result<()> {
+        let (body, address) = reply.body_and_address();
+        if !PaxosMessage::type_names().contains(&body.type_name()) {
+            // Such as the reply to a forwarded command which arrives after its deadline.
+            warn!("Dropping an unexpected reply from '{}': '{}'", address.src, body);
+            return Ok(());
+        }
+        self.on_paxos_message(context, serde_json::from_value(body)?, address)
+    }
+
+    fn on_timeout(&mut self, context: &mut Context<Self>, timer_key: Self::TimerKey) -> Result<()> {
+        match timer_key {
+            PaxosTimer::Election => {
+                self.election_timer = None;
+                if self.role != Role::Leader {
+                    self.start_election(context)?;
+                }
+            }
+            PaxosTimer::Heartbeat => {
+                let Some(ballot) = self.ballot.clone() else { return Ok(()) };
+                for node_id in Paxos::peers(context) {
+                    Paxos::send(context, node_id, PaxosMessage::Decide { ballot: ballot.clone(), decisions: vec![] })?;
+                }
+                self.heartbeat_timer = Some(context.set_timer(HEARTBEAT_INTERVAL, PaxosTimer::Heartbeat));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
+
+    use serde::{Deserialize, Serialize};
+    use serde_json::{json, Value};
+
+    use crate::common::check::linearizability::check;
+    use crate::common::context::ActorCell;
+    use crate::common::driver::{ClientDriver, DriverConfig, Transport};
+    use crate::common::error::Result;
+    use crate::common::message::message::{Message, MessageAddress};
+    use crate::common::message::{MessageId, NodeId};
+    use crate::common::paxos::{Ballot, Paxos, PaxosMessage, Role};
+    use crate::common::runner::RunnerAction;
+    use crate::common::simulator::{Simulator, SimulatorConfig};
+    use crate::common::state_machine::{SnapshotConfig, StateMachine};
+    use crate::common::this_node::ThisNode;
+    use crate::common::workload::lin_kv::LinKvWorkload;
+
+    #[derive(Serialize, Deserialize, Default)]
+    struct Registers {
+        values: HashMap<String, Value>,
+    }
+
+    impl StateMachine for Registers {
+        type Command = Value;
+        type Output = Value;
+
+        fn snapshot_config() -> SnapshotConfig {
+            SnapshotConfig { threshold: 20, chunk_size: 16 }
+        }
+
+        fn apply(&mut self, command: Value) -> Value {
+            let key = command["key"].to_string();
+            match (command["type"].as_str(), self.values.get(&key)) {
+                (Some("read"), Some(value)) => json!({"type": "read_ok", "value": value}),
+                (Some("write"), _) => {
+                    self.values.insert(key, command["value"].clone());
+                    json!({"type": "write_ok"})
+                }
+                (Some("cas"), Some(value)) if *value == command["from"] => {
+                    self.values.insert(key, command["to"].clone());
+                    json!({"type": "cas_ok"})
+                }
+                (Some("cas"), Some(_)) => json!({"type": "error", "code": 22}),
+                _ => json!({"type": "error", "code": 20}),
+            }
+        }
+    }
+
+    #[test]
+    fn should_serve_linearizable_registers_from_a_stable_leader() -> Result<()> {
+        let mut simulator = Simulator::<Paxos<Registers>>::new(SimulatorConfig {
+            node_count: 3,
+            latency: Duration::from_millis(5),
+            jitter: Duration::from_millis(10),
+            ..SimulatorConfig::default()
+        })?;
+        let history = ClientDriver::new(DriverConfig {
+            concurrency: 5,
+            rate: 100.0,
+            time_limit: Duration::from_secs(3),
+            ..DriverConfig::default()
+        }, LinKvWorkload).run(&mut simulator)?;
+
+        let report = check(&history)?;
+
+        assert!(report.valid);
+        assert!(history.operations().iter().any(|operation| operation.is_ok()));
+        let nodes: Vec<_> = simulator.node_ids().iter().map(|node_id| simulator.node(node_id).unwrap()).collect();
+        assert_eq!(nodes.iter().filter(|paxos| paxos.role() == Role::Leader).count(), 1);
+        assert!(nodes.iter().all(|paxos| paxos.applied() > 0 && paxos.applied() + 5 >= nodes[0].applied()));
+        assert!(nodes.iter().all(|paxos| paxos.snapshot_slot() > 0 && paxos.log_len() <= 4 * Registers::snapshot_config().threshold));
+        Ok(())
+    }
+
+    #[test]
+    fn should_send_snapshot_to_node_missing_compacted_slots() -> Result<()> {
+        let mut simulator = Simulator::<Paxos<Registers>>::new(SimulatorConfig {
+            node_count: 3,
+            latency: Duration::from_millis(5),
+            ..SimulatorConfig::default()
+        })?;
+        simulator.run_until(Duration::from_secs(1))?;
+        let node_ids = simulator.node_ids();
+        let leader = node_ids.iter().find(|node_id| simulator.node(node_id).unwrap().role() == Role::Leader).unwrap().clone();
+        let lagging = node_ids.iter().find(|node_id| **node_id != leader).unwrap().clone();
+        simulator.partition(vec![vec![lagging.clone()], node_ids.iter().filter(|node_id| **node_id != lagging).cloned().collect()]);
+
+        for msg_id in 0..50 {
+            simulator.send(Message::new_request(MessageAddress {
+                src: NodeId::from("c1"),
+                dest: leader.clone(),
+                msg_id: Some(MessageId(msg_id)),
+                in_reply_to: None,
+            }, json!({"type": "write", "key": msg_id % 3, "value": msg_id})))?;
+            let reply = simulator.receive(Duration::from_secs(2 + msg_id))?.unwrap();
+            assert_eq!(reply.body(), &json!({"type": "write_ok"}));
+        }
+        simulator.heal();
+        simulator.run_until(Duration::from_secs(55))?;
+
+        let (leader, lagging) = (simulator.node(&leader).unwrap(), simulator.node(&lagging).unwrap());
+        assert!(lagging.snapshot_slot() >= 20);
+        assert_eq!(lagging.applied(), leader.applied());
+        assert_eq!(lagging.state_machine().values, leader.state_machine().values);
+        Ok(())
+    }
+
+    #[test]
+    fn should_drop_forwarded_reply_arriving_after_its_deadline() -> Result<()> {
+        let now = Instant::now();
+        let this_node = ThisNode::new(NodeId::from("n0"), vec![NodeId::from("n0"), NodeId::from("n1")]);
+        let (mut cell, _) = ActorCell::<Paxos<Registers>>::new(this_node, 0, now)?;
+        let address = |src: &str, msg_id| MessageAddress {
+            src: NodeId::from(src),
+            dest: NodeId::from("n0"),
+            msg_id: Some(MessageId(msg_id)),
+            in_reply_to: None,
+        };
+        let decide = PaxosMessage::Decide { ballot: Ballot { round: 1, node_id: NodeId::from("n1") }, decisions: vec![] };
+        cell.on_json_message(Message::new_request(address("n1", 1), serde_json::to_value(decide)?), now)?;
+
+        let actions = cell.on_json_message(Message::new_request(address("c1", 1), json!({"type": "write", "key": 1, "value": 5})), now)?;
+        let [RunnerAction::SetTimer { timer_key: deadline, .. }, RunnerAction::SendMessage(forwarded)] = actions.as_slice() else {
+            panic!("Expected a forwarded command with its deadline")
+        };
+        assert_eq!(forwarded.dest(), &NodeId::from("n1"));
+        cell.on_timeout(*deadline, now + Duration::from_secs(1))?;
+
+        let late = Message::new_reply(forwarded.address().to_reply_address(), json!({"type": "write_ok"}));
+        assert!(cell.on_json_message(late, now + Duration::from_secs(2))?.is_empty());
+        Ok(())
+    }
+}
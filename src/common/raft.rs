@@ -0,0 +1,794 @@
+use std::collections::{BTreeSet, HashMap};
+use std::time::Duration;
+
+use log::{debug, warn};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::common::actor::Actor;
+use crate::common::context::{Context, TimerId};
+use crate::common::error::Error::UnexpectedError;
+use crate::common::error::Result;
+use crate::common::message::message::{Message, MessageAddress};
+use crate::common::message::{MessageKind, NodeId};
+use crate::common::state_machine::{abandon, forward, is_well_formed, SnapshotConfig, StateMachine};
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(50);
+const ELECTION_TIMEOUT_MS: std::ops::Range<u64> = 150..300;
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LogEntry {
+    pub term: u64,
+    pub command: Value,
+}
+
+#[derive(Serialize, Deserialize, MessageKind, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RaftMessage {
+    RequestVote {
+        term: u64,
+        last_log_index: usize,
+        last_log_term: u64,
+    },
+    RequestVoteOk {
+        term: u64,
+        vote_granted: bool,
+    },
+    AppendEntries {
+        term: u64,
+        prev_log_index: usize,
+        prev_log_term: u64,
+        entries: Vec<LogEntry>,
+        leader_commit: usize,
+    },
+    AppendEntriesOk {
+        term: u64,
+        success: bool,
+        match_index: usize,
+    },
+    /// A chunk of the snapshot of every entry up to `last_included_index`, for followers missing compacted entries.
+    InstallSnapshot {
+        term: u64,
+        last_included_index: usize,
+        last_included_term: u64,
+        offset: usize,
+        data: String,
+        done: bool,
+    },
+    /// Acknowledges the first `offset` bytes of a snapshot.
+    InstallSnapshotOk {
+        term: u64,
+        last_included_index: usize,
+        offset: usize,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+#[derive(Debug)]
+pub enum RaftTimer {
+    Election,
+    Heartbeat,
+}
+
+/// The snapshot a follower is receiving.
+struct IncomingSnapshot {
+    last_included_index: usize,
+    last_included_term: u64,
+    data: String,
+}
+
+/// Replicates a `StateMachine` with Raft. Every request which is not part of the protocol is a command: the leader
+/// appends it to the log and replies with its output once it is committed and applied, followers forward client
+/// commands to the leader they know of. Reads go through the log too, which keeps them linearizable.
+///
+/// Every `threshold` applied entries, the state machine is serialized and the log truncated. Followers
+/// which need compacted entries receive the snapshot instead, in chunks.
+pub struct Raft<S>
+    where S: StateMachine {
+    config: SnapshotConfig,
+    state_machine: S,
+    role: Role,
+    current_term: u64,
+    voted_for: Option<NodeId>,
+    leader: Option<NodeId>,
+    /// The entries after the snapshot: entry `i` of the protocol lives at `log[i - snapshot_index - 1]`.
+    log: Vec<LogEntry>,
+    snapshot_index: usize,
+    snapshot_term: u64,
+    /// The serialized state machine after applying every entry up to `snapshot_index`.
+    snapshot: String,
+    incoming_snapshot: Option<IncomingSnapshot>,
+    /// How many bytes of the snapshot each lagging follower has acknowledged.
+    snapshot_offsets: HashMap<NodeId, usize>,
+    commit_index: usize,
+    last_applied: usize,
+    votes: BTreeSet<NodeId>,
+    next_index: HashMap<NodeId, usize>,
+    match_index: HashMap<NodeId, usize>,
+    /// The requests the leader answers once the entry at their index is applied.
+    pending: HashMap<usize, MessageAddress>,
+    election_timer: Option<TimerId>,
+    heartbeat_timer: Option<TimerId>,
+}
+
+impl<S> Raft<S>
+    where S: StateMachine {
+    pub fn state_machine(&self) -> &S {
+        &self.state_machine
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    pub fn term(&self) -> u64 {
+        self.current_term
+    }
+
+    pub fn leader(&self) -> Option<&NodeId> {
+        self.leader.as_ref()
+    }
+
+    pub fn commit_index(&self) -> usize {
+        self.commit_index
+    }
+
+    pub fn snapshot_index(&self) -> usize {
+        self.snapshot_index
+    }
+
+    /// The number of entries kept in memory.
+    pub fn log_len(&self) -> usize {
+        self.log.len()
+    }
+
+    fn peers(context: &Context<Self>) -> Vec<NodeId> {
+        let this_node = context.this_node();
+        this_node.node_ids.iter().filter(|node_id| **node_id != this_node.node_id).cloned().collect()
+    }
+
+    fn is_majority(context: &Context<Self>, count: usize) -> bool {
+        count * 2 > context.this_node().node_ids.len()
+    }
+
+    fn last_index(&self) -> usize {
+        self.snapshot_index + self.log.len()
+    }
+
+    fn last_log_term(&self) -> u64 {
+        self.log.last().map_or(self.snapshot_term, |entry| entry.term)
+    }
+
+    /// The term of an entry, unless it has been compacted or does not exist yet.
+    fn term_at(&self, index: usize) -> Option<u64> {
+        if index == self.snapshot_index {
+            Some(self.snapshot_term)
+        } else if index > self.snapshot_index {
+            self.entry(index).map(|entry| entry.term)
+        } else {
+            None
+        }
+    }
+
+    fn entry(&self, index: usize) -> Option<&LogEntry> {
+        index.checked_sub(self.snapshot_index + 1).and_then(|position| self.log.get(position))
+    }
+
+    fn reset_election_timer(&mut self, context: &mut Context<Self>) {
+        if let Some(timer_id) = self.election_timer.take() {
+            context.cancel_timer(timer_id);
+        }
+        let timeout = Duration::from_millis(context.rng().gen_range(ELECTION_TIMEOUT_MS));
+        self.election_timer = Some(context.set_timer(timeout, RaftTimer::Election));
+    }
+
+    fn become_follower(&mut self, context: &mut Context<Self>, term: u64) {
+        if term > self.current_term {
+            self.current_term = term;
+            self.voted_for = None;
+        }
+        if self.role == Role::Leader {
+            debug!("Stepping down in term {}", term);
+            // Uncommitted entries may be overwritten by the next leader, so they cannot be answered anymore.
+            abandon(context, std::mem::take(&mut self.pending));
+            if let Some(timer_id) = self.heartbeat_timer.take() {
+                context.cancel_timer(timer_id);
+            }
+        }
+        self.role = Role::Follower;
+        self.reset_election_timer(context);
+    }
+
+    fn start_election(&mut self, context: &mut Context<Self>) -> Result<()> {
+        self.current_term += 1;
+        self.role = Role::Candidate;
+        self.leader = None;
+        self.voted_for = Some(context.this_node().node_id.clone());
+        self.votes = BTreeSet::from([context.this_node().node_id.clone()]);
+        debug!("Starting election for term {}", self.current_term);
+        for node_id in Raft::peers(context) {
+            context.send(node_id, serde_json::to_value(RaftMessage::RequestVote {
+                term: self.current_term,
+                last_log_index: self.last_index(),
+                last_log_term: self.last_log_term(),
+            })?);
+        }
+        self.reset_election_timer(context);
+        self.count_votes(context)
+    }
+
+    fn count_votes(&mut self, context: &mut Context<Self>) -> Result<()> {
+        if self.role != Role::Candidate || !Raft::is_majority(context, self.votes.len()) {
+            return Ok(());
+        }
+        debug!("Leading term {}", self.current_term);
+        self.role = Role::Leader;
+        self.leader = Some(context.this_node().node_id.clone());
+        if let Some(timer_id) = self.election_timer.take() {
+            context.cancel_timer(timer_id);
+        }
+        let peers = Raft::peers(context);
+        self.next_index = peers.iter().map(|node_id| (node_id.clone(), self.last_index() + 1)).collect();
+        self.match_index = peers.iter().map(|node_id| (node_id.clone(), 0)).collect();
+        self.snapshot_offsets.clear();
+        self.replicate(context)?;
+        self.heartbeat_timer = Some(context.set_timer(HEARTBEAT_INTERVAL, RaftTimer::Heartbeat));
+        Ok(())
+    }
+
+    fn replicate(&mut self, context: &mut Context<Self>) -> Result<()> {
+        for node_id in Raft::peers(context) {
+            self.send_append_entries(context, node_id)?;
+        }
+        Ok(())
+    }
+
+    fn send_append_entries(&self, context: &mut Context<Self>, node_id: NodeId) -> Result<()> {
+        let next_index = self.next_index.get(&node_id).copied().unwrap_or(self.last_index() + 1);
+        if next_index <= self.snapshot_index {
+            return self.send_snapshot(context, node_id);
+        }
+        let prev_log_index = next_index - 1;
+        context.send(node_id, serde_json::to_value(RaftMessage::AppendEntries {
+            term: self.current_term,
+            prev_log_index,
+            prev_log_term: self.term_at(prev_log_index).unwrap_or_default(),
+            entries: self.log[prev_log_index - self.snapshot_index..].to_vec(),
+            leader_commit: self.commit_index,
+        })?);
+        Ok(())
+    }
+
+    /// Sends the chunk of the snapshot following what the follower has acknowledged.
+    fn send_snapshot(&self, context: &mut Context<Self>, node_id: NodeId) -> Result<()> {
+        let offset = self.snapshot_offsets.get(&node_id).copied().unwrap_or_default();
+        let mut end = (offset + self.config.chunk_size.max(1)).min(self.snapshot.len());
+        while !self.snapshot.is_char_boundary(end) {
+            end += 1;
+        }
+        context.send(node_id, serde_json::to_value(RaftMessage::InstallSnapshot {
+            term: self.current_term,
+            last_included_index: self.snapshot_index,
+            last_included_term: self.snapshot_term,
+            offset,
+            data: self.snapshot[offset..end].to_string(),
+            done: end == self.snapshot.len(),
+        })?);
+        Ok(())
+    }
+
+    /// Serializes the state machine and drops the applied entries, once enough of them have accumulated.
+    fn compact(&mut self) -> Result<()> {
+        if self.last_applied - self.snapshot_index < self.config.threshold.max(1) {
+            return Ok(());
+        }
+        self.snapshot_term = self.term_at(self.last_applied).unwrap_or_default();
+        self.log.drain(..self.last_applied - self.snapshot_index);
+        self.snapshot_index = self.last_applied;
+        self.snapshot = serde_json::to_string(&self.state_machine)?;
+        // Transfers in progress are restarted with the new snapshot.
+        self.snapshot_offsets.clear();
+        debug!("Compacted the log up to {}", self.snapshot_index);
+        Ok(())
+    }
+
+    /// Commits the latest entry of the current term stored on a majority, and everything before it.
+    fn advance_commit_index(&mut self, context: &mut Context<Self>) -> Result<()> {
+        for index in (self.commit_index + 1..=self.last_index()).rev() {
+            let replicas = 1 + self.match_index.values().filter(|match_index| **match_index >= index).count();
+            if self.term_at(index) == Some(self.current_term) && Raft::is_majority(context, replicas) {
+                self.commit_index = index;
+                break;
+            }
+        }
+        self.apply_committed(context)
+    }
+
+    fn apply_committed(&mut self, context: &mut Context<Self>) -> Result<()> {
+        while self.last_applied < self.commit_index {
+            self.last_applied += 1;
+            let entry = self.entry(self.last_applied)
+                .ok_or_else(|| UnexpectedError(format!("Missing committed entry {}", self.last_applied)))?;
+            let output = self.state_machine.apply(serde_json::from_value(entry.command.clone())?);
+            if let Some(address) = self.pending.remove(&self.last_applied) {
+                context.reply(address, serde_json::to_value(output)?);
+            }
+        }
+        self.compact()
+    }
+
+    fn on_command(&mut self, context: &mut Context<Self>, command: Value, address: MessageAddress) -> Result<()> {
+        if self.role == Role::Leader {
+            if !is_well_formed::<S, Self>(context, &command, &address) {
+                return Ok(());
+            }
+            self.log.push(LogEntry { term: self.current_term, command });
+            self.pending.insert(self.last_index(), address);
+            self.replicate(context)?;
+            return self.advance_commit_index(context);
+        }
+        forward(context, self.leader.clone(), command, address)
+    }
+
+    fn on_raft_message(&mut self, context: &mut Context<Self>, message: RaftMessage, address: MessageAddress) -> Result<()> {
+        match message {
+            RaftMessage::RequestVote { term, last_log_index, last_log_term } => {
+                if term > self.current_term {
+                    self.become_follower(context, term);
+                }
+                let up_to_date = (last_log_term, last_log_index) >= (self.last_log_term(), self.last_index());
+                let vote_granted = term == self.current_term
+                    && self.voted_for.as_ref().is_none_or(|voted_for| *voted_for == address.src)
+                    && up_to_date;
+                if vote_granted {
+                    self.voted_for = Some(address.src.clone());
+                    self.reset_election_timer(context);
+                }
+                context.reply(address, serde_json::to_value(RaftMessage::RequestVoteOk { term: self.current_term, vote_granted })?);
+            }
+            RaftMessage::RequestVoteOk { term, vote_granted } => {
+                if term > self.current_term {
+                    self.become_follower(context, term);
+                } else if term == self.current_term && vote_granted {
+                    self.votes.insert(address.src);
+                    self.count_votes(context)?;
+                }
+            }
+            RaftMessage::AppendEntries { term, prev_log_index, prev_log_term, entries, leader_commit } => {
+                if term < self.current_term {
+                    context.reply(address, serde_json::to_value(RaftMessage::AppendEntriesOk { term: self.current_term, success: false, match_index: 0 })?);
+                    return Ok(());
+                }
+                self.become_follower(context, term);
+                self.leader = Some(address.src.clone());
+                let match_index = prev_log_index + entries.len();
+                // Compacted entries are committed, so they match the leader's: only the ones after the snapshot are checked.
+                let compacted = self.snapshot_index.saturating_sub(prev_log_index).min(entries.len());
+                let (prev_log_index, prev_log_term) = match compacted {
+                    0 => (prev_log_index, prev_log_term),
+                    _ => (prev_log_index + compacted, entries[compacted - 1].term),
+                };
+                let consistent = prev_log_index < self.snapshot_index || self.term_at(prev_log_index) == Some(prev_log_term);
+                if !consistent {
+                    context.reply(address, serde_json::to_value(RaftMessage::AppendEntriesOk { term: self.current_term, success: false, match_index: 0 })?);
+                    return Ok(());
+                }
+                for (offset, entry) in entries.into_iter().skip(compacted).enumerate() {
+                    let index = prev_log_index + offset + 1;
+                    if self.entry(index).is_some_and(|existing| existing.term != entry.term) {
+                        self.log.truncate(index - self.snapshot_index - 1);
+                    }
+                    if index > self.last_index() {
+                        self.log.push(entry);
+                    }
+                }
+                if leader_commit > self.commit_index {
+                    self.commit_index = self.commit_index.max(leader_commit.min(match_index));
+                    self.apply_committed(context)?;
+                }
+                context.reply(address, serde_json::to_value(RaftMessage::AppendEntriesOk { term: self.current_term, success: true, match_index })?);
+            }
+            RaftMessage::AppendEntriesOk { term, success, match_index } => {
+                if term > self.current_term {
+                    self.become_follower(context, term);
+                } else if self.role == Role::Leader && term == self.current_term {
+                    if success {
+                        let known = self.match_index.entry(address.src.clone()).or_default();
+                        *known = (*known).max(match_index);
+                        self.next_index.insert(address.src, match_index + 1);
+                        self.advance_commit_index(context)?;
+                    } else {
+                        let next_index = self.next_index.entry(address.src.clone()).or_insert(1);
+                        *next_index = (*next_index - 1).max(1);
+                        self.send_append_entries(context, address.src)?;
+                    }
+                }
+            }
+            RaftMessage::InstallSnapshot { term, last_included_index, last_included_term, offset, data, done } => {
+                if term < self.current_term {
+                    context.reply(address, serde_json::to_value(RaftMessage::InstallSnapshotOk { term: self.current_term, last_included_index, offset: 0 })?);
+                    return Ok(());
+                }
+                self.become_follower(context, term);
+                self.leader = Some(address.src.clone());
+                let received = self.receive_snapshot(last_included_index, last_included_term, offset, data, done)?;
+                context.reply(address, serde_json::to_value(RaftMessage::InstallSnapshotOk { term: self.current_term, last_included_index, offset: received })?);
+            }
+            RaftMessage::InstallSnapshotOk { term, last_included_index, offset } => {
+                if term > self.current_term {
+                    self.become_follower(context, term);
+                } else if self.role == Role::Leader && term == self.current_term && last_included_index == self.snapshot_index {
+                    if offset >= self.snapshot.len() {
+                        self.snapshot_offsets.remove(&address.src);
+                        let known = self.match_index.entry(address.src.clone()).or_default();
+                        *known = (*known).max(last_included_index);
+                        self.next_index.insert(address.src.clone(), last_included_index + 1);
+                    } else {
+                        self.snapshot_offsets.insert(address.src.clone(), offset);
+                    }
+                    self.send_append_entries(context, address.src)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Buffers a chunk of a snapshot and installs it once complete, returning how many bytes have been received.
+    fn receive_snapshot(&mut self, last_included_index: usize, last_included_term: u64, offset: usize, data: String, done: bool) -> Result<usize> {
+        if offset == 0 {
+            self.incoming_snapshot = Some(IncomingSnapshot { last_included_index, last_included_term, data: String::new() });
+        }
+        let Some(incoming) = self.incoming_snapshot.as_mut().filter(|incoming| incoming.last_included_index == last_included_index) else {
+            return Ok(0);
+        };
+        if offset != incoming.data.len() {
+            return Ok(incoming.data.len());
+        }
+        incoming.data.push_str(&data);
+        let received = incoming.data.len();
+        if !done {
+            return Ok(received);
+        }
+        let Some(incoming) = self.incoming_snapshot.take() else { return Ok(received) };
+        if incoming.last_included_index <= self.snapshot_index {
+            return Ok(received);
+        }
+        // Entries following the snapshot are kept if the log agrees with it, everything else is replaced.
+        if self.term_at(incoming.last_included_index) == Some(incoming.last_included_term) {
+            self.log.drain(..incoming.last_included_index - self.snapshot_index);
+        } else {
+            self.log.clear();
+        }
+        self.state_machine = serde_json::from_str(&incoming.data)?;
+        self.snapshot = incoming.data;
+        self.snapshot_index = incoming.last_included_index;
+        self.snapshot_term = incoming.last_included_term;
+        self.commit_index = self.commit_index.max(self.snapshot_index);
+        self.last_applied = self.snapshot_index;
+        debug!("Installed a snapshot up to {}", self.snapshot_index);
+        Ok(received)
+    }
+}
+
+impl<S> Actor for Raft<S>
+    where S: StateMachine {
+    type Msg = Value;
+    type TimerKey = RaftTimer;
+
+    fn new(context: &mut Context<Self>) -> Result<Self> {
+        let state_machine = S::default();
+        let mut raft = Raft {
+            config: S::snapshot_config(),
+            snapshot: serde_json::to_string(&state_machine)?,
+            state_machine,
+            role: Role::Follower,
+            current_term: 0,
+            voted_for: None,
+            leader: None,
+            log: vec![],
+            snapshot_index: 0,
+            snapshot_term: 0,
+            incoming_snapshot: None,
+            snapshot_offsets: HashMap::new(),
+            commit_index: 0,
+            last_applied: 0,
+            votes: BTreeSet::new(),
+            next_index: HashMap::new(),
+            match_index: HashMap::new(),
+            pending: HashMap::new(),
+            election_timer: None,
+            heartbeat_timer: None,
+        };
+        raft.reset_election_timer(context);
+        Ok(raft)
+    }
+
+    fn on_request(&mut self, context: &mut Context<Self>, request: Message<Self::Msg>) -> Result<()> {
+        let (body, address) = request.body_and_address();
+        if RaftMessage::type_names().contains(&body.type_name()) {
+            self.on_raft_message(context, serde_json::from_value(body)?, address)
+        } else {
+            self.on_command(context, body, address)
+        }
+    }
+
+    fn on_reply(&mut self, context: &mut Context<Self>, reply: Message<Self::Msg>) -> Result<()> {
+        let (body, address) = reply.body_and_address();
+        if !RaftMessage::type_names().contains(&body.type_name()) {
+            // Such as the reply to a forwarded command which arrives after its deadline.
+            warn!("Dropping an unexpected reply from '{}': '{}'", address.src, body);
+            return Ok(());
+        }
+        self.on_raft_message(context, serde_json::from_value(body)?, address)
+    }
+
+    fn on_timeout(&mut self, context: &mut Context<Self>, timer_key: Self::TimerKey) -> Result<()> {
+        match timer_key {
+            RaftTimer::Election => {
+                self.election_timer = None;
+                if self.role != Role::Leader {
+                    self.start_election(context)?;
+                }
+            }
+            RaftTimer::Heartbeat => {
+                self.replicate(context)?;
+                self.heartbeat_timer = Some(context.set_timer(HEARTBEAT_INTERVAL, RaftTimer::Heartbeat));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
+
+    use serde::{Deserialize, Serialize};
+    use serde_json::{json, Value};
+
+    use crate::common::check::linearizability::check;
+    use crate::common::context::ActorCell;
+    use crate::common::driver::{ClientDriver, DriverConfig, Transport};
+    use crate::common::error::Result;
+    use crate::common::message::message::{Message, MessageAddress};
+    use crate::common::message::{MessageId, NodeId};
+    use crate::common::raft::{LogEntry, Raft, RaftMessage, Role};
+    use crate::common::state_machine::{SnapshotConfig, StateMachine};
+    use crate::common::runner::RunnerAction;
+    use crate::common::simulator::{Simulator, SimulatorConfig};
+    use crate::common::this_node::ThisNode;
+    use crate::common::workload::lin_kv::LinKvWorkload;
+
+    #[derive(Serialize, Deserialize, Default)]
+    struct Registers {
+        values: HashMap<String, Value>,
+    }
+
+    impl StateMachine for Registers {
+        type Command = Value;
+        type Output = Value;
+
+        fn snapshot_config() -> SnapshotConfig {
+            SnapshotConfig { threshold: 20, chunk_size: 16 }
+        }
+
+        fn apply(&mut self, command: Value) -> Value {
+            let key = command["key"].to_string();
+            match (command["type"].as_str(), self.values.get(&key)) {
+                (Some("read"), Some(value)) => json!({"type": "read_ok", "value": value}),
+                (Some("write"), _) => {
+                    self.values.insert(key, command["value"].clone());
+                    json!({"type": "write_ok"})
+                }
+                (Some("cas"), Some(value)) if *value == command["from"] => {
+                    self.values.insert(key, command["to"].clone());
+                    json!({"type": "cas_ok"})
+                }
+                (Some("cas"), Some(_)) => json!({"type": "error", "code": 22}),
+                _ => json!({"type": "error", "code": 20}),
+            }
+        }
+    }
+
+    #[test]
+    fn should_elect_a_single_leader() -> Result<()> {
+        let mut simulator = Simulator::<Raft<Registers>>::new(SimulatorConfig {
+            node_count: 5,
+            latency: Duration::from_millis(5),
+            ..SimulatorConfig::default()
+        })?;
+
+        simulator.run_until(Duration::from_secs(2))?;
+
+        let nodes: Vec<_> = simulator.node_ids().iter().map(|node_id| simulator.node(node_id).unwrap()).collect();
+        let leaders: Vec<_> = nodes.iter().filter(|raft| raft.role() == Role::Leader).collect();
+        assert_eq!(leaders.len(), 1);
+        assert!(nodes.iter().all(|raft| raft.term() == leaders[0].term() && raft.leader().is_some()));
+        Ok(())
+    }
+
+    #[test]
+    fn should_serve_linearizable_registers() -> Result<()> {
+        let mut simulator = Simulator::<Raft<Registers>>::new(SimulatorConfig {
+            node_count: 3,
+            latency: Duration::from_millis(5),
+            jitter: Duration::from_millis(10),
+            ..SimulatorConfig::default()
+        })?;
+        let history = ClientDriver::new(DriverConfig {
+            concurrency: 5,
+            rate: 100.0,
+            time_limit: Duration::from_secs(3),
+            ..DriverConfig::default()
+        }, LinKvWorkload).run(&mut simulator)?;
+
+        let report = check(&history)?;
+
+        assert!(report.valid);
+        assert!(history.operations().iter().any(|operation| operation.is_ok()));
+        for node_id in simulator.node_ids() {
+            let raft = simulator.node(&node_id).unwrap();
+            assert!(raft.snapshot_index() > 0);
+            assert!(raft.log_len() <= 2 * Registers::snapshot_config().threshold);
+        }
+        Ok(())
+    }
+
+    #[derive(Deserialize, Debug)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum CounterCommand {
+        Add { delta: i64 },
+    }
+
+    #[derive(Serialize, Deserialize, Default)]
+    struct Counter {
+        value: i64,
+    }
+
+    impl StateMachine for Counter {
+        type Command = CounterCommand;
+        type Output = Value;
+
+        fn apply(&mut self, CounterCommand::Add { delta }: CounterCommand) -> Value {
+            self.value += delta;
+            json!({"type": "add_ok", "value": self.value})
+        }
+    }
+
+    fn from_client(msg_id: u64, dest: &NodeId, body: Value) -> Message<Value> {
+        Message::new_request(MessageAddress {
+            src: NodeId::from("c1"),
+            dest: dest.clone(),
+            msg_id: Some(MessageId(msg_id)),
+            in_reply_to: None,
+        }, body)
+    }
+
+    fn leader_of<S>(simulator: &Simulator<Raft<S>>) -> Option<NodeId>
+        where S: StateMachine {
+        simulator.node_ids().into_iter().find(|node_id| simulator.node(node_id).unwrap().role() == Role::Leader)
+    }
+
+    #[test]
+    fn should_refuse_malformed_commands() -> Result<()> {
+        let mut simulator = Simulator::<Raft<Counter>>::new(SimulatorConfig {
+            node_count: 3,
+            latency: Duration::from_millis(5),
+            ..SimulatorConfig::default()
+        })?;
+        simulator.run_until(Duration::from_secs(1))?;
+        let leader = leader_of(&simulator).unwrap();
+
+        simulator.send(from_client(1, &leader, json!({"type": "add", "delta": "one"})))?;
+        let refused = simulator.receive(Duration::from_secs(2))?.unwrap();
+        simulator.send(from_client(2, &leader, json!({"type": "add", "delta": 1})))?;
+        let applied = simulator.receive(Duration::from_secs(3))?.unwrap();
+
+        assert_eq!(refused.body()["code"], json!(12));
+        assert_eq!(applied.body(), &json!({"type": "add_ok", "value": 1}));
+        assert_eq!(leader_of(&simulator), Some(leader));
+        Ok(())
+    }
+
+    #[test]
+    fn should_answer_pending_clients_when_stepping_down() -> Result<()> {
+        let mut simulator = Simulator::<Raft<Counter>>::new(SimulatorConfig {
+            node_count: 3,
+            latency: Duration::from_millis(5),
+            ..SimulatorConfig::default()
+        })?;
+        simulator.run_until(Duration::from_secs(1))?;
+        let leader = leader_of(&simulator).unwrap();
+        let others: Vec<_> = simulator.node_ids().into_iter().filter(|node_id| *node_id != leader).collect();
+        simulator.partition(vec![vec![leader.clone()], others]);
+
+        simulator.send(from_client(1, &leader, json!({"type": "add", "delta": 1})))?;
+        assert!(simulator.receive(Duration::from_secs(3))?.is_none());
+        simulator.heal();
+        let reply = simulator.receive(Duration::from_secs(5))?.unwrap();
+
+        assert_eq!(reply.body()["code"], json!(0));
+        assert_ne!(leader_of(&simulator), Some(leader));
+        Ok(())
+    }
+
+    fn from_leader(msg_id: u64, message: RaftMessage) -> Result<Message<Value>> {
+        Ok(Message::new_request(MessageAddress {
+            src: NodeId::from("n1"),
+            dest: NodeId::from("n0"),
+            msg_id: Some(MessageId(msg_id)),
+            in_reply_to: None,
+        }, serde_json::to_value(message)?))
+    }
+
+    #[test]
+    fn should_drop_forwarded_reply_arriving_after_its_deadline() -> Result<()> {
+        let now = Instant::now();
+        let this_node = ThisNode::new(NodeId::from("n0"), vec![NodeId::from("n0"), NodeId::from("n1")]);
+        let (mut cell, _) = ActorCell::<Raft<Registers>>::new(this_node, 0, now)?;
+        cell.on_json_message(from_leader(1, RaftMessage::AppendEntries {
+            term: 1,
+            prev_log_index: 0,
+            prev_log_term: 0,
+            entries: vec![],
+            leader_commit: 0,
+        })?, now)?;
+
+        let actions = cell.on_json_message(from_client(1, &NodeId::from("n0"), json!({"type": "write", "key": 1, "value": 5})), now)?;
+        let [RunnerAction::SetTimer { timer_key: deadline, .. }, RunnerAction::SendMessage(forwarded)] = actions.as_slice() else {
+            panic!("Expected a forwarded command with its deadline")
+        };
+        assert_eq!(forwarded.dest(), &NodeId::from("n1"));
+        let actions = cell.on_timeout(*deadline, now + Duration::from_secs(1))?;
+        let [RunnerAction::SendMessage(timed_out)] = actions.as_slice() else { panic!("Expected a reply to the client") };
+        assert_eq!(timed_out.body()["code"], json!(0));
+
+        let late = Message::new_reply(forwarded.address().to_reply_address(), json!({"type": "write_ok"}));
+        assert!(cell.on_json_message(late, now + Duration::from_secs(2))?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn should_install_snapshot_sent_in_chunks() -> Result<()> {
+        let this_node = ThisNode::new(NodeId::from("n0"), vec![NodeId::from("n0"), NodeId::from("n1")]);
+        let (mut cell, _) = ActorCell::<Raft<Registers>>::new(this_node, 0, Instant::now())?;
+        let snapshot = json!({"values": {"1": 5}}).to_string();
+        let (first, second) = snapshot.split_at(10);
+
+        for (msg_id, offset, data, done) in [(1, 0, first, false), (2, 10, second, true)] {
+            let actions = cell.on_json_message(from_leader(msg_id, RaftMessage::InstallSnapshot {
+                term: 1,
+                last_included_index: 30,
+                last_included_term: 1,
+                offset,
+                data: data.to_string(),
+                done,
+            })?, Instant::now())?;
+            let reply = actions.iter().find_map(|action| match action {
+                RunnerAction::SendMessage(message) => Some(message.body().clone()),
+                _ => None,
+            }).unwrap();
+            assert_eq!(reply["offset"], json!(offset + data.len()));
+        }
+        cell.on_json_message(from_leader(3, RaftMessage::AppendEntries {
+            term: 1,
+            prev_log_index: 30,
+            prev_log_term: 1,
+            entries: vec![LogEntry { term: 1, command: json!({"type": "write", "key": 2, "value": 7}) }],
+            leader_commit: 31,
+        })?, Instant::now())?;
+
+        let raft = cell.actor();
+        assert_eq!(raft.snapshot_index(), 30);
+        assert_eq!(raft.commit_index(), 31);
+        assert_eq!(raft.state_machine().values, HashMap::from([("1".to_string(), json!(5)), ("2".to_string(), json!(7))]));
+        Ok(())
+    }
+}
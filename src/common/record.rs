@@ -17,18 +17,12 @@ impl<A> Eq for Record<A> {}
 
 impl<A> PartialOrd<Self> for Record<A> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        if self.timestamp < other.timestamp {
-            Some(Ordering::Greater)
-        } else if self.timestamp == other.timestamp {
-            Some(Ordering::Equal)
-        } else {
-            Some(Ordering::Less)
-        }
+        Some(self.cmp(other))
     }
 }
 
 impl<A> Ord for Record<A> {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.partial_cmp(other).unwrap()
+        other.timestamp.cmp(&self.timestamp)
     }
 }
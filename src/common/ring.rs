@@ -0,0 +1,155 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use crate::common::message::NodeId;
+
+const VIRTUAL_NODES: usize = 64;
+
+/// A consistent hash ring: every node places `virtual_nodes` tokens on it, and owns the keys hashed between each of
+/// its tokens and the one before. Adding or removing a node only moves the keys of its own tokens.
+#[derive(Clone, Debug)]
+pub struct Ring {
+    virtual_nodes: usize,
+    tokens: BTreeMap<u64, NodeId>,
+    node_ids: BTreeSet<NodeId>,
+}
+
+impl Ring {
+    pub fn new(node_ids: &[NodeId]) -> Ring {
+        Ring::with_virtual_nodes(node_ids, VIRTUAL_NODES)
+    }
+
+    pub fn with_virtual_nodes(node_ids: &[NodeId], virtual_nodes: usize) -> Ring {
+        let mut ring = Ring { virtual_nodes, tokens: BTreeMap::new(), node_ids: BTreeSet::new() };
+        for node_id in node_ids {
+            ring.add(node_id.clone());
+        }
+        ring
+    }
+
+    pub fn node_ids(&self) -> &BTreeSet<NodeId> {
+        &self.node_ids
+    }
+
+    pub fn add(&mut self, node_id: NodeId) {
+        if self.node_ids.insert(node_id.clone()) {
+            for index in 0..self.virtual_nodes {
+                self.tokens.insert(hash(&(&node_id, index)), node_id.clone());
+            }
+        }
+    }
+
+    pub fn remove(&mut self, node_id: &NodeId) {
+        if self.node_ids.remove(node_id) {
+            self.tokens.retain(|_, owner| owner != node_id);
+        }
+    }
+
+    /// Rebalances onto the given membership, adding and removing nodes one by one so keys move minimally.
+    pub fn set_nodes(&mut self, node_ids: &[NodeId]) {
+        let removed: Vec<NodeId> = self.node_ids.iter().filter(|node_id| !node_ids.contains(node_id)).cloned().collect();
+        for node_id in removed {
+            self.remove(&node_id);
+        }
+        for node_id in node_ids {
+            self.add(node_id.clone());
+        }
+    }
+
+    pub fn owner<K>(&self, key: &K) -> Option<&NodeId>
+        where K: Hash {
+        let token = hash(key);
+        self.tokens.range(token..).chain(self.tokens.range(..token)).map(|(_, node_id)| node_id).next()
+    }
+
+    /// The first `count` distinct nodes from the key clockwise, its owner first.
+    pub fn replicas<K>(&self, key: &K, count: usize) -> Vec<NodeId>
+        where K: Hash {
+        let mut walk = self.walk(key);
+        walk.truncate(count);
+        walk
+    }
+
+    /// Every node, in the order the ring is walked from the key: its replicas first, then the nodes standing in for
+    /// them.
+    pub fn walk<K>(&self, key: &K) -> Vec<NodeId>
+        where K: Hash {
+        let token = hash(key);
+        let mut nodes: Vec<NodeId> = Vec::with_capacity(self.node_ids.len());
+        for node_id in self.tokens.range(token..).chain(self.tokens.range(..token)).map(|(_, node_id)| node_id) {
+            if !nodes.contains(node_id) {
+                nodes.push(node_id.clone());
+                if nodes.len() == self.node_ids.len() {
+                    break;
+                }
+            }
+        }
+        nodes
+    }
+}
+
+/// Hashed with fixed keys, so every node places the tokens alike.
+fn hash<T>(value: &T) -> u64
+    where T: Hash + ?Sized {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::common::message::NodeId;
+    use crate::common::ring::Ring;
+
+    const KEYS: u64 = 10_000;
+
+    fn node_ids(count: usize) -> Vec<NodeId> {
+        (0..count).map(|idx| NodeId::from(format!("n{}", idx))).collect()
+    }
+
+    #[test]
+    fn should_spread_keys_evenly() {
+        let ring = Ring::new(&node_ids(5));
+        let mut loads: HashMap<NodeId, u64> = HashMap::new();
+
+        for key in 0..KEYS {
+            *loads.entry(ring.owner(&key).unwrap().clone()).or_default() += 1;
+        }
+
+        assert_eq!(loads.len(), 5);
+        assert!(loads.values().all(|load| (KEYS / 5 * 7 / 10..KEYS / 5 * 13 / 10).contains(load)), "Uneven loads: '{:?}'", loads);
+    }
+
+    #[test]
+    fn should_move_keys_to_added_node_only() {
+        let before = Ring::new(&node_ids(4));
+        let mut after = before.clone();
+        after.set_nodes(&node_ids(5));
+        let added = NodeId::from("n4");
+
+        let moved: Vec<u64> = (0..KEYS).filter(|key| before.owner(key) != after.owner(key)).collect();
+
+        assert!(moved.iter().all(|key| after.owner(key) == Some(&added)));
+        assert!((KEYS / 5 * 7 / 10..KEYS / 5 * 13 / 10).contains(&(moved.len() as u64)), "Moved {} keys", moved.len());
+        after.remove(&added);
+        assert!((0..KEYS).all(|key| before.owner(&key) == after.owner(&key)));
+    }
+
+    #[test]
+    fn should_look_up_distinct_replicas() {
+        let ring = Ring::new(&node_ids(5));
+
+        for key in 0..100 {
+            let replicas = ring.replicas(&key, 3);
+            assert_eq!(replicas.len(), 3);
+            assert_eq!(ring.owner(&key), replicas.first());
+            let mut walk = ring.walk(&key);
+            assert_eq!(walk[..3], replicas[..]);
+            walk.sort();
+            assert_eq!(walk, node_ids(5));
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::common::actor::Actor;
+use crate::common::context::{Context, TimerId};
+use crate::common::error::Error::UnexpectedError;
+use crate::common::error::Result;
+use crate::common::message::message::{Message, MessageAddress};
+use crate::common::message::{MessageId, MessageKind};
+
+/// How long an RPC waits for its reply before its callback gets a timeout.
+pub const RPC_TIMEOUT: Duration = Duration::from_millis(1000);
+const TIMEOUT: u64 = 0;
+
+/// An outbound request body, serialized with its `type`, whose successful reply deserializes into `Response`.
+pub trait Request: Serialize {
+    type Response: DeserializeOwned;
+}
+
+/// The body of a Maelstrom `error` reply.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct RpcError {
+    pub code: u64,
+    #[serde(default)]
+    pub text: Option<String>,
+}
+
+impl RpcError {
+    /// The error of a request which got no reply in time, and may or may not have taken effect.
+    pub fn timeout() -> RpcError {
+        RpcError { code: TIMEOUT, text: Some("RPC timed out".to_string()) }
+    }
+}
+
+pub type RpcResult<A> = std::result::Result<A, RpcError>;
+
+pub(crate) type Callback<A> = Box<dyn FnOnce(&mut A, &mut Context<A>, RpcResult<Value>) -> Result<()>>;
+
+/// Splits a reply into its body or the error it carries.
+pub(crate) fn decode(reply: Value) -> Result<RpcResult<Value>> {
+    if reply.type_name() == "error" {
+        Ok(Err(serde_json::from_value(reply)?))
+    } else {
+        Ok(Ok(reply))
+    }
+}
+
+/// Tracks outbound requests by `msg_id`, so each reply is decoded into the `Response` of the request it answers.
+/// Every request has a deadline timer, after which its callback gets `RpcError::timeout`.
+pub(crate) struct Rpc<A>
+    where A: Actor {
+    pending: HashMap<MessageId, (Callback<A>, TimerId)>,
+}
+
+impl<A> Rpc<A>
+    where A: Actor {
+    pub fn new() -> Rpc<A> {
+        Rpc {
+            pending: HashMap::new(),
+        }
+    }
+
+    pub fn call<R, F>(&mut self, address: MessageAddress, request: R, deadline: TimerId, callback: F) -> Result<Message<Value>>
+        where R: Request,
+              F: FnOnce(&mut A, &mut Context<A>, RpcResult<R::Response>) -> Result<()> + 'static {
+        let body = serde_json::to_value(request)?;
+        let msg_id = address.msg_id.clone()
+            .ok_or_else(|| UnexpectedError(format!("Request without msg_id: '{:?}'", address)))?;
+        self.pending.insert(msg_id, (Box::new(move |actor, context, reply| {
+            let response = match reply {
+                Ok(body) => Ok(serde_json::from_value(body)?),
+                Err(error) => Err(error),
+            };
+            callback(actor, context, response)
+        }), deadline));
+        Ok(Message::new_request(address, body))
+    }
+
+    /// Takes the callback of the request a reply answers, if any, with its deadline timer.
+    pub fn take(&mut self, address: &MessageAddress) -> Option<(Callback<A>, TimerId)> {
+        address.in_reply_to.as_ref().and_then(|in_reply_to| self.pending.remove(in_reply_to))
+    }
+
+    /// Whether a reply answers a request still waiting for it.
+    pub fn is_pending(&self, address: &MessageAddress) -> bool {
+        address.in_reply_to.as_ref().is_some_and(|in_reply_to| self.pending.contains_key(in_reply_to))
+    }
+
+    /// Takes the callback of a request whose deadline has passed.
+    pub fn expire(&mut self, msg_id: &MessageId) -> Option<Callback<A>> {
+        self.pending.remove(msg_id).map(|(callback, _)| callback)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use serde::{Deserialize, Serialize};
+    use serde_json::{json, Value};
+
+    use crate::common::actor::Actor;
+    use crate::common::context::{ActorCell, Context};
+    use crate::common::error::Result;
+    use crate::common::message::message::{Message, MessageAddress};
+    use crate::common::message::{MessageId, NodeId};
+    use crate::common::rpc::{Request, RpcError, RPC_TIMEOUT};
+    use crate::common::runner::RunnerAction;
+    use crate::common::this_node::ThisNode;
+
+    #[derive(Serialize)]
+    #[serde(tag = "type", rename = "read")]
+    struct KvRead {
+        key: u64,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct KvReadOk {
+        value: u64,
+    }
+
+    impl Request for KvRead {
+        type Response = KvReadOk;
+    }
+
+    #[derive(Default)]
+    struct ReaderActor {
+        values: Vec<std::result::Result<KvReadOk, RpcError>>,
+    }
+
+    impl Actor for ReaderActor {
+        type Msg = Value;
+        type TimerKey = ();
+
+        fn new(_: &mut Context<Self>) -> Result<Self> {
+            Ok(ReaderActor::default())
+        }
+
+        fn on_request(&mut self, context: &mut Context<Self>, _: Message<Self::Msg>) -> Result<()> {
+            context.rpc(NodeId::from("lin-kv"), KvRead { key: 1 }, |actor: &mut ReaderActor, _, response| {
+                actor.values.push(response);
+                Ok(())
+            })
+        }
+    }
+
+    fn read(cell: &mut ActorCell<ReaderActor>) -> Result<MessageAddress> {
+        let request = Message::new_request(MessageAddress {
+            src: NodeId::from("c1"),
+            dest: NodeId::from("n0"),
+            msg_id: Some(MessageId(1)),
+            in_reply_to: None,
+        }, json!({"type": "read"}));
+        let actions = cell.on_message(request, Instant::now())?;
+        let [RunnerAction::SetTimer { .. }, RunnerAction::SendMessage(message)] = actions.as_slice() else { panic!("Expected a deadline and a message") };
+        assert_eq!(message.body(), &json!({"type": "read", "key": 1}));
+        Ok(message.address())
+    }
+
+    fn new_cell() -> Result<ActorCell<ReaderActor>> {
+        Ok(ActorCell::new(ThisNode::new(NodeId::from("n0"), vec![NodeId::from("n0")]), 0, Instant::now())?.0)
+    }
+
+    #[test]
+    fn should_decode_replies_into_request_responses() -> Result<()> {
+        let mut cell = new_cell()?;
+        let first = read(&mut cell)?;
+        let second = read(&mut cell)?;
+
+        cell.on_message(Message::new_reply(second.to_reply_address(), json!({"type": "error", "code": 20, "text": "not found"})), Instant::now())?;
+        cell.on_message(Message::new_reply(first.to_reply_address(), json!({"type": "read_ok", "value": 5})), Instant::now())?;
+
+        assert_eq!(cell.actor().values, vec![
+            Err(RpcError { code: 20, text: Some("not found".to_string()) }),
+            Ok(KvReadOk { value: 5 }),
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn should_time_out_unanswered_requests_once() -> Result<()> {
+        let mut cell = new_cell()?;
+        let request = Message::new_request(MessageAddress {
+            src: NodeId::from("c1"),
+            dest: NodeId::from("n0"),
+            msg_id: Some(MessageId(1)),
+            in_reply_to: None,
+        }, json!({"type": "read"}));
+        let actions = cell.on_message(request, Instant::now())?;
+        let [RunnerAction::SetTimer { delay, timer_key }, RunnerAction::SendMessage(message)] = actions.as_slice() else { panic!("Expected a deadline and a message") };
+        assert_eq!(*delay, RPC_TIMEOUT);
+
+        cell.on_timeout(*timer_key, Instant::now() + RPC_TIMEOUT)?;
+        cell.on_message(Message::new_reply(message.address().to_reply_address(), json!({"type": "read_ok", "value": 5})), Instant::now())?;
+
+        assert_eq!(cell.actor().values, vec![Err(RpcError::timeout())]);
+        Ok(())
+    }
+
+    #[test]
+    fn should_drop_replies_to_unknown_requests() -> Result<()> {
+        let mut cell = new_cell()?;
+        let address = MessageAddress { src: NodeId::from("n0"), dest: NodeId::from("lin-kv"), msg_id: Some(MessageId(7)), in_reply_to: None };
+
+        let actions = cell.on_message(Message::new_reply(address.to_reply_address(), json!({"type": "read_ok", "value": 5})), Instant::now())?;
+
+        assert!(actions.is_empty());
+        assert_eq!(cell.actor().values, vec![]);
+        Ok(())
+    }
+}
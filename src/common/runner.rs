@@ -1,14 +1,18 @@
 use std::cmp::max;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::ops::{Add};
 use std::time::{Duration, Instant};
 
-use log::{debug, trace};
+use log::{debug, info, trace};
+use serde_json::Value;
 use stderrlog::{ColorChoice, LogLevelNum, Timestamp};
 
 use crate::common::actor::Actor;
 use crate::common::console::Console;
-use crate::common::error::Error::UnexpectedMessage;
+use crate::common::context::{Action, ActorCell, TimerId};
+use crate::common::error::Error::{Config, UnexpectedMessage};
 use crate::common::message::init::InitMessage;
+use crate::common::message::{MessageId, MessageKind, NodeId};
 use crate::common::message::message::{Message, MessageAddress};
 use crate::common::timer::Timer;
 
@@ -16,8 +20,16 @@ use super::error::Result;
 use super::this_node::ThisNode;
 
 const MINIMUM_READ_DURATION: Duration = Duration::from_millis(1000);
+const SEED_FLAG: &str = "--seed";
+const SEED_VAR: &str = "GOSSIP_GLOMERS_SEED";
 
 pub fn run_actor<A>() -> Result<()>
+    where A: Actor {
+    run_actor_with(MiddlewareStack::<A>::new())
+}
+
+/// Runs the actor with every inbound message and outbound action passing through the middlewares.
+pub fn run_actor_with<A>(mut middlewares: MiddlewareStack<A>) -> Result<()>
     where A: Actor {
     stderrlog::new()
         .verbosity(LogLevelNum::Debug)
@@ -26,54 +38,129 @@ pub fn run_actor<A>() -> Result<()>
         .init()
         .unwrap();
 
+    let seed = seed(std::env::args().skip(1), std::env::var(SEED_VAR).ok())?;
     let console = Console::new();
     let this_node = init(&console)?;
-    let mut actor = A::new(this_node)?;
+    let mut timer: Timer<Scheduled> = Timer::new();
 
-    let mut timer: Timer<A::TimerKey> = Timer::new();
+    debug!("Seeding the RNG of '{}' with '{}'", this_node.node_id, seed);
+    let now = Instant::now();
+    let (mut cell, actions) = ActorCell::<A>::new(this_node, seed, now)?;
+    for action in actions {
+        dispatch_action(&console, &mut timer, &mut middlewares, now, 0, action)?;
+    }
 
     loop {
         let now = Instant::now();
         let expired_timers = timer.remove_expired_timers(now);
         for expired_timer in expired_timers {
-            trace!("Got expired timer: '{:?}'", expired_timer);
             let now = Instant::now();
-            for action in actor.on_timeout(expired_timer, now)? {
-                execute_action::<A>(&console, &mut timer, now, action)?;
+            match expired_timer {
+                Scheduled::Timer(timer_id) => {
+                    trace!("Got expired timer: '{:?}'", timer_id);
+                    for action in cell.on_timeout(timer_id, now)? {
+                        dispatch_action(&console, &mut timer, &mut middlewares, now, 0, action)?;
+                    }
+                }
+                Scheduled::Inbound { next, message } => {
+                    for action in dispatch_message(&mut cell, &mut timer, &mut middlewares, now, next, message)? {
+                        dispatch_action(&console, &mut timer, &mut middlewares, now, 0, action)?;
+                    }
+                }
+                Scheduled::Outbound { next, action } => {
+                    dispatch_action(&console, &mut timer, &mut middlewares, now, next, action)?;
+                }
             }
         }
 
         let now = Instant::now();
         let duration_until_next_timer = timer.duration_until_next_timer(now);
         trace!("Duration until next timer: '{:?}'", duration_until_next_timer);
-        if let Some(message) = console.read::<Message<A::Msg>>(max(duration_until_next_timer, MINIMUM_READ_DURATION))? {
+        if let Some(message) = console.read::<Message<Value>>(max(duration_until_next_timer, MINIMUM_READ_DURATION))? {
             debug!("Got message: '{:?}'", message);
             let now = Instant::now();
-            for action in actor.on_request(message, now)? {
-                execute_action::<A>(&console, &mut timer, now, action)?;
+            for action in dispatch_message(&mut cell, &mut timer, &mut middlewares, now, 0, message)? {
+                dispatch_action(&console, &mut timer, &mut middlewares, now, 0, action)?;
             }
         }
     }
 }
 
-fn execute_action<A>(console: &Console,
-                     timer: &mut Timer<A::TimerKey>,
-                     now: Instant,
-                     action: RunnerAction<A::Msg, A::TimerKey>) -> Result<()>
+enum Scheduled {
+    Timer(TimerId),
+    Inbound {
+        next: usize,
+        message: Message<Value>,
+    },
+    Outbound {
+        next: usize,
+        action: Action,
+    },
+}
+
+fn dispatch_message<A>(cell: &mut ActorCell<A>,
+                       timer: &mut Timer<Scheduled>,
+                       middlewares: &mut MiddlewareStack<A>,
+                       now: Instant,
+                       from: usize,
+                       message: Message<Value>) -> Result<Vec<Action>>
+    where A: Actor {
+    match middlewares.inbound(from, message, now) {
+        Staged::Ready(message) => cell.on_json_message(message, now),
+        Staged::Delayed { delay, next, value } => {
+            trace!("Delaying inbound message by '{:?}': '{:?}'", delay, value);
+            timer.add_timer(now.add(delay), Scheduled::Inbound { next, message: value });
+            Ok(vec![])
+        }
+        Staged::Dropped => Ok(vec![]),
+    }
+}
+
+fn dispatch_action<A>(console: &Console,
+                      timer: &mut Timer<Scheduled>,
+                      middlewares: &mut MiddlewareStack<A>,
+                      now: Instant,
+                      from: usize,
+                      action: Action) -> Result<()>
     where A: Actor {
-    match action {
-        RunnerAction::SendMessage(message) => {
+    match middlewares.outbound(from, action, now) {
+        Staged::Ready(RunnerAction::SendMessage(message)) => {
             debug!("Writing message: '{:?}'", message);
             console.write(&message)?;
         }
-        RunnerAction::SetTimer { delay, timer_key } => {
+        Staged::Ready(RunnerAction::SetTimer { delay, timer_key }) => {
             trace!("Adding timer. Delay: '{:?}', key: '{:?}'", delay, timer_key);
-            timer.add_timer(now.add(delay), timer_key);
+            timer.add_timer(now.add(delay), Scheduled::Timer(timer_key));
+        }
+        Staged::Delayed { delay, next, value } => {
+            trace!("Delaying outbound action by '{:?}': '{:?}'", delay, value);
+            timer.add_timer(now.add(delay), Scheduled::Outbound { next, action: value });
         }
+        Staged::Dropped => {}
     }
     Ok(())
 }
 
+/// Reads `<flag> <value>` from the command line, falling back to the given environment variable.
+pub fn option(flag: &str, var: &str) -> Result<Option<String>> {
+    flag_value(std::env::args().skip(1), flag, std::env::var(var).ok())
+}
+
+fn flag_value<I>(mut args: I, flag: &str, var: Option<String>) -> Result<Option<String>>
+    where I: Iterator<Item=String> {
+    match args.position(|arg| arg == flag) {
+        Some(_) => Ok(Some(args.next().ok_or_else(|| Config(format!("Missing value for '{}'", flag)))?)),
+        None => Ok(var),
+    }
+}
+
+/// Reads the seed from `--seed <seed>`, falling back to the `GOSSIP_GLOMERS_SEED` variable and then to 0.
+fn seed<I>(args: I, var: Option<String>) -> Result<u64>
+    where I: Iterator<Item=String> {
+    let value = flag_value(args, SEED_FLAG, var)?;
+    value.map_or(Ok(0), |value| value.parse().map_err(|_| Config(format!("Invalid seed '{}'", value))))
+}
+
 fn init(console: &Console) -> Result<ThisNode> {
     let message: Message<InitMessage> = console.read_blocking()?;
     debug!("Got init request: '{:?}'", message);
@@ -90,7 +177,7 @@ fn init(console: &Console) -> Result<ThisNode> {
     }
 }
 
-
+#[derive(Debug)]
 pub enum RunnerAction<A, B> {
     SendMessage(Message<A>),
     SetTimer {
@@ -110,3 +197,271 @@ pub fn send<A, B>(address: MessageAddress, value: A) -> RunnerAction<A, B> {
 pub fn set_timer<A, B>(delay: Duration, timer_key: B) -> RunnerAction<A, B> {
     RunnerAction::SetTimer { delay, timer_key }
 }
+
+pub enum Intercept<A> {
+    Pass(A),
+    Delay(Duration, A),
+    Drop,
+}
+
+/// Inspects, transforms, delays or drops what goes in and out of an actor. Middlewares see inbound messages
+/// as received, before they are decoded for the actor, and outbound actions before the runner executes them,
+/// in the order of the stack.
+pub trait Middleware<A>
+    where A: Actor {
+    fn inbound(&mut self, message: Message<Value>, _now: Instant) -> Intercept<Message<Value>> {
+        Intercept::Pass(message)
+    }
+
+    fn outbound(&mut self, action: Action, _now: Instant) -> Intercept<Action> {
+        Intercept::Pass(action)
+    }
+}
+
+pub(crate) enum Staged<A> {
+    Ready(A),
+    Delayed {
+        delay: Duration,
+        next: usize,
+        value: A,
+    },
+    Dropped,
+}
+
+pub struct MiddlewareStack<A>
+    where A: Actor {
+    middlewares: Vec<Box<dyn Middleware<A>>>,
+}
+
+impl<A> Default for MiddlewareStack<A>
+    where A: Actor {
+    fn default() -> Self {
+        MiddlewareStack {
+            middlewares: vec![],
+        }
+    }
+}
+
+impl<A> MiddlewareStack<A>
+    where A: Actor {
+    pub fn new() -> MiddlewareStack<A> {
+        MiddlewareStack::default()
+    }
+
+    pub fn with<M>(mut self, middleware: M) -> MiddlewareStack<A>
+        where M: Middleware<A> + 'static {
+        self.middlewares.push(Box::new(middleware));
+        self
+    }
+
+    /// Runs the message through the middlewares starting at `from`. A delayed message resumes after the middleware
+    /// which delayed it.
+    pub(crate) fn inbound(&mut self, from: usize, mut message: Message<Value>, now: Instant) -> Staged<Message<Value>> {
+        for (idx, middleware) in self.middlewares.iter_mut().enumerate().skip(from) {
+            message = match middleware.inbound(message, now) {
+                Intercept::Pass(message) => message,
+                Intercept::Delay(delay, message) => return Staged::Delayed { delay, next: idx + 1, value: message },
+                Intercept::Drop => return Staged::Dropped,
+            };
+        }
+        Staged::Ready(message)
+    }
+
+    pub(crate) fn outbound(&mut self, from: usize, mut action: Action, now: Instant) -> Staged<Action> {
+        for (idx, middleware) in self.middlewares.iter_mut().enumerate().skip(from) {
+            action = match middleware.outbound(action, now) {
+                Intercept::Pass(action) => action,
+                Intercept::Delay(delay, action) => return Staged::Delayed { delay, next: idx + 1, value: action },
+                Intercept::Drop => return Staged::Dropped,
+            };
+        }
+        Staged::Ready(action)
+    }
+}
+
+/// How many of the latest `msg_id`s of every source `Dedup` remembers.
+const DEDUP_WINDOW: usize = 1024;
+
+/// Drops requests whose `src` and `msg_id` were already seen, such as retransmissions. Only the latest
+/// `DEDUP_WINDOW` ids of every source are kept, and ids older than all of them are dropped too.
+#[derive(Default)]
+pub struct Dedup {
+    seen: HashMap<NodeId, BTreeSet<MessageId>>,
+}
+
+impl<A> Middleware<A> for Dedup
+    where A: Actor {
+    fn inbound(&mut self, message: Message<Value>, _now: Instant) -> Intercept<Message<Value>> {
+        let Some(msg_id) = message.address().msg_id else { return Intercept::Pass(message) };
+        let seen = self.seen.entry(message.src().clone()).or_default();
+        let expired = seen.len() >= DEDUP_WINDOW && seen.first().is_some_and(|oldest| msg_id < *oldest);
+        if expired || !seen.insert(msg_id) {
+            return Intercept::Drop;
+        }
+        if seen.len() > DEDUP_WINDOW {
+            seen.pop_first();
+        }
+        Intercept::Pass(message)
+    }
+}
+
+/// Drops the inbound messages matching a predicate.
+pub struct DropWhere<F>(pub F);
+
+impl<A, F> Middleware<A> for DropWhere<F>
+    where A: Actor,
+          F: FnMut(&Message<Value>) -> bool {
+    fn inbound(&mut self, message: Message<Value>, _now: Instant) -> Intercept<Message<Value>> {
+        if (self.0)(&message) {
+            Intercept::Drop
+        } else {
+            Intercept::Pass(message)
+        }
+    }
+}
+
+/// Delays every outbound message by a fixed duration.
+pub struct DelayOutbound(pub Duration);
+
+impl<A> Middleware<A> for DelayOutbound
+    where A: Actor {
+    fn outbound(&mut self, action: Action, _now: Instant) -> Intercept<Action> {
+        match action {
+            RunnerAction::SendMessage(_) => Intercept::Delay(self.0, action),
+            action => Intercept::Pass(action),
+        }
+    }
+}
+
+/// Counts the messages going in and out by their `type`, logging the counters every `period` messages.
+pub struct CountMessages {
+    period: u64,
+    total: u64,
+    counters: BTreeMap<String, u64>,
+}
+
+impl CountMessages {
+    pub fn new(period: u64) -> CountMessages {
+        CountMessages {
+            period,
+            total: 0,
+            counters: BTreeMap::new(),
+        }
+    }
+
+    fn count(&mut self, direction: &str, type_name: &str) {
+        *self.counters.entry(format!("{}.{}", direction, type_name)).or_default() += 1;
+        self.total += 1;
+        if self.total.is_multiple_of(self.period) {
+            info!("Message counters: '{:?}'", self.counters);
+        }
+    }
+}
+
+impl<A> Middleware<A> for CountMessages
+    where A: Actor {
+    fn inbound(&mut self, message: Message<Value>, _now: Instant) -> Intercept<Message<Value>> {
+        self.count("in", message.body().type_name());
+        Intercept::Pass(message)
+    }
+
+    fn outbound(&mut self, action: Action, _now: Instant) -> Intercept<Action> {
+        if let RunnerAction::SendMessage(message) = &action {
+            self.count("out", message.body().type_name());
+        }
+        Intercept::Pass(action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use serde_json::{json, Value};
+
+    use crate::common::actor::Actor;
+    use crate::common::context::Context;
+    use crate::common::error::Result;
+    use crate::common::message::{MessageId, NodeId};
+    use crate::common::message::message::{Message, MessageAddress};
+    use crate::common::runner::{Dedup, DelayOutbound, DropWhere, MiddlewareStack, RunnerAction, seed, Staged, DEDUP_WINDOW};
+
+    fn args(args: &[&str]) -> impl Iterator<Item=String> {
+        args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>().into_iter()
+    }
+
+    #[test]
+    fn should_read_seed_from_flag_then_variable() -> Result<()> {
+        assert_eq!(seed(args(&["--seed", "42"]), Some("7".to_string()))?, 42);
+        assert_eq!(seed(args(&[]), Some("7".to_string()))?, 7);
+        assert_eq!(seed(args(&[]), None)?, 0);
+        assert!(seed(args(&["--seed"]), None).is_err());
+        assert!(seed(args(&[]), Some("seven".to_string())).is_err());
+        Ok(())
+    }
+
+    struct NoopActor;
+
+    impl Actor for NoopActor {
+        type Msg = Value;
+        type TimerKey = ();
+
+        fn new(_: &mut Context<Self>) -> Result<Self> {
+            Ok(NoopActor)
+        }
+
+        fn on_request(&mut self, _: &mut Context<Self>, _: Message<Self::Msg>) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn message(src: &str, msg_id: u64) -> Message<Value> {
+        Message::new_request(MessageAddress {
+            src: NodeId::from(src),
+            dest: NodeId::from("n0"),
+            msg_id: Some(MessageId(msg_id)),
+            in_reply_to: None,
+        }, json!({"type": "read"}))
+    }
+
+    #[test]
+    fn should_drop_duplicates_and_matching_messages() {
+        let mut middlewares = MiddlewareStack::<NoopActor>::new()
+            .with(Dedup::default())
+            .with(DropWhere(|message: &Message<Value>| message.src() == &NodeId::from("n2")));
+        let now = Instant::now();
+
+        assert!(matches!(middlewares.inbound(0, message("c1", 1), now), Staged::Ready(_)));
+        assert!(matches!(middlewares.inbound(0, message("c1", 1), now), Staged::Dropped));
+        assert!(matches!(middlewares.inbound(0, message("c2", 1), now), Staged::Ready(_)));
+        assert!(matches!(middlewares.inbound(0, message("n2", 1), now), Staged::Dropped));
+    }
+
+    #[test]
+    fn should_only_remember_the_latest_ids_of_every_source() {
+        let mut middlewares = MiddlewareStack::<NoopActor>::new().with(Dedup::default());
+        let now = Instant::now();
+
+        for msg_id in 1..=DEDUP_WINDOW as u64 + 1 {
+            assert!(matches!(middlewares.inbound(0, message("c1", msg_id), now), Staged::Ready(_)));
+        }
+
+        assert!(matches!(middlewares.inbound(0, message("c1", 1), now), Staged::Dropped));
+        assert!(matches!(middlewares.inbound(0, message("c1", DEDUP_WINDOW as u64), now), Staged::Dropped));
+        assert!(matches!(middlewares.inbound(0, message("c2", 1), now), Staged::Ready(_)));
+    }
+
+    #[test]
+    fn should_resume_delayed_actions_after_the_delaying_middleware() {
+        let mut middlewares = MiddlewareStack::<NoopActor>::new()
+            .with(DelayOutbound(Duration::from_millis(5)))
+            .with(DelayOutbound(Duration::from_millis(7)));
+        let now = Instant::now();
+
+        let Staged::Delayed { delay, next, value } = middlewares.outbound(0, RunnerAction::SendMessage(message("n0", 1)), now) else { panic!("Expected a delay") };
+        assert_eq!((delay, next), (Duration::from_millis(5), 1));
+        let Staged::Delayed { delay, next, value } = middlewares.outbound(next, value, now) else { panic!("Expected a delay") };
+        assert_eq!((delay, next), (Duration::from_millis(7), 2));
+        assert!(matches!(middlewares.outbound(next, value, now), Staged::Ready(RunnerAction::SendMessage(_))));
+    }
+}
@@ -0,0 +1,441 @@
+use std::collections::{BinaryHeap, BTreeMap, HashMap, VecDeque};
+use std::ops::Add;
+use std::time::{Duration, Instant};
+
+use log::{debug, trace};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use serde_json::{json, Value};
+
+use crate::common::actor::Actor;
+use crate::common::context::{Action, ActorCell, Metrics, TimerId};
+use crate::common::driver::Transport;
+use crate::common::error::Result;
+use crate::common::message::message::Message;
+use crate::common::message::{NodeId, ServiceKind};
+use crate::common::record::Record;
+use crate::common::runner::{MiddlewareStack, RunnerAction, Staged};
+use crate::common::this_node::ThisNode;
+
+const KEY_DOES_NOT_EXIST: u64 = 20;
+const PRECONDITION_FAILED: u64 = 22;
+const NOT_SUPPORTED: u64 = 10;
+
+#[derive(Clone, Debug)]
+pub struct SimulatorConfig {
+    pub node_count: usize,
+    pub latency: Duration,
+    pub jitter: Duration,
+    pub seed: u64,
+}
+
+impl Default for SimulatorConfig {
+    fn default() -> Self {
+        SimulatorConfig {
+            node_count: 1,
+            latency: Duration::from_millis(0),
+            jitter: Duration::from_millis(0),
+            seed: 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NetworkStats {
+    pub all: u64,
+    pub clients: u64,
+    pub servers: u64,
+}
+
+#[derive(Debug)]
+enum SimulatorEvent {
+    Deliver(String),
+    Timeout {
+        node_id: NodeId,
+        timer_id: TimerId,
+    },
+    /// A message or an action delayed by a middleware of the node, resuming at the `next` one.
+    Inbound {
+        node_id: NodeId,
+        next: usize,
+        message: Message<Value>,
+    },
+    Outbound {
+        node_id: NodeId,
+        next: usize,
+        action: Action,
+    },
+}
+
+/// The `seq-kv`, `lin-kv` and `lww-kv` services, all linearizable here, and `lin-tso`.
+#[derive(Default)]
+struct Services {
+    stores: HashMap<ServiceKind, HashMap<String, Value>>,
+    next_ts: u64,
+}
+
+impl Services {
+    fn serve(&mut self, kind: ServiceKind, request: &Value) -> Value {
+        let key = request["key"].to_string();
+        let store = self.stores.entry(kind).or_default();
+        let error = |code, text: String| json!({"type": "error", "code": code, "text": text});
+        match (kind, request["type"].as_str().unwrap_or_default()) {
+            (ServiceKind::LinTso, "ts") => {
+                self.next_ts += 1;
+                json!({"type": "ts_ok", "ts": self.next_ts})
+            }
+            (ServiceKind::LinTso, _) => error(NOT_SUPPORTED, format!("Unsupported request '{}'", request)),
+            (_, "read") => match store.get(&key) {
+                Some(value) => json!({"type": "read_ok", "value": value}),
+                None => error(KEY_DOES_NOT_EXIST, format!("Key {} does not exist", key)),
+            },
+            (_, "write") => {
+                store.insert(key, request["value"].clone());
+                json!({"type": "write_ok"})
+            }
+            (_, "cas") => match store.get(&key) {
+                Some(value) if *value != request["from"] => error(PRECONDITION_FAILED, format!("Expected {}, found {}", request["from"], value)),
+                None if !request["create_if_not_exists"].as_bool().unwrap_or_default() => error(KEY_DOES_NOT_EXIST, format!("Key {} does not exist", key)),
+                _ => {
+                    store.insert(key, request["to"].clone());
+                    json!({"type": "cas_ok"})
+                }
+            },
+            _ => error(NOT_SUPPORTED, format!("Unsupported request '{}'", request)),
+        }
+    }
+}
+
+/// Runs a cluster of actors in a single thread, with virtual time and an in-memory network. Messages to a service
+/// are answered by an in-memory implementation of it. Every node runs behind its own middlewares, as with
+/// `run_actor_with`.
+pub struct Simulator<A>
+    where A: Actor {
+    config: SimulatorConfig,
+    origin: Instant,
+    now: Duration,
+    rng: StdRng,
+    nodes: BTreeMap<NodeId, ActorCell<A>>,
+    middlewares: BTreeMap<NodeId, MiddlewareStack<A>>,
+    services: Services,
+    /// The side of the partition of every node listed in one, nodes missing from it reaching every node.
+    partition: HashMap<NodeId, usize>,
+    events: BinaryHeap<Record<SimulatorEvent>>,
+    client_inbox: VecDeque<Message<Value>>,
+    stats: NetworkStats,
+}
+
+impl<A> Simulator<A>
+    where A: Actor {
+    pub fn new(config: SimulatorConfig) -> Result<Simulator<A>> {
+        Simulator::with_middlewares(config, |_| MiddlewareStack::new())
+    }
+
+    /// Runs every node behind the middlewares built for it.
+    pub fn with_middlewares<F>(config: SimulatorConfig, middlewares: F) -> Result<Simulator<A>>
+        where F: Fn(&NodeId) -> MiddlewareStack<A> {
+        let node_ids: Vec<NodeId> = (0..config.node_count).map(|idx| NodeId::Server(format!("n{}", idx))).collect();
+        let mut simulator = Simulator {
+            rng: StdRng::seed_from_u64(config.seed),
+            config,
+            origin: Instant::now(),
+            now: Duration::from_millis(0),
+            nodes: BTreeMap::new(),
+            middlewares: node_ids.iter().map(|node_id| (node_id.clone(), middlewares(node_id))).collect(),
+            services: Services::default(),
+            partition: HashMap::new(),
+            events: BinaryHeap::new(),
+            client_inbox: VecDeque::new(),
+            stats: NetworkStats::default(),
+        };
+        for node_id in &node_ids {
+            let this_node = ThisNode::new(node_id.clone(), node_ids.clone());
+            let (cell, actions) = ActorCell::new(this_node, simulator.config.seed, simulator.origin)?;
+            simulator.nodes.insert(node_id.clone(), cell);
+            simulator.execute_actions(node_id, actions)?;
+        }
+        Ok(simulator)
+    }
+
+    pub fn node_ids(&self) -> Vec<NodeId> {
+        self.nodes.keys().cloned().collect()
+    }
+
+    pub fn node(&self, node_id: &NodeId) -> Option<&A> {
+        self.nodes.get(node_id).map(ActorCell::actor)
+    }
+
+    pub fn metrics(&self, node_id: &NodeId) -> Option<&Metrics> {
+        self.nodes.get(node_id).map(ActorCell::metrics)
+    }
+
+    pub fn stats(&self) -> NetworkStats {
+        self.stats
+    }
+
+    pub fn send(&mut self, message: Message<Value>) -> Result<()> {
+        let from_server = message.src().is_peer();
+        let to_server = message.dest().is_peer();
+        self.route(from_server, to_server, serde_json::to_string(&message)?);
+        Ok(())
+    }
+
+    /// Cuts the network between the given groups of nodes, dropping the messages between two of them from now on.
+    /// Nodes in no group, such as clients and services unless listed, still reach every node.
+    pub fn partition(&mut self, groups: Vec<Vec<NodeId>>) {
+        self.partition = groups.into_iter()
+            .enumerate()
+            .flat_map(|(side, group)| group.into_iter().map(move |node_id| (node_id, side)))
+            .collect();
+    }
+
+    pub fn heal(&mut self) {
+        self.partition.clear();
+    }
+
+    fn is_cut(&self, src: &NodeId, dest: &NodeId) -> bool {
+        matches!((self.partition.get(src), self.partition.get(dest)), (Some(src), Some(dest)) if src != dest)
+    }
+
+    /// Processes the next scheduled delivery or timeout. Returns `false` when nothing is scheduled.
+    pub fn step(&mut self) -> Result<bool> {
+        let Some(Record { timestamp, value: event }) = self.events.pop() else {
+            return Ok(false);
+        };
+        self.now = timestamp.duration_since(self.origin);
+        let now = self.origin.add(self.now);
+        match event {
+            SimulatorEvent::Deliver(line) => {
+                let message: Message<Value> = serde_json::from_str(&line)?;
+                if self.is_cut(message.src(), message.dest()) {
+                    debug!("Dropping message across the partition: '{}'", line);
+                } else if message.dest().is_client() {
+                    self.client_inbox.push_back(message);
+                } else if let NodeId::Service(kind) = message.dest() {
+                    let response = self.services.serve(*kind, message.body());
+                    let (_, address) = message.body_and_address();
+                    self.route(false, false, serde_json::to_string(&Message::new_reply(address.to_reply_address(), response))?);
+                } else if self.nodes.contains_key(message.dest()) {
+                    let node_id = message.dest().clone();
+                    self.deliver(node_id, 0, message, now)?;
+                } else {
+                    debug!("Dropping message to an unknown node: '{}'", line);
+                }
+            }
+            SimulatorEvent::Timeout { node_id, timer_id } => {
+                if let Some(node) = self.nodes.get_mut(&node_id) {
+                    trace!("Expired timer on '{}': '{:?}'", node_id, timer_id);
+                    let actions = node.on_timeout(timer_id, now)?;
+                    self.execute_actions(&node_id, actions)?;
+                }
+            }
+            SimulatorEvent::Inbound { node_id, next, message } => self.deliver(node_id, next, message, now)?,
+            SimulatorEvent::Outbound { node_id, next, action } => self.execute_action(&node_id, next, action)?,
+        }
+        Ok(true)
+    }
+
+    /// Passes a message through the inbound middlewares of the node, starting at `from`, then to the node.
+    fn deliver(&mut self, node_id: NodeId, from: usize, message: Message<Value>, now: Instant) -> Result<()> {
+        let staged = match self.middlewares.get_mut(&node_id) {
+            Some(middlewares) => middlewares.inbound(from, message, now),
+            None => Staged::Ready(message),
+        };
+        match staged {
+            Staged::Ready(message) => {
+                let Some(node) = self.nodes.get_mut(&node_id) else { return Ok(()) };
+                debug!("Delivering message: '{:?}'", message);
+                let actions = node.on_json_message(message, now)?;
+                self.execute_actions(&node_id, actions)
+            }
+            Staged::Delayed { delay, next, value } => {
+                self.schedule(delay, SimulatorEvent::Inbound { node_id, next, message: value });
+                Ok(())
+            }
+            Staged::Dropped => Ok(()),
+        }
+    }
+
+    pub fn run_until(&mut self, time: Duration) -> Result<()> {
+        while self.next_event_time().is_some_and(|next| next <= time) {
+            self.step()?;
+        }
+        self.now = self.now.max(time);
+        Ok(())
+    }
+
+    fn next_event_time(&self) -> Option<Duration> {
+        self.events.peek().map(|Record { timestamp, .. }| timestamp.duration_since(self.origin))
+    }
+
+    fn execute_actions(&mut self, node_id: &NodeId, actions: Vec<Action>) -> Result<()> {
+        for action in actions {
+            self.execute_action(node_id, 0, action)?;
+        }
+        Ok(())
+    }
+
+    /// Passes an action through the outbound middlewares of the node, starting at `from`, then executes it.
+    fn execute_action(&mut self, node_id: &NodeId, from: usize, action: Action) -> Result<()> {
+        let now = self.origin.add(self.now);
+        let staged = match self.middlewares.get_mut(node_id) {
+            Some(middlewares) => middlewares.outbound(from, action, now),
+            None => Staged::Ready(action),
+        };
+        match staged {
+            Staged::Ready(RunnerAction::SendMessage(message)) => {
+                let to_server = message.dest().is_peer();
+                self.route(true, to_server, serde_json::to_string(&message)?);
+            }
+            Staged::Ready(RunnerAction::SetTimer { delay, timer_key }) => {
+                self.schedule(delay, SimulatorEvent::Timeout { node_id: node_id.clone(), timer_id: timer_key });
+            }
+            Staged::Delayed { delay, next, value } => {
+                self.schedule(delay, SimulatorEvent::Outbound { node_id: node_id.clone(), next, action: value });
+            }
+            Staged::Dropped => {}
+        }
+        Ok(())
+    }
+
+    fn route(&mut self, from_server: bool, to_server: bool, serialized: String) {
+        self.stats.all += 1;
+        if from_server && to_server {
+            self.stats.servers += 1;
+        } else {
+            self.stats.clients += 1;
+        }
+        let jitter = if self.config.jitter.is_zero() {
+            Duration::from_millis(0)
+        } else {
+            self.rng.gen_range(Duration::from_millis(0)..self.config.jitter)
+        };
+        self.schedule(self.config.latency.add(jitter), SimulatorEvent::Deliver(serialized));
+    }
+
+    fn schedule(&mut self, delay: Duration, event: SimulatorEvent) {
+        self.events.push(Record { timestamp: self.origin.add(self.now).add(delay), value: event });
+    }
+}
+
+impl<A> Transport for Simulator<A>
+    where A: Actor {
+    fn node_ids(&self) -> Vec<NodeId> {
+        Simulator::node_ids(self)
+    }
+
+    fn now(&self) -> Duration {
+        self.now
+    }
+
+    fn send(&mut self, message: Message<Value>) -> Result<()> {
+        Simulator::send(self, message)
+    }
+
+    fn receive(&mut self, until: Duration) -> Result<Option<Message<Value>>> {
+        loop {
+            if let Some(message) = self.client_inbox.pop_front() {
+                return Ok(Some(message));
+            }
+            if self.next_event_time().is_some_and(|next| next <= until) {
+                self.step()?;
+            } else {
+                self.now = self.now.max(until);
+                return Ok(None);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::{json, Value};
+
+    use crate::common::actor::Actor;
+    use crate::common::context::Context;
+    use crate::common::driver::Transport;
+    use crate::common::error::Result;
+    use crate::common::message::message::{Message, MessageAddress};
+    use crate::common::message::{MessageId, NodeId, ServiceKind};
+    use crate::common::runner::{Dedup, DelayOutbound, MiddlewareStack};
+    use crate::common::simulator::{Simulator, SimulatorConfig};
+
+    struct EchoActor;
+
+    impl Actor for EchoActor {
+        type Msg = Value;
+        type TimerKey = ();
+
+        fn new(_: &mut Context<Self>) -> Result<Self> {
+            Ok(EchoActor)
+        }
+
+        fn on_request(&mut self, context: &mut Context<Self>, request: Message<Self::Msg>) -> Result<()> {
+            let (body, address) = request.body_and_address();
+            context.reply(address, json!({"type": "echo_ok", "echo": body["echo"]}));
+            Ok(())
+        }
+    }
+
+    fn request(simulator: &mut Simulator<EchoActor>, dest: NodeId, body: Value) -> Result<Option<Value>> {
+        simulator.send(Message::new_request(MessageAddress {
+            src: NodeId::from("c1"),
+            dest,
+            msg_id: Some(MessageId(1)),
+            in_reply_to: None,
+        }, body))?;
+        let until = Transport::now(simulator) + Duration::from_millis(100);
+        Ok(simulator.receive(until)?.map(|message| message.body_and_address().0))
+    }
+
+    #[test]
+    fn should_answer_kv_requests() -> Result<()> {
+        let mut simulator = Simulator::<EchoActor>::new(SimulatorConfig::default())?;
+        let lin_kv = NodeId::from(ServiceKind::LinKv);
+
+        let missing = request(&mut simulator, lin_kv.clone(), json!({"type": "cas", "key": "k", "from": 1, "to": 2}))?;
+        let created = request(&mut simulator, lin_kv.clone(), json!({"type": "cas", "key": "k", "from": 1, "to": 2, "create_if_not_exists": true}))?;
+        let stale = request(&mut simulator, lin_kv.clone(), json!({"type": "cas", "key": "k", "from": 1, "to": 3}))?;
+        let read = request(&mut simulator, lin_kv, json!({"type": "read", "key": "k"}))?;
+
+        assert_eq!(missing.map(|body| body["code"].clone()), Some(json!(20)));
+        assert_eq!(created.map(|body| body["type"].clone()), Some(json!("cas_ok")));
+        assert_eq!(stale.map(|body| body["code"].clone()), Some(json!(22)));
+        assert_eq!(read.map(|body| body["value"].clone()), Some(json!(2)));
+        Ok(())
+    }
+
+    #[test]
+    fn should_drop_messages_across_partition_until_healed() -> Result<()> {
+        let mut simulator = Simulator::<EchoActor>::new(SimulatorConfig { node_count: 2, ..SimulatorConfig::default() })?;
+
+        simulator.partition(vec![vec![NodeId::from("n0")], vec![NodeId::from("c1"), NodeId::from("n1")]]);
+        let cut = request(&mut simulator, NodeId::from("n0"), json!({"type": "echo", "echo": 1}))?;
+        let same_side = request(&mut simulator, NodeId::from("n1"), json!({"type": "echo", "echo": 2}))?;
+        simulator.heal();
+        let healed = request(&mut simulator, NodeId::from("n0"), json!({"type": "echo", "echo": 3}))?;
+
+        assert_eq!(cut, None);
+        assert_eq!(same_side, Some(json!({"type": "echo_ok", "echo": 2})));
+        assert_eq!(healed, Some(json!({"type": "echo_ok", "echo": 3})));
+        Ok(())
+    }
+
+    #[test]
+    fn should_run_nodes_behind_their_middlewares() -> Result<()> {
+        let mut simulator = Simulator::<EchoActor>::with_middlewares(SimulatorConfig::default(), |_| {
+            MiddlewareStack::new().with(Dedup::default()).with(DelayOutbound(Duration::from_millis(30)))
+        })?;
+
+        let first = request(&mut simulator, NodeId::from("n0"), json!({"type": "echo", "echo": 1}))?;
+        let answered_at = Transport::now(&simulator);
+        let retransmitted = request(&mut simulator, NodeId::from("n0"), json!({"type": "echo", "echo": 1}))?;
+
+        assert_eq!(first, Some(json!({"type": "echo_ok", "echo": 1})));
+        assert_eq!(answered_at, Duration::from_millis(30));
+        assert_eq!(retransmitted, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::common::actor::Actor;
+use crate::common::context::Context;
+use crate::common::error::Result;
+use crate::common::message::message::MessageAddress;
+use crate::common::message::NodeId;
+use crate::common::rpc::Request;
+
+const TIMEOUT: u64 = 0;
+const TEMPORARILY_UNAVAILABLE: u64 = 11;
+const MALFORMED_REQUEST: u64 = 12;
+
+#[derive(Clone, Debug)]
+pub struct SnapshotConfig {
+    /// How many applied entries the log keeps before they are compacted into a snapshot.
+    pub threshold: usize,
+    /// How many bytes of a snapshot each message carries.
+    pub chunk_size: usize,
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        SnapshotConfig {
+            threshold: 1000,
+            chunk_size: 64 * 1024,
+        }
+    }
+}
+
+/// The deterministic service replicated by a consensus backend, `Raft` or `Paxos`. Every decided command is
+/// applied on every node, in the same order. The state machine is serialized whole into snapshots.
+pub trait StateMachine: Default + Serialize + DeserializeOwned + 'static {
+    type Command: Debug + DeserializeOwned;
+    type Output: Serialize;
+
+    fn apply(&mut self, command: Self::Command) -> Self::Output;
+
+    /// Used by backends which compact their log.
+    fn snapshot_config() -> SnapshotConfig {
+        SnapshotConfig::default()
+    }
+}
+
+/// A client request forwarded to the leader as is.
+#[derive(Serialize)]
+#[serde(transparent)]
+struct Forward(Value);
+
+impl Request for Forward {
+    type Response = Value;
+}
+
+/// Handles a command received by a node which is not the leader. Client commands are forwarded to the leader, if
+/// one is known, and its reply relayed. Commands from other nodes are refused, so a command never bounces between
+/// nodes with stale leaders.
+pub fn forward<A>(context: &mut Context<A>, leader: Option<NodeId>, command: Value, address: MessageAddress) -> Result<()>
+    where A: Actor<Msg = Value> {
+    match leader {
+        Some(leader) if address.src.is_client() => {
+            context.rpc(leader, Forward(command), move |_, context, response| {
+                let body = match response {
+                    Ok(body) => body,
+                    Err(error) => json!({"type": "error", "code": error.code, "text": error.text}),
+                };
+                context.reply(address, body);
+                Ok(())
+            })
+        }
+        _ => {
+            context.reply(address, json!({"type": "error", "code": TEMPORARILY_UNAVAILABLE, "text": "No known leader"}));
+            Ok(())
+        }
+    }
+}
+
+/// Whether a client command decodes into `S::Command`, replying with a `malformed-request` error otherwise. The
+/// leader checks commands before proposing them, since every node applies them once decided.
+pub fn is_well_formed<S, A>(context: &mut Context<A>, command: &Value, address: &MessageAddress) -> bool
+    where S: StateMachine,
+          A: Actor<Msg = Value> {
+    match serde_json::from_value::<S::Command>(command.clone()) {
+        Ok(_) => true,
+        Err(error) => {
+            context.reply(address.clone(), json!({"type": "error", "code": MALFORMED_REQUEST, "text": error.to_string()}));
+            false
+        }
+    }
+}
+
+/// Answers the clients of the commands a leader proposed before stepping down. The next leader may still decide
+/// them, so their outcome is unknown.
+pub fn abandon<A, K>(context: &mut Context<A>, pending: HashMap<K, MessageAddress>)
+    where A: Actor<Msg = Value>,
+          K: Ord {
+    let mut pending: Vec<(K, MessageAddress)> = pending.into_iter().collect();
+    pending.sort_by(|(left, _), (right, _)| left.cmp(right));
+    for (_, address) in pending {
+        context.reply(address, json!({"type": "error", "code": TIMEOUT, "text": "Leader stepped down"}));
+    }
+}
@@ -0,0 +1,423 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::Duration;
+
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+
+use crate::common::actor::Actor;
+use crate::common::context::Context;
+use crate::common::error::Error::{UnexpectedError, UnexpectedMessage};
+use crate::common::error::Result;
+use crate::common::message::message::{Message, MessageAddress};
+use crate::common::message::{MessageId, MessageKind, NodeId};
+
+#[derive(Clone, Debug)]
+pub struct SwimConfig {
+    /// How often a member is probed.
+    pub period: Duration,
+    /// How long a direct ping may go unanswered before asking other members to probe the target.
+    pub ping_timeout: Duration,
+    /// How many members are asked to probe an unresponsive target.
+    pub indirect_probes: usize,
+    /// How long a suspected member has to refute the suspicion before it is declared dead.
+    pub suspicion_timeout: Duration,
+    /// Each update is piggybacked on `retransmit_multiplier * log2(members)` messages.
+    pub retransmit_multiplier: usize,
+    pub max_piggyback: usize,
+}
+
+impl Default for SwimConfig {
+    fn default() -> Self {
+        SwimConfig {
+            period: Duration::from_millis(200),
+            ping_timeout: Duration::from_millis(80),
+            indirect_probes: 3,
+            suspicion_timeout: Duration::from_millis(1000),
+            retransmit_multiplier: 3,
+            max_piggyback: 8,
+        }
+    }
+}
+
+/// Ordered so that, at the same incarnation, suspicion overrides liveness and death overrides suspicion.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum MemberState {
+    Alive,
+    Suspect,
+    Dead,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Update {
+    pub node_id: NodeId,
+    pub state: MemberState,
+    pub incarnation: u64,
+}
+
+#[derive(Serialize, Deserialize, MessageKind, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SwimMessage {
+    Ping {
+        #[serde(default)]
+        updates: Vec<Update>,
+    },
+    PingOk {
+        #[serde(default)]
+        updates: Vec<Update>,
+    },
+    PingReq {
+        target: NodeId,
+        #[serde(default)]
+        updates: Vec<Update>,
+    },
+    PingReqOk {
+        #[serde(default)]
+        updates: Vec<Update>,
+    },
+}
+
+#[derive(Debug)]
+pub enum SwimTimer {
+    Period,
+    PingTimeout,
+    Suspicion {
+        node_id: NodeId,
+        incarnation: u64,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Member {
+    pub state: MemberState,
+    pub incarnation: u64,
+}
+
+/// The member probed in the current period, and the requests whose replies count as its ack.
+struct Probe {
+    target: NodeId,
+    msg_ids: HashSet<MessageId>,
+    acked: bool,
+}
+
+struct Gossip {
+    update: Update,
+    transmissions: usize,
+}
+
+/// SWIM membership: every period one member is pinged directly, then through `indirect_probes` other members,
+/// and suspected if nobody gets an ack. Suspected members which do not refute in time are declared dead.
+/// Membership updates are piggybacked on the protocol messages.
+///
+/// Dead members keep being probed, so that they learn about their death, refute it with a higher incarnation
+/// and rejoin once a partition heals. Host it in a `Composite` next to a workload, which reads the live members
+/// through `ThisNode::live_nodes`.
+pub struct Swim {
+    config: SwimConfig,
+    incarnation: u64,
+    members: BTreeMap<NodeId, Member>,
+    probe_order: Vec<NodeId>,
+    probe: Option<Probe>,
+    /// The `ping_req` each ping sent on behalf of another member answers.
+    relays: HashMap<MessageId, MessageAddress>,
+    gossip: Vec<Gossip>,
+}
+
+impl Swim {
+    pub fn with_config(context: &mut Context<Self>, config: SwimConfig) -> Swim {
+        let this_node = context.this_node();
+        let members = this_node.node_ids.iter()
+            .filter(|node_id| **node_id != this_node.node_id)
+            .map(|node_id| (node_id.clone(), Member { state: MemberState::Alive, incarnation: 0 }))
+            .collect();
+        let swim = Swim {
+            config,
+            incarnation: 0,
+            members,
+            probe_order: vec![],
+            probe: None,
+            relays: HashMap::new(),
+            gossip: vec![],
+        };
+        swim.publish(context);
+        context.set_timer(swim.config.period, SwimTimer::Period);
+        swim
+    }
+
+    pub fn incarnation(&self) -> u64 {
+        self.incarnation
+    }
+
+    pub fn members(&self) -> &BTreeMap<NodeId, Member> {
+        &self.members
+    }
+
+    fn publish(&self, context: &mut Context<Self>) {
+        let mut live_nodes: Vec<NodeId> = self.members.iter()
+            .filter(|(_, member)| member.state != MemberState::Dead)
+            .map(|(node_id, _)| node_id.clone())
+            .chain([context.this_node().node_id.clone()])
+            .collect();
+        live_nodes.sort();
+        context.this_node().set_live_nodes(live_nodes);
+    }
+
+    fn on_period(&mut self, context: &mut Context<Self>) -> Result<()> {
+        if let Some(probe) = self.probe.take() {
+            if !probe.acked {
+                let incarnation = self.members.get(&probe.target).map_or(0, |member| member.incarnation);
+                self.apply(context, Update { node_id: probe.target, state: MemberState::Suspect, incarnation });
+            }
+        }
+        self.relays.clear();
+        if self.probe_order.is_empty() {
+            self.probe_order = self.members.keys().cloned().collect();
+            self.probe_order.shuffle(context.rng());
+        }
+        if let Some(target) = self.probe_order.pop() {
+            let msg_id = self.send_request(context, target.clone(), |updates| SwimMessage::Ping { updates })?;
+            self.probe = Some(Probe { target, msg_ids: HashSet::from([msg_id]), acked: false });
+            context.set_timer(self.config.ping_timeout, SwimTimer::PingTimeout);
+        }
+        context.set_timer(self.config.period, SwimTimer::Period);
+        Ok(())
+    }
+
+    fn on_ping_timeout(&mut self, context: &mut Context<Self>) -> Result<()> {
+        let Some(target) = self.probe.as_ref().filter(|probe| !probe.acked).map(|probe| probe.target.clone()) else {
+            return Ok(());
+        };
+        let mut relays: Vec<NodeId> = self.members.iter()
+            .filter(|(node_id, member)| **node_id != target && member.state == MemberState::Alive)
+            .map(|(node_id, _)| node_id.clone())
+            .collect();
+        relays.shuffle(context.rng());
+        relays.truncate(self.config.indirect_probes);
+        for relay in relays {
+            let msg_id = self.send_request(context, relay, |updates| SwimMessage::PingReq { target: target.clone(), updates })?;
+            if let Some(probe) = self.probe.as_mut() {
+                probe.msg_ids.insert(msg_id);
+            }
+        }
+        Ok(())
+    }
+
+    fn send_request<F>(&mut self, context: &mut Context<Self>, dest: NodeId, body: F) -> Result<MessageId>
+        where F: FnOnce(Vec<Update>) -> SwimMessage {
+        let address = context.this_node().new_destination_address(dest);
+        let msg_id = address.msg_id.clone()
+            .ok_or_else(|| UnexpectedError(format!("Request without msg_id: '{:?}'", address)))?;
+        let mut updates = self.piggyback();
+        // Gossip about a member stops once spread, so a suspected or dead member is told about itself on every
+        // probe, until it refutes.
+        let about_dest = self.members.get(&address.dest).filter(|member| member.state != MemberState::Alive);
+        if let Some(member) = about_dest {
+            if !updates.iter().any(|update| update.node_id == address.dest) {
+                updates.push(Update { node_id: address.dest.clone(), state: member.state, incarnation: member.incarnation });
+            }
+        }
+        context.send_message(Message::new_request(address, body(updates)));
+        Ok(msg_id)
+    }
+
+    /// Applies an update if it is newer than what is known about its member, and gossips it further.
+    fn apply(&mut self, context: &mut Context<Self>, update: Update) {
+        if update.node_id == context.this_node().node_id {
+            // Refute suspicion or death by outliving the incarnation it was raised at.
+            if update.state != MemberState::Alive && update.incarnation >= self.incarnation {
+                self.incarnation = update.incarnation + 1;
+                self.disseminate(Update { node_id: update.node_id, state: MemberState::Alive, incarnation: self.incarnation });
+            }
+            return;
+        }
+        let Some(member) = self.members.get_mut(&update.node_id) else { return };
+        if (update.incarnation, update.state) <= (member.incarnation, member.state) {
+            return;
+        }
+        *member = Member { state: update.state, incarnation: update.incarnation };
+        if update.state == MemberState::Suspect {
+            context.set_timer(self.config.suspicion_timeout, SwimTimer::Suspicion { node_id: update.node_id.clone(), incarnation: update.incarnation });
+        }
+        self.disseminate(update);
+        self.publish(context);
+    }
+
+    fn apply_all(&mut self, context: &mut Context<Self>, updates: Vec<Update>) {
+        for update in updates {
+            self.apply(context, update);
+        }
+    }
+
+    fn disseminate(&mut self, update: Update) {
+        self.gossip.retain(|gossip| gossip.update.node_id != update.node_id);
+        self.gossip.push(Gossip { update, transmissions: 0 });
+    }
+
+    /// The least transmitted updates, dropping the ones which have been transmitted often enough.
+    fn piggyback(&mut self) -> Vec<Update> {
+        let limit = self.config.retransmit_multiplier * (usize::BITS - (self.members.len() + 1).leading_zeros()) as usize;
+        self.gossip.sort_by_key(|gossip| gossip.transmissions);
+        let updates = self.gossip.iter_mut().take(self.config.max_piggyback).map(|gossip| {
+            gossip.transmissions += 1;
+            gossip.update.clone()
+        }).collect();
+        self.gossip.retain(|gossip| gossip.transmissions < limit);
+        updates
+    }
+}
+
+impl Actor for Swim {
+    type Msg = SwimMessage;
+    type TimerKey = SwimTimer;
+
+    fn new(context: &mut Context<Self>) -> Result<Self> {
+        Ok(Swim::with_config(context, SwimConfig::default()))
+    }
+
+    fn on_request(&mut self, context: &mut Context<Self>, request: Message<Self::Msg>) -> Result<()> {
+        let (body, address) = request.body_and_address();
+        match body {
+            SwimMessage::Ping { updates } => {
+                self.apply_all(context, updates);
+                let updates = self.piggyback();
+                context.reply(address, SwimMessage::PingOk { updates });
+            }
+            SwimMessage::PingReq { target, updates } => {
+                self.apply_all(context, updates);
+                let msg_id = self.send_request(context, target, |updates| SwimMessage::Ping { updates })?;
+                self.relays.insert(msg_id, address);
+            }
+            reply => return Err(UnexpectedMessage(reply.type_name().to_string())),
+        }
+        Ok(())
+    }
+
+    fn on_reply(&mut self, context: &mut Context<Self>, reply: Message<Self::Msg>) -> Result<()> {
+        let (body, address) = reply.body_and_address();
+        match body {
+            SwimMessage::PingOk { updates } | SwimMessage::PingReqOk { updates } => self.apply_all(context, updates),
+            request => return Err(UnexpectedMessage(request.type_name().to_string())),
+        }
+        let Some(in_reply_to) = address.in_reply_to else { return Ok(()) };
+        if let Some(request_address) = self.relays.remove(&in_reply_to) {
+            let updates = self.piggyback();
+            context.reply(request_address, SwimMessage::PingReqOk { updates });
+        }
+        if let Some(probe) = self.probe.as_mut().filter(|probe| probe.msg_ids.contains(&in_reply_to)) {
+            probe.acked = true;
+        }
+        Ok(())
+    }
+
+    fn on_timeout(&mut self, context: &mut Context<Self>, timer_key: Self::TimerKey) -> Result<()> {
+        match timer_key {
+            SwimTimer::Period => self.on_period(context),
+            SwimTimer::PingTimeout => self.on_ping_timeout(context),
+            SwimTimer::Suspicion { node_id, incarnation } => {
+                let suspected = self.members.get(&node_id).is_some_and(|member| member.state == MemberState::Suspect && member.incarnation == incarnation);
+                if suspected {
+                    self.apply(context, Update { node_id, state: MemberState::Dead, incarnation });
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use serde_json::json;
+
+    use crate::common::context::ActorCell;
+    use crate::common::error::Result;
+    use crate::common::message::message::{Message, MessageAddress};
+    use crate::common::message::{MessageId, NodeId};
+    use crate::common::runner::RunnerAction;
+    use crate::common::simulator::{Simulator, SimulatorConfig};
+    use crate::common::swim::{MemberState, Swim, SwimMessage};
+    use crate::common::this_node::ThisNode;
+
+    #[test]
+    fn should_keep_every_member_alive() -> Result<()> {
+        let mut simulator = Simulator::<Swim>::new(SimulatorConfig { node_count: 5, latency: Duration::from_millis(5), ..SimulatorConfig::default() })?;
+
+        simulator.run_until(Duration::from_secs(5))?;
+
+        for node_id in simulator.node_ids() {
+            let swim = simulator.node(&node_id).unwrap();
+            assert_eq!(swim.members().len(), 4);
+            assert!(swim.members().values().all(|member| member.state == MemberState::Alive));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_declare_silent_members_dead() -> Result<()> {
+        let node_ids = vec![NodeId::from("n0"), NodeId::from("n1")];
+        let this_node = ThisNode::new(NodeId::from("n0"), node_ids);
+        let (mut cell, mut actions) = ActorCell::<Swim>::new(this_node.clone(), 0, Instant::now())?;
+
+        // Nobody answers: fire every timer in the order they were set.
+        let mut now = Instant::now();
+        for _ in 0..20 {
+            let timers: Vec<_> = actions.drain(..).filter_map(|action| match action {
+                RunnerAction::SetTimer { delay, timer_key } => Some((delay, timer_key)),
+                RunnerAction::SendMessage(_) => None,
+            }).collect();
+            for (delay, timer_id) in timers {
+                now += delay;
+                actions.extend(cell.on_timeout(timer_id, now)?);
+            }
+        }
+
+        assert_eq!(cell.actor().members()[&NodeId::from("n1")].state, MemberState::Dead);
+        assert_eq!(this_node.live_nodes(), vec![NodeId::from("n0")]);
+        Ok(())
+    }
+
+    #[test]
+    fn should_refute_suspicion() -> Result<()> {
+        let this_node = ThisNode::new(NodeId::from("n0"), vec![NodeId::from("n0"), NodeId::from("n1")]);
+        let (mut cell, _) = ActorCell::<Swim>::new(this_node, 0, Instant::now())?;
+        let ping = Message::new_request(MessageAddress {
+            src: NodeId::from("n1"),
+            dest: NodeId::from("n0"),
+            msg_id: Some(MessageId(1)),
+            in_reply_to: None,
+        }, json!({"type": "ping", "updates": [{"node_id": "n0", "state": "suspect", "incarnation": 0}]}));
+
+        let actions = cell.on_json_message(ping, Instant::now())?;
+
+        let [RunnerAction::SendMessage(reply)] = actions.as_slice() else { panic!("Expected a single reply") };
+        let SwimMessage::PingOk { updates } = serde_json::from_value(reply.body().clone())? else { panic!("Expected a ping_ok") };
+        assert_eq!(updates[0].state, MemberState::Alive);
+        assert_eq!(updates[0].incarnation, 1);
+        assert_eq!(cell.actor().incarnation(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn should_revive_dead_members_once_partition_heals() -> Result<()> {
+        let mut simulator = Simulator::<Swim>::new(SimulatorConfig {
+            node_count: 3,
+            latency: Duration::from_millis(5),
+            ..SimulatorConfig::default()
+        })?;
+        let n0 = NodeId::from("n0");
+
+        simulator.partition(vec![vec![n0.clone()], vec![NodeId::from("n1"), NodeId::from("n2")]]);
+        simulator.run_until(Duration::from_secs(5))?;
+        assert_eq!(simulator.node(&NodeId::from("n1")).unwrap().members()[&n0].state, MemberState::Dead);
+        simulator.heal();
+        simulator.run_until(Duration::from_secs(10))?;
+
+        for node_id in simulator.node_ids() {
+            let swim = simulator.node(&node_id).unwrap();
+            assert!(swim.members().values().all(|member| member.state == MemberState::Alive), "Members of {}: {:?}", node_id, swim.members());
+        }
+        assert!(simulator.node(&n0).unwrap().incarnation() > 0);
+        Ok(())
+    }
+}
@@ -1,15 +1,20 @@
 use std::cell::RefCell;
+use std::rc::Rc;
 
 use crate::common::message::MessageId;
 use crate::common::message::message::MessageAddress;
+use crate::common::topology::Topology;
 
 use super::message::NodeId;
 
-#[derive(Debug)]
+/// Clones share the outbound message id counter, so components of one node never reuse a `msg_id`.
+#[derive(Clone, Debug)]
 pub struct ThisNode {
     pub node_id: NodeId,
     pub node_ids: Vec<NodeId>,
-    outbound_message_id: RefCell<MessageId>,
+    outbound_message_id: Rc<RefCell<MessageId>>,
+    topology: Rc<RefCell<Option<Topology>>>,
+    live_nodes: Rc<RefCell<Option<Vec<NodeId>>>>,
 }
 
 impl ThisNode {
@@ -17,7 +22,9 @@ impl ThisNode {
         ThisNode {
             node_id,
             node_ids,
-            outbound_message_id: RefCell::new(MessageId(1)),
+            outbound_message_id: Rc::new(RefCell::new(MessageId(1))),
+            topology: Rc::new(RefCell::new(None)),
+            live_nodes: Rc::new(RefCell::new(None)),
         }
     }
 
@@ -26,7 +33,34 @@ impl ThisNode {
         MessageAddress {
             src: self.node_id.clone(),
             dest,
-            msg_id,
+            msg_id: Some(msg_id),
+            in_reply_to: None,
         }
     }
+
+    pub fn set_topology(&self, topology: Topology) {
+        self.topology.replace(Some(topology));
+    }
+
+    pub fn topology(&self) -> Option<Topology> {
+        self.topology.borrow().clone()
+    }
+
+    /// The neighbors of this node in its topology, or every other node until a topology is set.
+    pub fn neighbors(&self) -> Vec<NodeId> {
+        match self.topology.borrow().as_ref() {
+            Some(topology) => topology.neighbors(&self.node_id).to_vec(),
+            None => self.node_ids.iter().filter(|node_id| **node_id != self.node_id).cloned().collect(),
+        }
+    }
+
+    /// Publishes the nodes a membership protocol believes alive, this node included.
+    pub fn set_live_nodes(&self, live_nodes: Vec<NodeId>) {
+        self.live_nodes.replace(Some(live_nodes));
+    }
+
+    /// The nodes believed alive, or every node until a membership protocol publishes its view.
+    pub fn live_nodes(&self) -> Vec<NodeId> {
+        self.live_nodes.borrow().clone().unwrap_or_else(|| self.node_ids.clone())
+    }
 }
@@ -0,0 +1,214 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::str::FromStr;
+
+use rand::Rng;
+use rand::seq::SliceRandom;
+
+use crate::common::error::Error::Config;
+use crate::common::error::{Error, Result};
+use crate::common::message::NodeId;
+
+/// How a node picks its neighbors: the topology Maelstrom sends, or one computed from the node ids.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overlay {
+    Given,
+    /// Every node sends to its successors in the sorted ring of node ids.
+    Ring {
+        successors: usize,
+    },
+    Tree {
+        fanout: usize,
+    },
+    Grid,
+    RandomRegular {
+        degree: usize,
+    },
+}
+
+/// Parses `given`, `ring:<successors>`, `tree:<fanout>`, `grid` or `random:<degree>`.
+impl FromStr for Overlay {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Overlay> {
+        let (name, parameter) = value.split_once(':').map_or((value, None), |(name, parameter)| (name, Some(parameter)));
+        let parameter = || parameter
+            .and_then(|parameter| parameter.parse().ok())
+            .ok_or_else(|| Config(format!("Expected a number in overlay '{}'", value)));
+        match name {
+            "given" => Ok(Overlay::Given),
+            "ring" => Ok(Overlay::Ring { successors: parameter()? }),
+            "tree" => Ok(Overlay::Tree { fanout: parameter()? }),
+            "grid" => Ok(Overlay::Grid),
+            "random" => Ok(Overlay::RandomRegular { degree: parameter()? }),
+            _ => Err(Config(format!("Unknown overlay '{}'", value)))
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Topology {
+    adjacency: BTreeMap<NodeId, Vec<NodeId>>,
+}
+
+impl Topology {
+    pub fn new(adjacency: BTreeMap<NodeId, Vec<NodeId>>) -> Topology {
+        Topology { adjacency }
+    }
+
+    /// Builds the overlay over the given nodes, or `None` for `Overlay::Given`.
+    pub fn generate<R>(overlay: Overlay, node_ids: &[NodeId], rng: &mut R) -> Result<Option<Topology>>
+        where R: Rng {
+        Ok(match overlay {
+            Overlay::Given => None,
+            Overlay::Ring { successors } => Some(Topology::ring(node_ids, successors)),
+            Overlay::Tree { fanout } => Some(Topology::tree(node_ids, fanout)),
+            Overlay::Grid => Some(Topology::grid(node_ids)),
+            Overlay::RandomRegular { degree } => Some(Topology::random_regular(node_ids, degree, rng)?),
+        })
+    }
+
+    pub fn ring(node_ids: &[NodeId], successors: usize) -> Topology {
+        let mut sorted = node_ids.to_vec();
+        sorted.sort();
+        let adjacency = sorted.iter().enumerate().map(|(idx, node_id)| {
+            let neighbors = sorted.iter().cycle().skip(idx + 1).take(successors.min(sorted.len().saturating_sub(1))).cloned().collect();
+            (node_id.clone(), neighbors)
+        }).collect();
+        Topology { adjacency }
+    }
+
+    /// Connects every node to its parent and children in a tree laid out breadth first in the order of `node_ids`.
+    pub fn tree(node_ids: &[NodeId], fanout: usize) -> Topology {
+        let fanout = fanout.max(1);
+        let adjacency = node_ids.iter().enumerate().map(|(idx, node_id)| {
+            let parent = (idx > 0).then(|| (idx - 1) / fanout);
+            let children = (idx * fanout + 1..(idx + 1) * fanout + 1).filter(|child| *child < node_ids.len());
+            let neighbors = parent.into_iter().chain(children).map(|neighbor| node_ids[neighbor].clone()).collect();
+            (node_id.clone(), neighbors)
+        }).collect();
+        Topology { adjacency }
+    }
+
+    /// The default Maelstrom topology: nodes laid out row by row in a square grid, connected to their horizontal and vertical neighbors.
+    pub fn grid(node_ids: &[NodeId]) -> Topology {
+        let width = (node_ids.len() as f64).sqrt().ceil() as usize;
+        let adjacency = node_ids
+            .iter()
+            .enumerate()
+            .map(|(idx, node_id)| {
+                let mut neighbors = vec![];
+                if idx % width > 0 {
+                    neighbors.push(idx - 1);
+                }
+                if idx % width + 1 < width && idx + 1 < node_ids.len() {
+                    neighbors.push(idx + 1);
+                }
+                if idx >= width {
+                    neighbors.push(idx - width);
+                }
+                if idx + width < node_ids.len() {
+                    neighbors.push(idx + width);
+                }
+                (node_id.clone(), neighbors.into_iter().map(|neighbor| node_ids[neighbor].clone()).collect())
+            })
+            .collect();
+        Topology { adjacency }
+    }
+
+    /// A random undirected graph where every node has `degree` neighbors, drawn by pairing edge endpoints at random
+    /// until no pair forms a loop or a duplicate edge.
+    pub fn random_regular<R>(node_ids: &[NodeId], degree: usize, rng: &mut R) -> Result<Topology>
+        where R: Rng {
+        if degree >= node_ids.len() || (degree * node_ids.len()) % 2 == 1 {
+            return Err(Config(format!("No {}-regular graph over {} nodes", degree, node_ids.len())));
+        }
+        const ATTEMPTS: usize = 1000;
+        for _ in 0..ATTEMPTS {
+            let mut endpoints: Vec<usize> = (0..node_ids.len()).flat_map(|idx| std::iter::repeat_n(idx, degree)).collect();
+            endpoints.shuffle(rng);
+            let mut edges = BTreeSet::new();
+            let valid = endpoints.chunks(2).all(|pair| pair[0] != pair[1] && edges.insert((pair[0].min(pair[1]), pair[0].max(pair[1]))));
+            if valid {
+                let mut adjacency: BTreeMap<NodeId, Vec<NodeId>> = node_ids.iter().map(|node_id| (node_id.clone(), vec![])).collect();
+                for (from, to) in edges {
+                    adjacency.get_mut(&node_ids[from]).unwrap().push(node_ids[to].clone());
+                    adjacency.get_mut(&node_ids[to]).unwrap().push(node_ids[from].clone());
+                }
+                return Ok(Topology { adjacency });
+            }
+        }
+        Err(Config(format!("Could not draw a {}-regular graph over {} nodes", degree, node_ids.len())))
+    }
+
+    pub fn neighbors(&self, node_id: &NodeId) -> &[NodeId] {
+        self.adjacency.get(node_id).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn adjacency(&self) -> &BTreeMap<NodeId, Vec<NodeId>> {
+        &self.adjacency
+    }
+}
+
+impl From<HashMap<NodeId, BTreeSet<NodeId>>> for Topology {
+    fn from(topology: HashMap<NodeId, BTreeSet<NodeId>>) -> Self {
+        Topology {
+            adjacency: topology.into_iter().map(|(node_id, neighbors)| (node_id, neighbors.into_iter().collect())).collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use crate::common::error::Result;
+    use crate::common::message::NodeId;
+    use crate::common::topology::{Overlay, Topology};
+
+    fn node_ids(count: usize) -> Vec<NodeId> {
+        (0..count).map(|idx| NodeId::from(format!("n{}", idx))).collect()
+    }
+
+    fn neighbors<'a>(topology: &'a Topology, node_id: &str) -> Vec<&'a str> {
+        topology.neighbors(&NodeId::from(node_id)).iter().map(|neighbor| match neighbor {
+            NodeId::Server(name) => name.as_str(),
+            _ => panic!("Unexpected neighbor"),
+        }).collect()
+    }
+
+    #[test]
+    fn should_build_ring_and_tree() {
+        let ring = Topology::ring(&node_ids(4), 2);
+        let tree = Topology::tree(&node_ids(5), 2);
+
+        assert_eq!(neighbors(&ring, "n3"), vec!["n0", "n1"]);
+        assert_eq!(neighbors(&tree, "n0"), vec!["n1", "n2"]);
+        assert_eq!(neighbors(&tree, "n1"), vec!["n0", "n3", "n4"]);
+        assert_eq!(neighbors(&tree, "n4"), vec!["n1"]);
+    }
+
+    #[test]
+    fn should_draw_random_regular_graph() -> Result<()> {
+        let mut rng = StdRng::seed_from_u64(0);
+
+        let topology = Topology::random_regular(&node_ids(10), 3, &mut rng)?;
+
+        for (node_id, neighbors) in topology.adjacency() {
+            assert_eq!(neighbors.len(), 3);
+            assert!(!neighbors.contains(node_id));
+            assert!(neighbors.iter().all(|neighbor| topology.neighbors(neighbor).contains(node_id)));
+        }
+        assert!(Topology::random_regular(&node_ids(5), 3, &mut rng).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_overlays() -> Result<()> {
+        assert_eq!("ring:4".parse::<Overlay>()?, Overlay::Ring { successors: 4 });
+        assert_eq!("grid".parse::<Overlay>()?, Overlay::Grid);
+        assert!("tree".parse::<Overlay>().is_err());
+        assert!("mesh".parse::<Overlay>().is_err());
+        Ok(())
+    }
+}
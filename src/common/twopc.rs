@@ -0,0 +1,578 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+use log::debug;
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::common::actor::Actor;
+use crate::common::context::Context;
+use crate::common::error::Error::UnexpectedMessage;
+use crate::common::error::Result;
+use crate::common::message::message::{Message, MessageAddress};
+use crate::common::message::{MessageKind, NodeId};
+use crate::common::ring::Ring;
+use crate::common::rpc::{Request, RpcError, RpcResult};
+
+const PREPARE_TIMEOUT: Duration = Duration::from_millis(500);
+const RESEND_INTERVAL: Duration = Duration::from_millis(200);
+const IN_DOUBT_INTERVAL: Duration = Duration::from_millis(500);
+/// How long a participant waits for the decision on a transaction it voted for before asking its coordinator.
+const IN_DOUBT_TIMEOUT: Duration = Duration::from_millis(1000);
+/// How long a participant remembers a transaction it aborted, well past the deadline of any prepare still in
+/// flight for it.
+const ABORTED_RETENTION: Duration = Duration::from_millis(5000);
+const MALFORMED_REQUEST: u64 = 12;
+const TXN_CONFLICT: u64 = 30;
+
+/// The data one node holds, which transactions spanning several nodes lock, read and change.
+pub trait Shard: Default + 'static {
+    type Key: Hash + Eq + Clone + Debug;
+    /// The client request of a transaction.
+    type Txn: DeserializeOwned + Debug;
+    /// What one node runs of a transaction.
+    type Part: Serialize + DeserializeOwned + Debug;
+    /// What a node returns for its part when it votes to commit.
+    type Vote: Serialize + DeserializeOwned + Debug;
+    type Output: Serialize;
+
+    /// Splits a transaction into the parts run by the owners of its keys.
+    fn split<F>(txn: &Self::Txn, owner: F) -> BTreeMap<NodeId, Self::Part>
+        where F: Fn(&Self::Key) -> NodeId;
+
+    /// The keys a part locks from its prepare until its transaction commits or aborts.
+    fn keys(part: &Self::Part) -> Vec<Self::Key>;
+
+    /// Runs a part against its locked keys, without applying its writes yet.
+    fn prepare(&self, part: &Self::Part) -> Self::Vote;
+
+    fn commit(&mut self, part: Self::Part);
+
+    /// The reply to the client, from the vote of every participant.
+    fn output(txn: Self::Txn, votes: BTreeMap<NodeId, Self::Vote>) -> Self::Output;
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TxnId {
+    pub coordinator: NodeId,
+    pub seq: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Decision {
+    Commit,
+    Abort,
+}
+
+#[derive(Serialize, Deserialize, MessageKind, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TwoPcMessage {
+    /// Asks a participant to lock the keys of its part and vote. A participant which cannot lock them at once
+    /// votes to abort, with a `txn-conflict` error.
+    Prepare {
+        txn_id: TxnId,
+        part: Value,
+    },
+    PrepareOk {
+        vote: Value,
+    },
+    Commit {
+        txn_id: TxnId,
+    },
+    CommitOk,
+    Abort {
+        txn_id: TxnId,
+    },
+    AbortOk,
+    /// Asks the coordinator of an in-doubt transaction for its decision, `None` while it is still undecided.
+    Status {
+        txn_id: TxnId,
+    },
+    StatusOk {
+        decision: Option<Decision>,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename = "prepare")]
+pub struct Prepare {
+    pub txn_id: TxnId,
+    pub part: Value,
+}
+
+#[derive(Deserialize)]
+pub struct PrepareOk {
+    pub vote: Value,
+}
+
+impl Request for Prepare {
+    type Response = PrepareOk;
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Decide {
+    Commit {
+        txn_id: TxnId,
+    },
+    Abort {
+        txn_id: TxnId,
+    },
+}
+
+impl Request for Decide {
+    type Response = IgnoredAny;
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename = "status")]
+pub struct Status {
+    pub txn_id: TxnId,
+}
+
+#[derive(Deserialize)]
+pub struct StatusOk {
+    pub decision: Option<Decision>,
+}
+
+impl Request for Status {
+    type Response = StatusOk;
+}
+
+#[derive(Debug)]
+pub enum TwoPcTimer {
+    Prepare(TxnId),
+    Resend(TxnId),
+    InDoubt,
+}
+
+/// A transaction this node coordinates.
+struct Coordinated<S>
+    where S: Shard {
+    address: MessageAddress,
+    txn: Option<S::Txn>,
+    participants: BTreeSet<NodeId>,
+    votes: BTreeMap<NodeId, S::Vote>,
+    decision: Option<Decision>,
+    /// The participants which have not acknowledged the decision yet.
+    unacknowledged: BTreeSet<NodeId>,
+}
+
+/// A part this node has voted to commit, holding its locks until the decision arrives.
+struct Prepared<S>
+    where S: Shard {
+    coordinator: NodeId,
+    part: S::Part,
+    since: Instant,
+}
+
+/// Runs transactions over the shards of a `Shard` placed on a consistent hash ring with two-phase commit. Every
+/// node coordinates the transactions clients send it, and is a participant holding the locks of the parts it
+/// prepared. A transaction commits if every participant votes to before `PREPARE_TIMEOUT`, and aborts otherwise.
+///
+/// Decisions are resent until every participant acknowledges them, and participants left in doubt ask the
+/// coordinator. A coordinator forgets a transaction once every participant has acknowledged its decision, and
+/// presumes the transactions it does not know of aborted.
+pub struct TwoPhaseCommit<S>
+    where S: Shard {
+    shard: S,
+    ring: Ring,
+    next_seq: u64,
+    coordinated: HashMap<TxnId, Coordinated<S>>,
+    prepared: HashMap<TxnId, Prepared<S>>,
+    locks: HashMap<S::Key, TxnId>,
+    /// The transactions aborted here and when, so a prepare arriving after the abort is refused.
+    aborted: HashMap<TxnId, Instant>,
+}
+
+impl<S> TwoPhaseCommit<S>
+    where S: Shard {
+    pub fn shard(&self) -> &S {
+        &self.shard
+    }
+
+    /// The transactions this node holds locks for.
+    pub fn prepared(&self) -> usize {
+        self.prepared.len()
+    }
+
+    fn on_txn(&mut self, context: &mut Context<Self>, txn: S::Txn, address: MessageAddress) -> Result<()> {
+        let txn_id = TxnId { coordinator: context.this_node().node_id.clone(), seq: self.next_seq };
+        self.next_seq += 1;
+        let parts = S::split(&txn, |key| self.ring.owner(key).cloned().unwrap_or_else(|| context.this_node().node_id.clone()));
+        debug!("Coordinating '{:?}' over '{:?}'", txn_id, parts.keys());
+        self.coordinated.insert(txn_id.clone(), Coordinated {
+            address,
+            txn: Some(txn),
+            participants: parts.keys().cloned().collect(),
+            votes: BTreeMap::new(),
+            decision: None,
+            unacknowledged: BTreeSet::new(),
+        });
+        if parts.is_empty() {
+            return self.decide(context, &txn_id, Decision::Commit);
+        }
+        context.set_timer(PREPARE_TIMEOUT, TwoPcTimer::Prepare(txn_id.clone()));
+        for (node_id, part) in parts {
+            let txn_id = txn_id.clone();
+            let request = Prepare { txn_id: txn_id.clone(), part: serde_json::to_value(part)? };
+            self.send(context, node_id, request, move |actor, context, node_id, response| actor.on_prepared(context, txn_id, node_id, response))?;
+        }
+        Ok(())
+    }
+
+    /// Sends a request to a node, answering it right away if it is this one.
+    fn send<R, F>(&mut self, context: &mut Context<Self>, node_id: NodeId, request: R, callback: F) -> Result<()>
+        where R: Request,
+              F: FnOnce(&mut Self, &mut Context<Self>, NodeId, RpcResult<R::Response>) -> Result<()> + 'static {
+        if node_id == context.this_node().node_id {
+            let request = serde_json::from_value(serde_json::to_value(request)?)?;
+            let response = match self.on_request_message(context, request, &node_id)? {
+                Ok(response) => Ok(serde_json::from_value(serde_json::to_value(response)?)?),
+                Err(error) => Err(error),
+            };
+            return callback(self, context, node_id, response);
+        }
+        context.rpc(node_id.clone(), request, move |actor: &mut TwoPhaseCommit<S>, context, response| {
+            callback(actor, context, node_id, response)
+        })
+    }
+
+    fn on_request_message(&mut self, context: &mut Context<Self>, request: TwoPcMessage, src: &NodeId) -> Result<RpcResult<TwoPcMessage>> {
+        match request {
+            TwoPcMessage::Prepare { txn_id, part } => self.on_prepare(context, txn_id, serde_json::from_value(part)?, src),
+            TwoPcMessage::Commit { txn_id } => {
+                self.apply(&txn_id, Decision::Commit, context.now());
+                Ok(Ok(TwoPcMessage::CommitOk))
+            }
+            TwoPcMessage::Abort { txn_id } => {
+                self.apply(&txn_id, Decision::Abort, context.now());
+                Ok(Ok(TwoPcMessage::AbortOk))
+            }
+            TwoPcMessage::Status { txn_id } => Ok(Ok(TwoPcMessage::StatusOk { decision: self.status(&txn_id) })),
+            request => Err(UnexpectedMessage(request.type_name().to_string())),
+        }
+    }
+
+    fn on_prepare(&mut self, context: &mut Context<Self>, txn_id: TxnId, part: S::Part, coordinator: &NodeId) -> Result<RpcResult<TwoPcMessage>> {
+        let conflict = |text: &str| Ok(Err(RpcError { code: TXN_CONFLICT, text: Some(text.to_string()) }));
+        if self.aborted.contains_key(&txn_id) {
+            return conflict("Already aborted");
+        }
+        let keys = S::keys(&part);
+        if keys.iter().any(|key| self.locks.get(key).is_some_and(|holder| *holder != txn_id)) {
+            context.metrics().increment("lock_conflicts");
+            return conflict("Keys locked by another transaction");
+        }
+        for key in keys {
+            self.locks.insert(key, txn_id.clone());
+        }
+        let vote = self.shard.prepare(&part);
+        self.prepared.insert(txn_id, Prepared { coordinator: coordinator.clone(), part, since: context.now() });
+        Ok(Ok(TwoPcMessage::PrepareOk { vote: serde_json::to_value(vote)? }))
+    }
+
+    /// Applies a decision to the part prepared here, if any, and releases its locks.
+    fn apply(&mut self, txn_id: &TxnId, decision: Decision, now: Instant) {
+        if decision == Decision::Abort {
+            self.aborted.insert(txn_id.clone(), now);
+        }
+        let Some(prepared) = self.prepared.remove(txn_id) else { return };
+        self.locks.retain(|_, holder| holder != txn_id);
+        if decision == Decision::Commit {
+            self.shard.commit(prepared.part);
+        }
+    }
+
+    /// The decision on a transaction coordinated here. Unknown transactions are presumed aborted: a participant
+    /// still in doubt has not acknowledged the decision, so the coordinator has not forgotten a decided one.
+    fn status(&self, txn_id: &TxnId) -> Option<Decision> {
+        self.coordinated.get(txn_id).map_or(Some(Decision::Abort), |coordinated| coordinated.decision)
+    }
+
+    fn on_prepared(&mut self, context: &mut Context<Self>, txn_id: TxnId, node_id: NodeId, response: RpcResult<PrepareOk>) -> Result<()> {
+        let Some(coordinated) = self.coordinated.get_mut(&txn_id) else { return Ok(()) };
+        if coordinated.decision.is_some() {
+            return Ok(());
+        }
+        match response {
+            Ok(PrepareOk { vote }) => {
+                coordinated.votes.insert(node_id, serde_json::from_value(vote)?);
+                if coordinated.votes.len() == coordinated.participants.len() {
+                    self.decide(context, &txn_id, Decision::Commit)?;
+                }
+                Ok(())
+            }
+            Err(_) => self.decide(context, &txn_id, Decision::Abort),
+        }
+    }
+
+    fn on_acknowledged(&mut self, txn_id: TxnId, node_id: NodeId) {
+        let Some(coordinated) = self.coordinated.get_mut(&txn_id) else { return };
+        coordinated.unacknowledged.remove(&node_id);
+        if coordinated.decision.is_some() && coordinated.unacknowledged.is_empty() {
+            self.coordinated.remove(&txn_id);
+        }
+    }
+
+    /// Records the decision, replies to the client and sends the decision to every participant.
+    fn decide(&mut self, context: &mut Context<Self>, txn_id: &TxnId, decision: Decision) -> Result<()> {
+        let Some(coordinated) = self.coordinated.get_mut(txn_id) else { return Ok(()) };
+        debug!("Deciding '{:?}' for '{:?}'", decision, txn_id);
+        context.metrics().increment(match decision {
+            Decision::Commit => "commits",
+            Decision::Abort => "aborts",
+        });
+        coordinated.decision = Some(decision);
+        coordinated.unacknowledged = coordinated.participants.clone();
+        let txn = coordinated.txn.take();
+        let body = match (decision, txn) {
+            (Decision::Commit, Some(txn)) => serde_json::to_value(S::output(txn, std::mem::take(&mut coordinated.votes)))?,
+            _ => json!({"type": "error", "code": TXN_CONFLICT, "text": "Transaction aborted"}),
+        };
+        context.reply(coordinated.address.clone(), body);
+        if coordinated.unacknowledged.is_empty() {
+            self.coordinated.remove(txn_id);
+            return Ok(());
+        }
+        self.send_decision(context, txn_id)?;
+        context.set_timer(RESEND_INTERVAL, TwoPcTimer::Resend(txn_id.clone()));
+        Ok(())
+    }
+
+    fn send_decision(&mut self, context: &mut Context<Self>, txn_id: &TxnId) -> Result<()> {
+        let Some(coordinated) = self.coordinated.get(txn_id) else { return Ok(()) };
+        let Some(decision) = coordinated.decision else { return Ok(()) };
+        for node_id in coordinated.unacknowledged.clone() {
+            let request = match decision {
+                Decision::Commit => Decide::Commit { txn_id: txn_id.clone() },
+                Decision::Abort => Decide::Abort { txn_id: txn_id.clone() },
+            };
+            let txn_id = txn_id.clone();
+            self.send(context, node_id, request, move |actor, _, node_id, response| {
+                if response.is_ok() {
+                    actor.on_acknowledged(txn_id, node_id);
+                }
+                Ok(())
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Asks the coordinators of the parts prepared for too long for their decision.
+    fn resolve_in_doubt(&mut self, context: &mut Context<Self>) -> Result<()> {
+        let now = context.now();
+        let in_doubt: Vec<(TxnId, NodeId)> = self.prepared.iter()
+            .filter(|(_, prepared)| now.duration_since(prepared.since) >= IN_DOUBT_TIMEOUT)
+            .map(|(txn_id, prepared)| (txn_id.clone(), prepared.coordinator.clone()))
+            .collect();
+        for (txn_id, coordinator) in in_doubt {
+            self.send(context, coordinator, Status { txn_id: txn_id.clone() }, move |actor, context, _, response| {
+                if let Ok(StatusOk { decision: Some(decision) }) = response {
+                    debug!("Resolved in-doubt '{:?}' as '{:?}'", txn_id, decision);
+                    actor.apply(&txn_id, decision, context.now());
+                }
+                Ok(())
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl<S> Actor for TwoPhaseCommit<S>
+    where S: Shard {
+    type Msg = Value;
+    type TimerKey = TwoPcTimer;
+
+    fn new(context: &mut Context<Self>) -> Result<Self> {
+        context.set_timer(IN_DOUBT_INTERVAL, TwoPcTimer::InDoubt);
+        Ok(TwoPhaseCommit {
+            shard: S::default(),
+            ring: Ring::new(&context.this_node().node_ids),
+            next_seq: 0,
+            coordinated: HashMap::new(),
+            prepared: HashMap::new(),
+            locks: HashMap::new(),
+            aborted: HashMap::new(),
+        })
+    }
+
+    fn on_request(&mut self, context: &mut Context<Self>, request: Message<Self::Msg>) -> Result<()> {
+        let (body, address) = request.body_and_address();
+        if !TwoPcMessage::type_names().contains(&body.type_name()) {
+            return match serde_json::from_value(body) {
+                Ok(txn) => self.on_txn(context, txn, address),
+                Err(error) => {
+                    context.reply(address, json!({"type": "error", "code": MALFORMED_REQUEST, "text": error.to_string()}));
+                    Ok(())
+                }
+            };
+        }
+        let response = self.on_request_message(context, serde_json::from_value(body)?, &address.src)?;
+        let body = match response {
+            Ok(response) => serde_json::to_value(response)?,
+            Err(error) => json!({"type": "error", "code": error.code, "text": error.text}),
+        };
+        context.reply(address, body);
+        Ok(())
+    }
+
+    fn on_timeout(&mut self, context: &mut Context<Self>, timer_key: Self::TimerKey) -> Result<()> {
+        match timer_key {
+            TwoPcTimer::Prepare(txn_id) => {
+                let undecided = self.coordinated.get(&txn_id).is_some_and(|coordinated| coordinated.decision.is_none());
+                if undecided {
+                    self.decide(context, &txn_id, Decision::Abort)?;
+                }
+            }
+            TwoPcTimer::Resend(txn_id) => {
+                if self.coordinated.contains_key(&txn_id) {
+                    self.send_decision(context, &txn_id)?;
+                    context.set_timer(RESEND_INTERVAL, TwoPcTimer::Resend(txn_id));
+                }
+            }
+            TwoPcTimer::InDoubt => {
+                let now = context.now();
+                self.aborted.retain(|_, since| now.duration_since(*since) < ABORTED_RETENTION);
+                self.resolve_in_doubt(context)?;
+                context.set_timer(IN_DOUBT_INTERVAL, TwoPcTimer::InDoubt);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+    use std::time::Duration;
+
+    use serde::Deserialize;
+    use serde_json::{json, Value};
+
+    use crate::common::check::txn::{check_rw_register, ConsistencyModel};
+    use crate::common::driver::{ClientDriver, DriverConfig, Transport};
+    use crate::common::error::Result;
+    use crate::common::message::message::{Message, MessageAddress};
+    use crate::common::message::{MessageId, NodeId};
+    use crate::common::simulator::{Simulator, SimulatorConfig};
+    use crate::common::twopc::{Shard, TwoPhaseCommit};
+    use crate::common::workload::txn::{TxnKind, TxnWorkload};
+
+    /// A client transaction, whose micro-ops are `[f, key, value]` arrays.
+    #[derive(Deserialize, Debug)]
+    struct Txn {
+        txn: Vec<Value>,
+    }
+
+    /// Registers keyed by the key of each micro-op, whose parts keep the position of their micro-ops in the txn.
+    #[derive(Default)]
+    struct Registers {
+        values: HashMap<u64, Value>,
+    }
+
+    impl Shard for Registers {
+        type Key = u64;
+        type Txn = Txn;
+        type Part = Vec<(usize, Value)>;
+        type Vote = Vec<(usize, Value)>;
+        type Output = Value;
+
+        fn split<F>(txn: &Txn, owner: F) -> BTreeMap<NodeId, Self::Part>
+            where F: Fn(&u64) -> NodeId {
+            let mut parts: BTreeMap<NodeId, Self::Part> = BTreeMap::new();
+            for (idx, micro_op) in txn.txn.iter().enumerate() {
+                let key = micro_op[1].as_u64().unwrap_or_default();
+                parts.entry(owner(&key)).or_default().push((idx, micro_op.clone()));
+            }
+            parts
+        }
+
+        fn keys(part: &Self::Part) -> Vec<u64> {
+            part.iter().map(|(_, micro_op)| micro_op[1].as_u64().unwrap_or_default()).collect()
+        }
+
+        fn prepare(&self, part: &Self::Part) -> Self::Vote {
+            let mut written: HashMap<u64, Value> = HashMap::new();
+            part.iter().map(|(idx, micro_op)| {
+                let key = micro_op[1].as_u64().unwrap_or_default();
+                if micro_op[0] == "w" {
+                    written.insert(key, micro_op[2].clone());
+                    return (*idx, micro_op.clone());
+                }
+                let value = written.get(&key).or(self.values.get(&key)).cloned().unwrap_or(Value::Null);
+                (*idx, json!(["r", key, value]))
+            }).collect()
+        }
+
+        fn commit(&mut self, part: Self::Part) {
+            for (_, micro_op) in part.into_iter().filter(|(_, micro_op)| micro_op[0] == "w") {
+                self.values.insert(micro_op[1].as_u64().unwrap_or_default(), micro_op[2].clone());
+            }
+        }
+
+        fn output(_: Txn, votes: BTreeMap<NodeId, Self::Vote>) -> Value {
+            let mut micro_ops: Vec<(usize, Value)> = votes.into_values().flatten().collect();
+            micro_ops.sort_by_key(|(idx, _)| *idx);
+            json!({"type": "txn_ok", "txn": micro_ops.into_iter().map(|(_, micro_op)| micro_op).collect::<Vec<_>>()})
+        }
+    }
+
+    #[test]
+    fn should_commit_serializable_transactions_across_shards() -> Result<()> {
+        let mut simulator = Simulator::<TwoPhaseCommit<Registers>>::new(SimulatorConfig {
+            node_count: 3,
+            latency: Duration::from_millis(5),
+            jitter: Duration::from_millis(10),
+            ..SimulatorConfig::default()
+        })?;
+        let history = ClientDriver::new(DriverConfig {
+            concurrency: 5,
+            rate: 100.0,
+            time_limit: Duration::from_secs(3),
+            ..DriverConfig::default()
+        }, TxnWorkload::new(TxnKind::RwRegister)).run(&mut simulator)?;
+        simulator.run_until(Duration::from_secs(10))?;
+
+        let report = check_rw_register(&history)?;
+
+        assert!(report.satisfies(ConsistencyModel::Serializable), "Anomalies: '{:?}'", report.anomalies);
+        assert!(history.operations().iter().any(|operation| operation.is_ok()));
+        for node_id in simulator.node_ids() {
+            let node = simulator.node(&node_id).unwrap();
+            assert_eq!(node.prepared(), 0);
+            assert!(node.coordinated.is_empty());
+            assert!(node.aborted.is_empty());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_refuse_malformed_transactions() -> Result<()> {
+        let mut simulator = Simulator::<TwoPhaseCommit<Registers>>::new(SimulatorConfig {
+            node_count: 1,
+            latency: Duration::from_millis(5),
+            ..SimulatorConfig::default()
+        })?;
+        let n0 = NodeId::from("n0");
+        let request = |msg_id, body| Message::new_request(MessageAddress {
+            src: NodeId::from("c1"),
+            dest: n0.clone(),
+            msg_id: Some(MessageId(msg_id)),
+            in_reply_to: None,
+        }, body);
+
+        simulator.send(request(1, json!({"type": "txn", "txn": 3})))?;
+        let malformed = simulator.receive(Duration::from_secs(1))?.unwrap();
+        simulator.send(request(2, json!({"type": "txn", "txn": [["w", 1, 1], ["r", 1, null]]})))?;
+        let committed = simulator.receive(Duration::from_secs(2))?.unwrap();
+
+        assert_eq!(malformed.body()["code"], json!(12));
+        assert_eq!(committed.body()["txn"], json!([["w", 1, 1], ["r", 1, 1]]));
+        Ok(())
+    }
+}
@@ -0,0 +1,76 @@
+use std::collections::BTreeMap;
+
+use rand::Rng;
+use rand::rngs::StdRng;
+use serde_json::{json, Value};
+
+use crate::common::message::NodeId;
+use crate::common::topology::Topology;
+use crate::common::workload::Workload;
+
+pub struct BroadcastWorkload {
+    next_message: i64,
+}
+
+impl BroadcastWorkload {
+    pub fn new() -> BroadcastWorkload {
+        BroadcastWorkload {
+            next_message: 0
+        }
+    }
+}
+
+impl Default for BroadcastWorkload {
+    fn default() -> Self {
+        BroadcastWorkload::new()
+    }
+}
+
+impl Workload for BroadcastWorkload {
+    fn setup(&mut self, node_ids: &[NodeId]) -> Vec<(NodeId, Value)> {
+        let topology = grid_topology(node_ids);
+        node_ids.iter().map(|node_id| (node_id.clone(), json!({"type": "topology", "topology": topology}))).collect()
+    }
+
+    fn generate(&mut self, rng: &mut StdRng) -> Value {
+        if rng.gen_bool(0.5) {
+            let message = self.next_message;
+            self.next_message += 1;
+            json!({"type": "broadcast", "message": message})
+        } else {
+            json!({"type": "read"})
+        }
+    }
+
+    fn finish(&mut self, node_ids: &[NodeId]) -> Vec<(NodeId, Value)> {
+        node_ids.iter().map(|node_id| (node_id.clone(), json!({"type": "read"}))).collect()
+    }
+}
+
+/// The default Maelstrom topology, see `Topology::grid`.
+fn grid_topology(node_ids: &[NodeId]) -> BTreeMap<String, Vec<String>> {
+    Topology::grid(node_ids)
+        .adjacency()
+        .iter()
+        .map(|(node_id, neighbors)| (node_id.to_string(), neighbors.iter().map(NodeId::to_string).collect()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::message::NodeId;
+    use crate::common::workload::broadcast::grid_topology;
+
+    #[test]
+    fn should_build_grid_topology() {
+        let node_ids: Vec<NodeId> = ["n0", "n1", "n2", "n3", "n4"].into_iter().map(NodeId::from).collect();
+
+        let topology = grid_topology(&node_ids);
+
+        assert_eq!(topology["n0"], vec!["n1", "n3"]);
+        assert_eq!(topology["n1"], vec!["n0", "n2", "n4"]);
+        assert_eq!(topology["n2"], vec!["n1"]);
+        assert_eq!(topology["n3"], vec!["n4", "n0"]);
+        assert_eq!(topology["n4"], vec!["n3", "n1"]);
+    }
+}
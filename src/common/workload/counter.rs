@@ -0,0 +1,24 @@
+use rand::Rng;
+use rand::rngs::StdRng;
+use serde_json::{json, Value};
+
+use crate::common::message::NodeId;
+use crate::common::workload::Workload;
+
+const MAX_DELTA: u64 = 5;
+
+pub struct CounterWorkload;
+
+impl Workload for CounterWorkload {
+    fn generate(&mut self, rng: &mut StdRng) -> Value {
+        if rng.gen_bool(0.5) {
+            json!({"type": "add", "delta": rng.gen_range(0..=MAX_DELTA)})
+        } else {
+            json!({"type": "read"})
+        }
+    }
+
+    fn finish(&mut self, node_ids: &[NodeId]) -> Vec<(NodeId, Value)> {
+        node_ids.iter().map(|node_id| (node_id.clone(), json!({"type": "read"}))).collect()
+    }
+}
@@ -0,0 +1,29 @@
+use rand::rngs::StdRng;
+use serde_json::{json, Value};
+
+use crate::common::workload::Workload;
+
+pub struct EchoWorkload {
+    counter: u64,
+}
+
+impl EchoWorkload {
+    pub fn new() -> EchoWorkload {
+        EchoWorkload {
+            counter: 0
+        }
+    }
+}
+
+impl Default for EchoWorkload {
+    fn default() -> Self {
+        EchoWorkload::new()
+    }
+}
+
+impl Workload for EchoWorkload {
+    fn generate(&mut self, _rng: &mut StdRng) -> Value {
+        self.counter += 1;
+        json!({"type": "echo", "echo": format!("Please echo {}", self.counter)})
+    }
+}
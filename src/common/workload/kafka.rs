@@ -0,0 +1,80 @@
+use std::collections::BTreeMap;
+
+use rand::Rng;
+use rand::rngs::StdRng;
+use serde_json::{json, Value};
+
+use crate::common::workload::Workload;
+
+const KEY_COUNT: usize = 5;
+
+pub struct KafkaWorkload {
+    next_message: i64,
+    latest_offsets: BTreeMap<String, u64>,
+    consumed_offsets: BTreeMap<String, u64>,
+}
+
+impl KafkaWorkload {
+    pub fn new() -> KafkaWorkload {
+        KafkaWorkload {
+            next_message: 0,
+            latest_offsets: BTreeMap::new(),
+            consumed_offsets: BTreeMap::new(),
+        }
+    }
+}
+
+impl Default for KafkaWorkload {
+    fn default() -> Self {
+        KafkaWorkload::new()
+    }
+}
+
+impl Workload for KafkaWorkload {
+    fn generate(&mut self, rng: &mut StdRng) -> Value {
+        match rng.gen_range(0..10) {
+            0..=4 => {
+                let key = format!("{}", rng.gen_range(0..KEY_COUNT));
+                let msg = self.next_message;
+                self.next_message += 1;
+                json!({"type": "send", "key": key, "msg": msg})
+            }
+            5..=7 => {
+                let offsets: BTreeMap<&String, u64> = self.latest_offsets
+                    .keys()
+                    .map(|key| (key, self.consumed_offsets.get(key).map_or(0, |offset| offset + 1)))
+                    .collect();
+                json!({"type": "poll", "offsets": offsets})
+            }
+            8 => json!({"type": "commit_offsets", "offsets": self.consumed_offsets}),
+            _ => {
+                let keys: Vec<&String> = self.latest_offsets.keys().collect();
+                json!({"type": "list_committed_offsets", "keys": keys})
+            }
+        }
+    }
+
+    fn observe(&mut self, request: &Value, response: &Value) {
+        match response.get("type").and_then(Value::as_str) {
+            Some("send_ok") => {
+                if let (Some(key), Some(offset)) = (request["key"].as_str(), response["offset"].as_u64()) {
+                    let latest = self.latest_offsets.entry(key.to_string()).or_default();
+                    *latest = (*latest).max(offset);
+                }
+            }
+            Some("poll_ok") => {
+                if let Some(msgs) = response["msgs"].as_object() {
+                    for (key, pairs) in msgs {
+                        let last_offset = pairs.as_array()
+                            .and_then(|pairs| pairs.iter().filter_map(|pair| pair[0].as_u64()).max());
+                        if let Some(last_offset) = last_offset {
+                            let consumed = self.consumed_offsets.entry(key.clone()).or_default();
+                            *consumed = (*consumed).max(last_offset);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,21 @@
+use rand::Rng;
+use rand::rngs::StdRng;
+use serde_json::{json, Value};
+
+use crate::common::workload::Workload;
+
+const KEY_COUNT: u64 = 5;
+const VALUE_COUNT: u64 = 5;
+
+pub struct LinKvWorkload;
+
+impl Workload for LinKvWorkload {
+    fn generate(&mut self, rng: &mut StdRng) -> Value {
+        let key = rng.gen_range(0..KEY_COUNT);
+        match rng.gen_range(0..3) {
+            0 => json!({"type": "read", "key": key}),
+            1 => json!({"type": "write", "key": key, "value": rng.gen_range(0..VALUE_COUNT)}),
+            _ => json!({"type": "cas", "key": key, "from": rng.gen_range(0..VALUE_COUNT), "to": rng.gen_range(0..VALUE_COUNT)}),
+        }
+    }
+}
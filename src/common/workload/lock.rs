@@ -0,0 +1,42 @@
+use std::collections::BTreeMap;
+
+use rand::Rng;
+use rand::rngs::StdRng;
+use serde_json::{json, Value};
+
+use crate::common::workload::Workload;
+
+const LOCK_COUNT: u64 = 3;
+const OWNER_COUNT: u64 = 5;
+const TTL_MILLIS: u64 = 1000;
+
+/// Acquires locks, and renews or releases them with the last fencing token handed out for each.
+#[derive(Default)]
+pub struct LockWorkload {
+    tokens: BTreeMap<String, u64>,
+}
+
+impl Workload for LockWorkload {
+    fn generate(&mut self, rng: &mut StdRng) -> Value {
+        let lock = format!("lock-{}", rng.gen_range(0..LOCK_COUNT));
+        match (rng.gen_range(0..3), self.tokens.get(&lock)) {
+            (1, Some(token)) => json!({"type": "renew", "lock": lock, "token": token, "ttl": TTL_MILLIS}),
+            (2, Some(token)) => json!({"type": "release", "lock": lock, "token": token}),
+            _ => json!({"type": "acquire", "lock": lock, "owner": format!("owner-{}", rng.gen_range(0..OWNER_COUNT)), "ttl": TTL_MILLIS}),
+        }
+    }
+
+    fn observe(&mut self, request: &Value, response: &Value) {
+        let Some(lock) = request["lock"].as_str() else { return };
+        match (response["type"].as_str(), response["token"].as_u64()) {
+            (Some("acquire_ok"), Some(token)) => {
+                let latest = self.tokens.entry(lock.to_string()).or_default();
+                *latest = (*latest).max(token);
+            }
+            (Some("release_ok"), _) if self.tokens.get(lock) == request["token"].as_u64().as_ref() => {
+                self.tokens.remove(lock);
+            }
+            _ => {}
+        }
+    }
+}
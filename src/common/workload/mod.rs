@@ -0,0 +1,91 @@
+pub mod echo;
+pub mod unique_ids;
+pub mod broadcast;
+pub mod counter;
+pub mod kafka;
+pub mod txn;
+pub mod lin_kv;
+pub mod lock;
+
+use rand::rngs::StdRng;
+use serde_json::{Map, Value};
+
+use crate::common::error::{Error, Result};
+use crate::common::message::NodeId;
+use crate::common::workload::broadcast::BroadcastWorkload;
+use crate::common::workload::counter::CounterWorkload;
+use crate::common::workload::echo::EchoWorkload;
+use crate::common::workload::kafka::KafkaWorkload;
+use crate::common::workload::lin_kv::LinKvWorkload;
+use crate::common::workload::lock::LockWorkload;
+use crate::common::workload::txn::{TxnKind, TxnWorkload};
+use crate::common::workload::unique_ids::UniqueIdsWorkload;
+
+pub trait Workload {
+    /// Requests sent once to the given nodes before the generator starts, e.g. `topology`.
+    fn setup(&mut self, _node_ids: &[NodeId]) -> Vec<(NodeId, Value)> {
+        vec![]
+    }
+
+    fn generate(&mut self, rng: &mut StdRng) -> Value;
+
+    fn observe(&mut self, _request: &Value, _response: &Value) {}
+
+    /// Requests sent once to the given nodes after the generator stops, e.g. final reads.
+    fn finish(&mut self, _node_ids: &[NodeId]) -> Vec<(NodeId, Value)> {
+        vec![]
+    }
+}
+
+pub fn workload(name: &str) -> Result<Box<dyn Workload>> {
+    match name {
+        "echo" => Ok(Box::new(EchoWorkload::new())),
+        "unique-ids" => Ok(Box::new(UniqueIdsWorkload)),
+        "broadcast" => Ok(Box::new(BroadcastWorkload::new())),
+        "g-counter" => Ok(Box::new(CounterWorkload)),
+        "kafka" => Ok(Box::new(KafkaWorkload::new())),
+        "txn-rw-register" => Ok(Box::new(TxnWorkload::new(TxnKind::RwRegister))),
+        "txn-list-append" => Ok(Box::new(TxnWorkload::new(TxnKind::ListAppend))),
+        "lin-kv" => Ok(Box::new(LinKvWorkload)),
+        "lock" => Ok(Box::new(LockWorkload::default())),
+        _ => Err(Error::UnexpectedError(format!("Unknown workload: '{}'", name)))
+    }
+}
+
+impl<W> Workload for Box<W>
+    where W: Workload + ?Sized {
+    fn setup(&mut self, node_ids: &[NodeId]) -> Vec<(NodeId, Value)> {
+        (**self).setup(node_ids)
+    }
+
+    fn generate(&mut self, rng: &mut StdRng) -> Value {
+        (**self).generate(rng)
+    }
+
+    fn observe(&mut self, request: &Value, response: &Value) {
+        (**self).observe(request, response)
+    }
+
+    fn finish(&mut self, node_ids: &[NodeId]) -> Vec<(NodeId, Value)> {
+        (**self).finish(node_ids)
+    }
+}
+
+pub fn request_type(body: &Value) -> &str {
+    body.get("type").and_then(Value::as_str).unwrap_or_default()
+}
+
+/// Strips the envelope fields from a message body, leaving the operation's payload.
+pub fn payload(body: &Value) -> Value {
+    match body {
+        Value::Object(fields) => {
+            let fields: Map<String, Value> = fields
+                .iter()
+                .filter(|(name, _)| !matches!(name.as_str(), "type" | "msg_id" | "in_reply_to"))
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect();
+            Value::Object(fields)
+        }
+        other => other.clone()
+    }
+}
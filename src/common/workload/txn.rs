@@ -0,0 +1,50 @@
+use rand::Rng;
+use rand::rngs::StdRng;
+use serde_json::{json, Value};
+
+use crate::common::workload::Workload;
+
+const KEY_COUNT: u64 = 10;
+const MAX_TXN_LENGTH: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TxnKind {
+    RwRegister,
+    ListAppend,
+}
+
+pub struct TxnWorkload {
+    kind: TxnKind,
+    next_value: u64,
+}
+
+impl TxnWorkload {
+    pub fn new(kind: TxnKind) -> TxnWorkload {
+        TxnWorkload {
+            kind,
+            next_value: 1,
+        }
+    }
+}
+
+impl Workload for TxnWorkload {
+    fn generate(&mut self, rng: &mut StdRng) -> Value {
+        let length = rng.gen_range(1..=MAX_TXN_LENGTH);
+        let txn: Vec<Value> = (0..length)
+            .map(|_| {
+                let key = rng.gen_range(0..KEY_COUNT);
+                if rng.gen_bool(0.5) {
+                    json!(["r", key, null])
+                } else {
+                    let value = self.next_value;
+                    self.next_value += 1;
+                    match self.kind {
+                        TxnKind::RwRegister => json!(["w", key, value]),
+                        TxnKind::ListAppend => json!(["append", key, value]),
+                    }
+                }
+            })
+            .collect();
+        json!({"type": "txn", "txn": txn})
+    }
+}
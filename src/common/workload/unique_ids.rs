@@ -0,0 +1,12 @@
+use rand::rngs::StdRng;
+use serde_json::{json, Value};
+
+use crate::common::workload::Workload;
+
+pub struct UniqueIdsWorkload;
+
+impl Workload for UniqueIdsWorkload {
+    fn generate(&mut self, _rng: &mut StdRng) -> Value {
+        json!({"type": "generate"})
+    }
+}
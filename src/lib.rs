@@ -1 +1,3 @@
+extern crate self as gossip_glomers;
+
 pub mod common;